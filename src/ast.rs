@@ -1,3 +1,5 @@
+use crate::diagnostic::Span;
+
 
 // ------------------------------ Function ------------------------------------------
 
@@ -8,21 +10,26 @@ pub struct CompUnit {
     pub funcs: Vec<DeclFuncPair>,
 }
 
+// `Error` marks the parts dropped by the parser's error recovery, they never reach the generator.
 #[derive(Debug)]
 pub enum DeclFuncPair {
     Decl(Decl),
     Func(FuncDef),
+    Struct(StructDef),
+    Error(),
 }
 
 
 // [...]代表里面出现0或1次.
-// FuncDef   ::= FuncType IDENT "(" [FuncFParams] ")" Block;
+// FuncDef   ::= FuncType IDENT "(" [FuncFParams] ")" (Block | ";");
+// no `block` is a prototype, `int f(int a[], int n);`.
 #[derive(Debug)]
 pub struct FuncDef {
-    pub func_type: i32,
+    pub func_type: FuncType,
     pub ident: String,
-    pub block: Block,
+    pub block: Option<Block>,
     pub params: Option<FuncFParams>,
+    pub span: Span, // the function name.
 }
 
 // FuncFParams ::= FuncFParam {"," FuncFParam};
@@ -31,19 +38,31 @@ pub struct FuncFParams {
     pub params: Vec<FuncFParam>,
 }
 
-// FuncFParam ::= BType IDENT ["[" "]" {"[" ConstExp "]"}]
+// FuncFParam ::= BType IDENT ["[" "]" {"[" ConstExp "]"}] | BType "*" IDENT
+// `int *p` is the pointer extension, the same type as `int p[]`.
 #[derive(Debug)]
 pub enum FuncFParam {
-    // pub btype: BType,
-    Integer(String),
-    Array(String, Vec<ConstExp>),
+    Scalar(BType, String, Span),
+    Array(BType, String, Vec<ConstExp>, Span),
+    Pointer(BType, String, Span),
+    Error(),
 }
 
-// FuncType  ::= "void" | "int";
-#[derive(Debug)]
+// FuncType  ::= "void" | "int" | "float";
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FuncType {
     Int,
     Void,
+    Float,
+}
+
+impl From<BType> for FuncType {
+    fn from(btype: BType) -> FuncType {
+        match btype {
+            BType::Int => FuncType::Int,
+            BType::Float => FuncType::Float,
+        }
+    }
 }
 
 
@@ -60,6 +79,8 @@ pub struct Block {
 
 // BlockItem     ::= Decl | Stmt;
 #[derive(Debug)]
+// the ast is built once and walked, an `Exp` is kept inline rather than boxed.
+#[allow(clippy::large_enum_variant)]
 pub enum BlockItem {
     Decl(Decl),
     Statement(Statement),
@@ -69,41 +90,91 @@ pub enum BlockItem {
 // statement: open_statement
 //          | closed_statement
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Statement {
     Open(OpenStatement),
     Closed(ClosedStatement),
 }
 // open_statement: IF '(' expression ')' statement
 //               | IF '(' expression ')' closed_statement ELSE open_statement
+//               | "while" "(" Exp ")" open_statement
+//               | ForHead open_statement
+// the body of a loop is kept as a `Statement`, open or closed depends on the variant.
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum OpenStatement {
     If(Exp, Box<Statement>),
     Ifelse(Exp, ClosedStatement, Box<OpenStatement>),
     While(Exp, Box<Statement>),
+    For(ForStmt),
 }
 // closed_statement: non_if_statement
 //                 | IF '(' expression ')' closed_statement ELSE closed_statement
+//                 | "while" "(" Exp ")" closed_statement
+//                 | ForHead closed_statement
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum ClosedStatement {
     Stmt(Stmt),
     Ifelse(Exp, Box<ClosedStatement>, Box<ClosedStatement>),
+    While(Exp, Box<Statement>),
+    For(ForStmt),
+}
+
+// ForHead ::= "for" "(" ForInit [Exp] ";" [SimpleStmt] ")";
+// `step` is a `Stmt::LvalExp` or a `Stmt::SingleExp`.
+#[derive(Debug)]
+pub struct ForStmt {
+    pub init: ForInit,
+    pub cond: Option<Exp>,
+    pub step: Option<Stmt>,
+    pub body: Box<Statement>,
+}
+
+// ForInit ::= Decl | [SimpleStmt] ";";
+// an empty one is `Stmt::ZeroExp()`.
+#[derive(Debug)]
+pub enum ForInit {
+    Decl(Decl),
+    Stmt(Box<Stmt>),
 }
 
 
 // Stmt ::= LVal "=" Exp ";"
+//        | LVal AssignOp Exp ";"
+//        | LVal ("++" | "--") ";" | ("++" | "--") LVal ";"
 //        | [Exp] ";"
 //        | Block
 //        | "return" [Exp] ";";
+//        | "do" Statement "while" "(" Exp ")" ";"
+//        | "switch" "(" Exp ")" "{" {SwitchItem} "}"
+// the `Span` of the keyword statements covers the whole statement.
+// the `String` of `CompoundAssign` and `Increment` is the operator of `binary_operation`, `+=` and `++` are "add".
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Stmt {
-    RetExp(Exp),
-    RetNone(),
+    RetExp(Exp, Span),
+    RetNone(Span),
     LvalExp(LVal, Exp),
+    CompoundAssign(LVal, String, Exp),
+    Increment(LVal, String),
     SingleExp(Exp),
     Block(Block),
     ZeroExp(),
-    BreakKeyWord(),
-    ContinueKeyWord(),
+    BreakKeyWord(Span),
+    ContinueKeyWord(Span),
+    DoWhile(Box<Statement>, Exp),
+    Switch(Exp, Vec<SwitchItem>),
+    Error(),
+}
+
+// SwitchItem ::= "case" ConstExp ":" | "default" ":" | BlockItem;
+// the labels are only allowed directly in the body of the `switch`.
+#[derive(Debug)]
+pub enum SwitchItem {
+    Case(Box<ConstExp>, Span),
+    Default(Span),
+    Item(Box<BlockItem>),
 }
 
 
@@ -114,42 +185,79 @@ pub enum Stmt {
 
 // ------------------------------ Variable ------------------------------------------
 
-// LVal ::= IDENT {"[" Exp "]"}
+// LVal ::= IDENT {"[" Exp "]"} | "*" UnaryExp | LVal "." IDENT {"[" Exp "]"}
+// `*p` is the pointer extension, `p` may be any expression of a pointer type.
+// `s.a[i]` is the struct extension, the field `a` of the struct `s`.
 #[derive(Debug)]
-pub struct LVal {
-    pub ident: String,
-    pub exps: Vec<Exp>,
+pub enum LVal {
+    Ident(String, Vec<Exp>, Span),
+    Deref(Box<UnaryExp>, Span),
+    Member(Box<LVal>, String, Vec<Exp>, Span),
 }
 
-// Decl          ::= ConstDecl | VarDecl
+// Decl          ::= ConstDecl | VarDecl | StructDecl
 #[derive(Debug)]
 pub enum Decl {
     Constdecl(ConstDecl),
     Vardecl(VarDecl),
+    Structdecl(StructDecl),
+    Error(),
+}
+
+// StructDef ::= "struct" IDENT "{" FieldDecl {FieldDecl} "}" ";"
+// the struct extension, only at the top level. the `Span` is the name.
+#[derive(Debug)]
+pub struct StructDef {
+    pub ident: String,
+    pub fields: Vec<FieldDef>,
+    pub span: Span,
+}
+
+// FieldDecl ::= FieldType IDENT {"[" ConstExp "]"} {"," IDENT {"[" ConstExp "]"}} ";"
+// one `FieldDef` for each name, the `Span` is the name.
+#[derive(Debug)]
+pub struct FieldDef {
+    pub ty: FieldType,
+    pub ident: String,
+    pub dims: Vec<ConstExp>,
+    pub span: Span,
+}
+
+// FieldType ::= BType | "struct" IDENT
+#[derive(Debug, Clone)]
+pub enum FieldType {
+    Basic(BType),
+    Struct(String, Span),
+}
+
+// StructDecl ::= "struct" IDENT VarDef {"," VarDef} ";"
+// the `Span` is the name of the struct.
+#[derive(Debug)]
+pub struct StructDecl {
+    pub ident: String,
+    pub vardefs: Vec<VarDef>,
+    pub span: Span,
 }
 
 // ConstDecl     ::= "const" BType ConstDef {"," ConstDef} ";";
 #[derive(Debug)]
 pub struct ConstDecl {
-    // pub btype: BType,
+    pub btype: BType,
     pub constdefs: Vec<ConstDef>,
 }
 // VarDecl       ::= BType VarDef {"," VarDef} ";";
 #[derive(Debug)]
 pub struct VarDecl {
-    // pub btype: BType,
+    pub btype: BType,
     pub vardefs: Vec<VarDef>,
 }
 
-// // BType         ::= "int";
-// #[derive(Debug)]
-// pub enum BType {
-//     Int,
-//     // Void,
-//     // Double,
-//     // Float,
-//     // String,
-// }
+// BType         ::= "int" | "float";
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BType {
+    Int,
+    Float,
+}
 
 // ConstDef      ::= IDENT {"[" ConstExp "]"} "=" ConstInitVal;
 #[derive(Debug)]
@@ -157,31 +265,40 @@ pub struct ConstDef {
     pub ident: String,
     pub dims: Vec<ConstExp>,
     pub constinitval: ConstInitVal,
+    pub span: Span, // the identifier.
 }
 
 // VarDef ::= IDENT {"[" ConstExp "]"}
-//      | IDENT {"[" ConstExp "]"} "=" InitVal;
+//      | IDENT {"[" ConstExp "]"} "=" InitVal
+//      | "*" IDENT ["=" Exp];
+// the `Span` is the identifier. a pointer is the extension, it has no dimensions and no braces.
 #[derive(Debug)]
 pub enum VarDef {
-    Ident(String, Vec<ConstExp>),
-    Identinitval(String, Vec<ConstExp>, InitVal),
+    Ident(String, Vec<ConstExp>, Span),
+    Identinitval(String, Vec<ConstExp>, InitVal, Span),
+    Pointer(String, Option<Exp>, Span),
 }
 
 // ConstInitVal  ::= ConstExp | "{" [ConstInitVal {"," ConstInitVal}] "}"
+// the `Span` of a list covers the braces.
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum ConstInitVal {
     SingleExp(ConstExp),
-    ZeroInit(),
-    MultiExp(Vec<ConstInitVal>),
+    ZeroInit(Span),
+    MultiExp(Vec<ConstInitVal>, Span),
+    Error(Span), // a syntax error, the name is still declared.
 }
 
 // InitVal ::= Exp 
 //           | "{" [InitVal {"," InitVal}] "}"
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum InitVal {
     SingleExp(Exp),
-    ZeroInit(),
-    MultiExp(Vec<InitVal>),
+    ZeroInit(Span),
+    MultiExp(Vec<InitVal>, Span),
+    Error(Span),
 }
 
 
@@ -198,10 +315,18 @@ pub struct ConstExp {
     pub exp: Exp,
 }
 
-// Exp         ::= LOrExp;
+// Exp         ::= CondExp;
 #[derive(Debug)]
 pub struct Exp {
-    pub lorexp: LOrExp,
+    pub condexp: CondExp,
+    pub span: Span,
+}
+
+// CondExp     ::= LOrExp | LOrExp "?" Exp ":" CondExp;
+#[derive(Debug)]
+pub enum CondExp {
+    Lorexp(LOrExp),
+    Condexp(LOrExp, Box<Exp>, Box<CondExp>, Span), // the span of the last arm, it isn't an `Exp`.
 }
 
 // LOrExp      ::= LAndExp | LOrExp "||" LAndExp;
@@ -211,11 +336,32 @@ pub enum LOrExp {
     Orexp(Box<LOrExp>, LAndExp),
 }
 
-// LAndExp     ::= EqExp | LAndExp "&&" EqExp;
+// LAndExp     ::= BitOrExp | LAndExp "&&" BitOrExp;
 #[derive(Debug)]
 pub enum LAndExp {
+    Bitorexp(BitOrExp),
+    Andexp(Box<LAndExp>, BitOrExp),
+}
+
+// BitOrExp    ::= BitXorExp | BitOrExp "|" BitXorExp;
+#[derive(Debug)]
+pub enum BitOrExp {
+    Bitxorexp(BitXorExp),
+    Orexp(Box<BitOrExp>, BitXorExp, String),
+}
+
+// BitXorExp   ::= BitAndExp | BitXorExp "^" BitAndExp;
+#[derive(Debug)]
+pub enum BitXorExp {
+    Bitandexp(BitAndExp),
+    Xorexp(Box<BitXorExp>, BitAndExp, String),
+}
+
+// BitAndExp   ::= EqExp | BitAndExp "&" EqExp;
+#[derive(Debug)]
+pub enum BitAndExp {
     Eqexp(EqExp),
-    Andexp(Box<LAndExp>, EqExp),
+    Andexp(Box<BitAndExp>, EqExp, String),
 }
 
 // EqExp       ::= RelExp | EqExp ("==" | "!=") RelExp;
@@ -226,14 +372,22 @@ pub enum EqExp {
     Neqexp(Box<EqExp>, RelExp, String),
 }
 
-// RelExp      ::= AddExp | RelExp ("<" | ">" | "<=" | ">=") AddExp;
+// RelExp      ::= ShiftExp | RelExp ("<" | ">" | "<=" | ">=") ShiftExp;
 #[derive(Debug)]
 pub enum RelExp {
+    Shiftexp(ShiftExp),
+    Ltexp(Box<RelExp>, ShiftExp, String),
+    Gtexp(Box<RelExp>, ShiftExp, String),
+    Geexp(Box<RelExp>, ShiftExp, String),
+    Leexp(Box<RelExp>, ShiftExp, String),
+}
+
+// ShiftExp    ::= AddExp | ShiftExp ("<<" | ">>") AddExp;
+#[derive(Debug)]
+pub enum ShiftExp {
     Addexp(AddExp),
-    Ltexp(Box<RelExp>, AddExp, String),
-    Gtexp(Box<RelExp>, AddExp, String),
-    Geexp(Box<RelExp>, AddExp, String),
-    Leexp(Box<RelExp>, AddExp, String),
+    Shlexp(Box<ShiftExp>, AddExp, String),
+    Sarexp(Box<ShiftExp>, AddExp, String),
 }
 
 // AddExp      ::= MulExp | AddExp ("+" | "-") MulExp;
@@ -245,22 +399,25 @@ pub enum AddExp {
 }
 
 // MulExp      ::= UnaryExp | MulExp ("*" | "/" | "%") UnaryExp;
+// `/` and `%` keep their `Span` for a constant division by zero.
 #[derive(Debug)]
 pub enum MulExp {
     Unaryexp(UnaryExp),
     Mulexp(Box<MulExp>, UnaryExp, String),
-    Divexp(Box<MulExp>, UnaryExp, String),
-    Modexp(Box<MulExp>, UnaryExp, String),
+    Divexp(Box<MulExp>, UnaryExp, String, Span),
+    Modexp(Box<MulExp>, UnaryExp, String, Span),
 }
 
 // UnaryExp    ::= PrimaryExp
 //               | IDENT "(" [FuncRParams] ")"
 //               | UnaryOp UnaryExp
+//               | "&" LVal
 #[derive(Debug)]
 pub enum UnaryExp {
     Primaryexp(PrimaryExp),
     Unaryexp(UnaryOp, Box<UnaryExp>),
-    Funcall(String, Option<FuncRParams>),
+    Funcall(String, Option<FuncRParams>, Span),
+    AddrOf(LVal, Span),
 }
 
 // FuncRParams ::= Exp {"," Exp};
@@ -269,18 +426,26 @@ pub struct FuncRParams {
     pub params: Vec<Exp>,
 }
 
-// PrimaryExp    ::= "(" Exp ")" | LVal | Number;
+// PrimaryExp    ::= "(" Exp ")" | LVal | Number | String;
+// Number        ::= IntConst | CharConst | floatConst;
+// a `float` literal can have the wrong type (`x % 2.0`), it keeps its `Span`. so does a string.
+// a string is the bytes without the `\0`, it's only an argument of `putf`.
+// `BigNum` is an integer literal too large for `int`, only `-2147483648` is allowed.
 #[derive(Debug)]
 pub enum PrimaryExp {
     Exp(Box<Exp>),
     Lval(LVal),
     Num(i32),
+    BigNum(u64, Span),
+    FloatNum(f32, Span),
+    Str(Vec<u8>, Span),
 }
 
-// UnaryOp     ::= "+" | "-" | "!";
+// UnaryOp     ::= "+" | "-" | "!" | "~";
 #[derive(Debug)]
 pub enum UnaryOp {
     Add,
     Sub,
     Not,
+    BitNot,
 }
\ No newline at end of file
@@ -0,0 +1,457 @@
+use lalrpop_util::lexer::Token;
+use lalrpop_util::{ErrorRecovery, ParseError};
+
+/*
+ * 所有错误信息的出口.
+ * Span 是源文件里的一段 byte offset `[lo, hi)`, grammar 用 `@L`/`@R` 记录, 挂在 ast 的结点上,
+ * 之后的 pass 报错的时候直接拿来用.
+ * Diagnostic 负责把 span 翻译成 `file:line:col`, 再打印出那一行源码和下面的 `^^^`.
+ * parser 看到的是预处理之后的文本, span 先经过 SourceMap 映射回原来的文件, 见 `preprocess.rs`.
+ */
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub lo: usize,
+    pub hi: usize,
+}
+
+impl Span {
+    pub fn new(lo: usize, hi: usize) -> Span {
+        Span { lo, hi }
+    }
+}
+
+
+// the input file, with the beginning offset of every line for quick lookup.
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: String, text: String) -> SourceFile {
+        let mut line_starts = vec![0];
+        for (i, c) in text.char_indices() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        SourceFile { name, text, line_starts }
+    }
+
+    // 1-based (line, column) of a byte offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = std::cmp::min(offset, self.text.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        let col = self.text[self.line_starts[line]..offset].chars().count();
+        (line + 1, col + 1)
+    }
+
+    // text of the 1-based line, without the line break.
+    pub fn line(&self, line: usize) -> &str {
+        let lo = self.line_starts[line - 1];
+        let hi = if line < self.line_starts.len() { self.line_starts[line] } else { self.text.len() };
+        self.text[lo..hi].trim_end_matches(['\n', '\r'])
+    }
+}
+
+
+// a piece of the preprocessed text. a verbatim piece is copied byte by byte from `orig`,
+// every byte of a macro expansion comes from the whole invocation `orig`.
+struct Segment {
+    out: usize,
+    len: usize,
+    file: usize,
+    orig: Span,
+    verbatim: bool,
+}
+
+// the preprocessed text and the line map back to the files it comes from.
+pub struct SourceMap {
+    pub text: String,
+    pub files: Vec<SourceFile>,
+    segments: Vec<Segment>,
+}
+
+impl SourceMap {
+    // a file without preprocessing, every offset is its own.
+    pub fn new(file: SourceFile) -> SourceMap {
+        let mut map = SourceMap { text: String::new(), files: vec![file], segments: Vec::new() };
+        let text = map.files[0].text.clone();
+        map.push_verbatim(0, &text, 0, text.len());
+        map
+    }
+
+    pub fn empty() -> SourceMap {
+        SourceMap { text: String::new(), files: Vec::new(), segments: Vec::new() }
+    }
+
+    pub fn add_file(&mut self, file: SourceFile) -> usize {
+        self.files.push(file);
+        self.files.len() - 1
+    }
+
+    // copy `text[lo..hi]`, `text` has the same offsets as `file`.
+    pub fn push_verbatim(&mut self, file: usize, text: &str, lo: usize, hi: usize) {
+        if lo >= hi {
+            return;
+        }
+        let out = self.text.len();
+        self.text.push_str(&text[lo..hi]);
+        if let Some(last) = self.segments.last_mut() {
+            if last.verbatim && last.file == file && last.out + last.len == out && last.orig.hi == lo {
+                last.len += hi - lo;
+                last.orig.hi = hi;
+                return;
+            }
+        }
+        self.segments.push(Segment { out, len: hi - lo, file, orig: Span::new(lo, hi), verbatim: true });
+    }
+
+    pub fn push_expansion(&mut self, file: usize, orig: Span, text: &str) {
+        let out = self.text.len();
+        self.text.push_str(text);
+        self.segments.push(Segment { out, len: text.len(), file, orig, verbatim: false });
+    }
+
+    // the lines of a directive or of a skipped `#if` branch, nothing points at them.
+    pub fn push_newline(&mut self) {
+        self.text.push('\n');
+    }
+
+    // the file and the span in it of a span of `text`.
+    pub fn locate(&self, span: Span) -> (&SourceFile, Span) {
+        let index = |offset: usize| match self.segments.binary_search_by(|seg| seg.out.cmp(&offset)) {
+            Ok(i) => i,
+            Err(i) => i.saturating_sub(1),
+        };
+        let map = |seg: &Segment, offset: usize, end: bool| {
+            if seg.verbatim {
+                seg.orig.lo + std::cmp::min(offset.saturating_sub(seg.out), seg.len)
+            } else if end {
+                seg.orig.hi
+            } else {
+                seg.orig.lo
+            }
+        };
+        if self.segments.is_empty() {
+            return (&self.files[0], Span::default());
+        }
+        let seg = &self.segments[index(span.lo)];
+        let lo = map(seg, span.lo, false);
+        let end = &self.segments[index(std::cmp::max(span.hi, span.lo + 1) - 1)];
+        let hi = if end.file == seg.file { std::cmp::max(map(end, span.hi, true), lo) } else { lo };
+        (&self.files[seg.file], Span::new(lo, hi))
+    }
+}
+
+
+// an error an action of the grammar finds in a token, at the token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenError {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub label: String, // printed right after the caret.
+    pub file: Option<usize>, // the span is already in this file of the SourceMap, for the preprocessor.
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, message: String) -> Diagnostic {
+        Diagnostic { message, span, label: "".to_string(), file: None }
+    }
+
+    pub fn in_file(mut self, file: usize) -> Diagnostic {
+        self.file = Some(file);
+        self
+    }
+
+    pub fn with_label(mut self, label: String) -> Diagnostic {
+        self.label = label;
+        self
+    }
+
+    // error: unexpected token `}`
+    //  --> hello.c:3:5
+    //   |
+    // 3 |     }
+    //   |     ^ expected one of `;`, `=`
+    pub fn render(&self, map: &SourceMap) -> String {
+        let (file, span) = match self.file {
+            Some(file) => (&map.files[file], self.span),
+            None => map.locate(self.span),
+        };
+        let (line, col) = file.line_col(span.lo);
+        let text = file.line(line);
+        let gutter = " ".repeat(line.to_string().len());
+
+        // the caret covers the span, but never runs past the first line.
+        let width = {
+            let (hi_line, hi_col) = file.line_col(span.hi);
+            let end = if hi_line == line { hi_col } else { text.chars().count() + 1 };
+            std::cmp::max(end.saturating_sub(col), 1)
+        };
+        // keep tabs so that the caret lines up with the source.
+        let padding: String = text.chars().take(col - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+
+        let mut program = format!("error: {}\n", self.message);
+        program.push_str(&format!("{}--> {}:{}:{}\n", gutter, file.name, line, col));
+        program.push_str(&format!("{} |\n", gutter));
+        program.push_str(&format!("{} | {}\n", line, text));
+        program.push_str(&format!("{} | {}{}", gutter, padding, "^".repeat(width)));
+        if !self.label.is_empty() {
+            program.push_str(&format!(" {}", self.label));
+        }
+        program.push('\n');
+        program
+    }
+}
+
+
+// lalrpop names a regex terminal after its pattern, escaped: `r#"[1-9][0-9]*"#`, `r#"\"...\""#`.
+// tell the literals apart by the pattern.
+fn terminal_name(pattern: &str) -> String {
+    let Some(body) = pattern.strip_prefix("r#\"") else {
+        return format!("`{}`", pattern.trim_matches('"'));
+    };
+    if body.starts_with("[_a-zA-Z]") {
+        "identifier".to_string()
+    } else if body.starts_with('\'') {
+        "character literal".to_string()
+    } else if body.starts_with("\\\"") {
+        "string literal".to_string()
+    } else if body.contains("\\.") || body.contains("[eE]") || body.contains("[pP]") {
+        "float literal".to_string()
+    } else {
+        "integer literal".to_string()
+    }
+}
+
+// the operators that can only follow an operand, and every operator that may come after one.
+const BINARY_ONLY: [&str; 24] = ["!=", "%", "%=", "&&", "&=", "*=", "+=", "-=", "/", "/=", "<", "<<", "<<=", "<=",
+    "==", ">", ">=", ">>", ">>=", "^", "^=", "|", "|=", "||"];
+const AFTER_OPERAND: [&str; 5] = ["&", "*", "+", "-", "?"];
+
+// make the terminals readable.
+fn expected_str(expected: &[String]) -> String {
+    // after an operand every operator is expected, they are just "an operator".
+    let is_operator = |name: &str| BINARY_ONLY.contains(&name) || AFTER_OPERAND.contains(&name);
+    let operators = expected.iter().any(|x| BINARY_ONLY.contains(&x.trim_matches('"')));
+    // the patterns of one kind of literal collapse into one name.
+    let mut names_dedup: Vec<String> = Vec::new();
+    for name in expected.iter().filter(|x| !operators || !is_operator(x.trim_matches('"'))).map(|x| terminal_name(x)) {
+        if !names_dedup.contains(&name) {
+            names_dedup.push(name);
+        }
+    }
+    if operators {
+        names_dedup.push("an operator".to_string());
+    }
+
+    match names_dedup.len() {
+        0 => "".to_string(),
+        1 => format!("expected {}", names_dedup[0]),
+        _ => format!("expected one of {}", names_dedup.join(", ")),
+    }
+}
+
+pub fn from_parse_error(err: ParseError<usize, Token, TokenError>) -> Diagnostic {
+    match err {
+        ParseError::InvalidToken { location } => {
+            Diagnostic::error(Span::new(location, location + 1), "invalid token".to_string())
+        },
+        ParseError::UnrecognizedEOF { location, expected } => {
+            Diagnostic::error(Span::new(location, location), "unexpected end of file".to_string())
+                .with_label(expected_str(&expected))
+        },
+        ParseError::UnrecognizedToken { token: (lo, tok, hi), expected } => {
+            Diagnostic::error(Span::new(lo, hi), format!("unexpected token `{}`", tok))
+                .with_label(expected_str(&expected))
+        },
+        ParseError::ExtraToken { token: (lo, tok, hi) } => {
+            Diagnostic::error(Span::new(lo, hi), format!("extra token `{}`", tok))
+        },
+        ParseError::User { error } => Diagnostic::error(error.span, error.message),
+    }
+}
+
+// the errors the grammar recovered from. an error of an item can be pushed after the errors inside the item,
+// report them in the order of the source.
+pub fn from_recovered(errors: Vec<ErrorRecovery<usize, Token, TokenError>>) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = errors.into_iter().map(|e| from_parse_error(e.error)).collect();
+    diagnostics.sort_by_key(|diag| diag.span.lo);
+    diagnostics
+}
+
+// print everything to stderr, the caller decides whether to stop.
+pub fn emit(diagnostics: &[Diagnostic], map: &SourceMap) {
+    for diag in diagnostics {
+        eprintln!("{}", diag.render(map));
+    }
+    if diagnostics.len() == 1 {
+        eprintln!("error: aborting due to previous error");
+    } else if diagnostics.len() > 1 {
+        eprintln!("error: aborting due to {} previous errors", diagnostics.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(text: &str) -> SourceMap {
+        SourceMap::new(SourceFile::new("t.c".to_string(), text.to_string()))
+    }
+
+    // the diagnostics of parsing `text`, rendered.
+    fn parse(text: &str) -> String {
+        let map = map(text);
+        let mut errors = Vec::new();
+        let result = crate::sysy::CompUnitParser::new().parse(&mut errors, &map.text);
+        let mut diagnostics = from_recovered(errors);
+        if let Err(err) = result {
+            diagnostics.push(from_parse_error(err));
+        }
+        diagnostics.iter().map(|diag| diag.render(&map)).collect()
+    }
+
+    #[test]
+    fn line_col() {
+        let file = SourceFile::new("t.c".to_string(), "ab\n\ncd".to_string());
+        assert_eq!(file.line_col(0), (1, 1));
+        assert_eq!(file.line_col(3), (2, 1));
+        assert_eq!(file.line_col(5), (3, 2));
+        assert_eq!(file.line_col(99), (3, 3));
+        assert_eq!(file.line(2), "");
+    }
+
+    #[test]
+    fn render_with_label() {
+        let diag = Diagnostic::error(Span::new(15, 18), "use of undeclared identifier `foo`".to_string())
+            .with_label("not found in this scope".to_string());
+        assert_eq!(diag.render(&map("int main() {\n  foo;\n}\n")), "\
+error: use of undeclared identifier `foo`
+ --> t.c:2:3
+  |
+2 |   foo;
+  |   ^^^ not found in this scope
+");
+    }
+
+    #[test]
+    fn caret_stops_at_the_end_of_the_line() {
+        let text = "\n\n\n\n\n\n\n\n\n\tint a = {\n1};\n";
+        let diag = Diagnostic::error(Span::new(16, 23), "excess elements".to_string());
+        assert_eq!(diag.render(&map(text)), "\
+error: excess elements
+  --> t.c:10:8
+   |
+10 | \tint a = {
+   | \t      ^^^
+");
+    }
+
+    #[test]
+    fn render_in_another_file() {
+        let mut map = map("#include \"a.h\"\n");
+        let file = map.add_file(SourceFile::new("a.h".to_string(), "#if\n".to_string()));
+        let diag = Diagnostic::error(Span::new(0, 3), "`#if` with no expression".to_string()).in_file(file);
+        assert_eq!(diag.render(&map), "\
+error: `#if` with no expression
+ --> a.h:1:1
+  |
+1 | #if
+  | ^^^
+");
+    }
+
+    #[test]
+    fn missing_semicolon() {
+        assert_eq!(parse("int main() {\n    return 0\n}\n"), "\
+error: unexpected token `}`
+ --> t.c:3:1
+  |
+3 | }
+  | ^ expected `;`
+");
+    }
+
+    #[test]
+    fn every_broken_statement_is_reported() {
+        let rendered = parse("int main() {\n  int a = 1 2;\n  a = ;\n  return a;\n}\nint f() {\n  return 0\n}\n");
+        assert_eq!(rendered.matches("error: ").count(), 3, "{}", rendered);
+        assert!(rendered.contains(" --> t.c:2:13\n") && rendered.contains(" --> t.c:3:7\n") && rendered.contains(" --> t.c:8:1\n"));
+    }
+
+    #[test]
+    fn broken_declarations_resynchronize() {
+        let rendered = parse("int main() {\n  int a = ;\n  const int b = 1\n  return a + b;\n}\n");
+        assert_eq!(rendered.matches("error: ").count(), 2);
+        assert!(rendered.contains(" --> t.c:2:11\n"));
+        assert!(rendered.contains(" --> t.c:4:3\n"));
+    }
+
+    #[test]
+    fn token_error_at_its_token() {
+        let error = TokenError { span: Span::new(22, 26), message: "bad literal".to_string() };
+        let diag = from_parse_error(ParseError::User { error });
+        assert!(diag.render(&map("int main() {\n  return 0x1g;\n}\n")).starts_with("error: bad literal\n --> t.c:2:10\n"));
+    }
+
+    #[test]
+    fn operators_after_an_operand_collapse() {
+        assert_eq!(parse("int main() {\n  return 1 2;\n}\n"), "\
+error: unexpected token `2`
+ --> t.c:2:12
+  |
+2 |   return 1 2;
+  |            ^ expected one of `)`, `,`, `:`, `;`, `=`, `]`, `}`, an operator
+");
+    }
+
+    #[test]
+    fn broken_signature_is_reported_once() {
+        for text in ["int f( {\n  return 1;\n}\n", "int f(int a, {\n  return a;\n}\n", "int f(int a {\n  return a;\n}\n"] {
+            let rendered = parse(&format!("{}int main() {{\n  return f(1);\n}}\n", text));
+            assert_eq!(rendered.matches("error: ").count(), 1, "{}", rendered);
+            assert!(rendered.starts_with("error: unexpected token `{`\n --> t.c:1:"));
+        }
+        // the parameters before the error are kept, the body and the call check fine.
+        let text = "int f(int a {\n  return a;\n}\nint main() {\n  return f(1);\n}\n";
+        assert_eq!(crate::testing::errors(text, Default::default()), "\
+error: unexpected token `{`
+ --> t.c:1:13
+  |
+1 | int f(int a {
+  |             ^ expected one of `)`, `,`, `[`
+");
+    }
+
+    #[test]
+    fn invalid_token() {
+        assert_eq!(parse("int main() {\n  int a = 1 $ 2;\n}\n"), "\
+error: invalid token
+ --> t.c:2:13
+  |
+2 |   int a = 1 $ 2;
+  |             ^
+");
+    }
+
+    #[test]
+    fn literal_terminals_collapse() {
+        let expected = parse("int main() {\n  return 1 +");
+        assert!(expected.starts_with("error: unexpected end of file\n --> t.c:2:13\n"));
+        assert!(expected.ends_with("^ expected one of `!`, `&`, `(`, `*`, `+`, `-`, `~`, \
+            string literal, character literal, integer literal, float literal, identifier\n"));
+    }
+}
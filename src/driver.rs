@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use koopa::back::KoopaGenerator;
+use koopa::ir::{BasicBlock, Function, Program};
+
+use crate::ast::CompUnit;
+use crate::diagnostic::{self, Diagnostic, SourceFile, SourceMap, Span};
+use crate::koopa_ir_gen::{self, FloatAbi, JumpTable};
+use crate::semantic::{self, Extensions, FunctionSignature};
+use crate::{preprocess, riscv_target_gen, sysy};
+
+/*
+ * 整个编译流程: 预处理, parse, 语义检查, 生成 koopa. main 和测试都走这里, 只是输入和输出的地方不一样.
+ * 任何一步有错误就停在那一步之后, 错误和 SourceMap 一起返回, 由调用的人打印.
+ * 语法错误会被 recover, 之后照样做语义检查, 一次把能发现的错误都报告出来.
+ */
+
+// what is printed in the end, `-koopa` can't have mutually recursive functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Koopa,
+    Riscv,
+}
+
+// the in-memory koopa Program, with what the backend needs besides it.
+pub struct Output {
+    pub program: Program,
+    pub float_abi: HashMap<Function, FloatAbi>,
+    pub jump_tables: HashMap<BasicBlock, JumpTable>,
+}
+
+impl Output {
+    // the text of `-koopa`.
+    pub fn koopa(&self) -> String {
+        let mut generator = KoopaGenerator::new(Vec::new());
+        generator.generate_on(&self.program).unwrap();
+        String::from_utf8(generator.writer()).unwrap()
+    }
+
+    // the assembly of `-riscv`.
+    pub fn riscv(self) -> String {
+        riscv_target_gen::generate(self.program, &self.float_abi, &self.jump_tables)
+    }
+}
+
+// 调用生成的parser: sysy, 指定start non-terminal: CompUnit(结尾默认加入Parser).
+// 只有 lexer 的错误或者实在救不回来的才没有 AST.
+fn parse(source: &SourceMap) -> (Option<CompUnit>, Vec<Diagnostic>) {
+    let mut errors = Vec::new();
+    let result = sysy::CompUnitParser::new().parse(&mut errors, &source.text);
+    let mut diagnostics = diagnostic::from_recovered(errors);
+    match result {
+        Ok(ast) => (Some(ast), diagnostics),
+        Err(err) => {
+            diagnostics.push(diagnostic::from_parse_error(err));
+            (None, diagnostics)
+        },
+    }
+}
+
+// compile `input`, the diagnostics point into the returned SourceMap.
+pub fn compile(input: SourceFile, include_dirs: &[String], runtime: &[(String, FunctionSignature)],
+        extensions: Extensions, target: Target) -> (SourceMap, Result<Output, Vec<Diagnostic>>) {
+    let (source, errors) = preprocess::run(input, include_dirs);
+    if !errors.is_empty() {
+        return (source, Err(errors));
+    }
+    let (ast, mut diagnostics) = parse(&source);
+    let Some(ast) = ast else {
+        return (source, Err(diagnostics));
+    };
+    let (errors, checked) = semantic::check(&ast, runtime, extensions);
+    diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+    // koopa 的文本里函数要先出现才能被调用, 也不能对同一个名字既 `decl` 又 `fun`,
+    // 所以互相递归的函数没法写成 koopa 文本, 这是 `-koopa` 的限制, 不是程序的错误. 与其输出一个读不回来的文件, 不如在这里报错.
+    // `-riscv` 直接用内存里的 Program, 不经过文本, 互相递归照样编译, 所以只在 Target::Koopa 检查.
+    if target == Target::Koopa {
+        if let (_, Some(cycle)) = checked.calls.callees_first(&semantic::function_names(&ast)) {
+            diagnostics.push(Diagnostic::from(cycle));
+        }
+    }
+    if !diagnostics.is_empty() {
+        return (source, Err(diagnostics));
+    }
+    // `starttime()` 传入的是原来文件里的行号, 和 `sylib.h` 里的 `__LINE__` 一样.
+    let timer_lines: HashMap<Span, i32> = checked.timers.iter().map(|&span| {
+        let (file, span_in_file) = source.locate(span);
+        (span, file.line_col(span_in_file.lo).0 as i32)
+    }).collect();
+    // koopa 里 float 也是 i32, 哪些参数和返回值是 float 要另外告诉后端. `switch` 的跳转表也一样.
+    let (program, float_abi, jump_tables) = koopa_ir_gen::generator(ast, runtime, &checked, timer_lines);
+    (source, Ok(Output { program, float_abi, jump_tables }))
+}
+
+// the prototypes of a `-runtime`, the runtime library is extended by them.
+type Prototypes = Vec<(String, FunctionSignature)>;
+
+// parse and check the prototypes of a `-runtime`, the errors are reported like those of the input.
+pub fn runtime_prototypes(text: String, runtime: &[(String, FunctionSignature)], extensions: Extensions) -> (SourceMap, Result<Prototypes, Vec<Diagnostic>>) {
+    let source = SourceMap::new(SourceFile::new("<runtime>".to_string(), text));
+    let (ast, mut diagnostics) = parse(&source);
+    let Some(ast) = ast else {
+        return (source, Err(diagnostics));
+    };
+    let (errors, prototypes) = semantic::runtime_prototypes(&ast, runtime, extensions);
+    diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+    if !diagnostics.is_empty() {
+        return (source, Err(diagnostics));
+    }
+    (source, Ok(prototypes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::BType;
+    use crate::semantic::Type;
+
+    // the prototypes of `text` after the default runtime library, or the rendered diagnostics.
+    fn prototypes(text: &str) -> std::result::Result<Prototypes, String> {
+        let (source, result) = runtime_prototypes(text.to_string(), &semantic::runtime_library(), Extensions::default());
+        result.map_err(|diagnostics| diagnostics.iter().map(|diag| diag.render(&source)).collect())
+    }
+
+    #[test]
+    fn runtime_prototypes_extend_the_library() {
+        let prototypes = prototypes("int getcycles(); void putdigits(int a[], int n); int getint();").unwrap();
+        assert_eq!(prototypes, [
+            ("getcycles".to_string(), FunctionSignature::new(vec![], Type::Int)),
+            ("putdigits".to_string(), FunctionSignature::new(vec![Type::Pointer(BType::Int, vec![]), Type::Int], Type::Void)),
+        ]); // `getint` is in the library already.
+        let mut runtime = semantic::runtime_library();
+        runtime.extend(prototypes);
+        let input = SourceFile::new("t.c".to_string(), "int main() {\n  return getcycles();\n}\n".to_string());
+        let (_, result) = compile(input, &[], &runtime, Extensions::default(), Target::Koopa);
+        assert!(result.unwrap().koopa().contains("decl @getcycles(): i32\n"));
+    }
+
+    #[test]
+    fn malformed_runtime_prototypes_are_errors() {
+        let errors = prototypes("int f(int a[];").unwrap_err();
+        assert!(errors.contains(" --> <runtime>:1:14\n"), "{}", errors);
+        let errors = prototypes("int g(int a) { return a; }").unwrap_err();
+        assert!(errors.starts_with("error: `-runtime` takes prototypes only, `g` has a body\n --> <runtime>:1:5\n"), "{}", errors);
+    }
+}
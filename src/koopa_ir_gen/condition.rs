@@ -0,0 +1,166 @@
+use koopa::ir::BasicBlock;
+
+use super::context::Context;
+use super::expression::{float_to_bool, pointer_to_bool, ExpResult};
+
+use crate::ast::*;
+use crate::koopa_ir_gen::get_value;
+
+/*
+ * 条件的求值: 在`if`, `while`这样的控制流里面, 条件的结果只用来决定跳到哪里, 不需要存成一个 int.
+ * `&&` 和 `||` 直接跳到 true_bb / false_bb, 短路的时候右边根本不会被执行:
+ *     a && b:  a 为真跳到 b 的 block, 为假直接跳到 false_bb.
+ *     a || b:  a 为真直接跳到 true_bb, 为假跳到 b 的 block.
+ * 其他的表达式按照普通的值求值, 最后 br 一下.
+ *
+ * 返回 Some(v) 说明条件是常量, 这时候什么指令都没有生成, 由调用者决定跳到哪里.
+ * 返回 None 说明当前 block 已经以跳转结束了.
+ */
+pub trait CondResult {
+    fn cond(&self, ctx: &mut Context, true_bb: BasicBlock, false_bb: BasicBlock) -> Option<bool>;
+}
+
+// the targets are known, emit a jump for a constant condition too.
+pub fn branch_to<C: CondResult>(ctx: &mut Context, exp: &C, true_bb: BasicBlock, false_bb: BasicBlock) {
+    if let Some(val) = exp.cond(ctx, true_bb, false_bb) {
+        ctx.jump(if val { true_bb } else { false_bb });
+    }
+}
+
+// Exp ::= CondExp;
+impl CondResult for Exp {
+    fn cond(&self, ctx: &mut Context, true_bb: BasicBlock, false_bb: BasicBlock) -> Option<bool> {
+        self.condexp.cond(ctx, true_bb, false_bb)
+    }
+}
+
+// CondExp ::= LOrExp | LOrExp "?" Exp ":" CondExp;
+// each arm jumps to the targets itself, the value of `c ? a : b` is never stored.
+impl CondResult for CondExp {
+    fn cond(&self, ctx: &mut Context, true_bb: BasicBlock, false_bb: BasicBlock) -> Option<bool> {
+        match self {
+            CondExp::Lorexp(lorexp) => lorexp.cond(ctx, true_bb, false_bb),
+            CondExp::Condexp(lorexp, exp, condexp, _) => {
+                let then_bb = ctx.new_bb("%ternary_true");
+                let else_bb = ctx.new_bb("%ternary_false");
+                match lorexp.cond(ctx, then_bb, else_bb) {
+                    Some(val) => { // only one arm is there.
+                        ctx.remove_bb(then_bb);
+                        ctx.remove_bb(else_bb);
+                        if val {
+                            exp.cond(ctx, true_bb, false_bb)
+                        } else {
+                            condexp.cond(ctx, true_bb, false_bb)
+                        }
+                    },
+                    None => {
+                        ctx.set_bb(then_bb);
+                        branch_to(ctx, exp.as_ref(), true_bb, false_bb);
+                        ctx.set_bb(else_bb);
+                        branch_to(ctx, condexp.as_ref(), true_bb, false_bb);
+                        None
+                    },
+                }
+            },
+        }
+    }
+}
+
+// LOrExp ::= LAndExp | LOrExp "||" LAndExp;
+impl CondResult for LOrExp {
+    fn cond(&self, ctx: &mut Context, true_bb: BasicBlock, false_bb: BasicBlock) -> Option<bool> {
+        match self {
+            LOrExp::Landexp(landexp) => landexp.cond(ctx, true_bb, false_bb),
+            LOrExp::Orexp(lorexp, landexp) => {
+                let rhs_bb = ctx.new_bb("%lor_rhs");
+                match lorexp.cond(ctx, true_bb, rhs_bb) {
+                    Some(true) => { // the right is never evaluated.
+                        ctx.remove_bb(rhs_bb);
+                        Some(true)
+                    },
+                    Some(false) => {
+                        ctx.remove_bb(rhs_bb);
+                        landexp.cond(ctx, true_bb, false_bb)
+                    },
+                    None => {
+                        ctx.set_bb(rhs_bb);
+                        branch_to(ctx, landexp, true_bb, false_bb);
+                        None
+                    },
+                }
+            },
+        }
+    }
+}
+
+// LAndExp ::= BitOrExp | LAndExp "&&" BitOrExp;
+impl CondResult for LAndExp {
+    fn cond(&self, ctx: &mut Context, true_bb: BasicBlock, false_bb: BasicBlock) -> Option<bool> {
+        match self {
+            LAndExp::Bitorexp(bitorexp) => bitorexp.cond(ctx, true_bb, false_bb),
+            LAndExp::Andexp(landexp, bitorexp) => {
+                let rhs_bb = ctx.new_bb("%land_rhs");
+                match landexp.cond(ctx, rhs_bb, false_bb) {
+                    Some(false) => { // the right is never evaluated.
+                        ctx.remove_bb(rhs_bb);
+                        Some(false)
+                    },
+                    Some(true) => {
+                        ctx.remove_bb(rhs_bb);
+                        bitorexp.cond(ctx, true_bb, false_bb)
+                    },
+                    None => {
+                        ctx.set_bb(rhs_bb);
+                        branch_to(ctx, bitorexp, true_bb, false_bb);
+                        None
+                    },
+                }
+            },
+        }
+    }
+}
+
+// BitOrExp is the leaf of a condition, evaluate it as a value.
+impl CondResult for BitOrExp {
+    fn cond(&self, ctx: &mut Context, true_bb: BasicBlock, false_bb: BasicBlock) -> Option<bool> {
+        let mut ret_val = self.eval(ctx);
+        ret_val = pointer_to_bool(ctx, &ret_val);
+        if ret_val.is_float {
+            ret_val = float_to_bool(ctx, &ret_val);
+        }
+        if ret_val.is_constant {
+            return Some(ret_val.exp_res_id != 0);
+        }
+        let val = get_value(ctx, &ret_val);
+        ctx.branch(val, true_bb, false_bb);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::semantic::Extensions;
+    use crate::testing::{koopa, run};
+
+    #[test]
+    fn short_circuit_branches_to_the_targets() {
+        let text = "int main() {\n  int a = getint(), b = getint(), r = 0;\n  if (a > 0 && b > 0 || a < -5) r = 1;\n\
+            while (a && b) a = a - 1;\n  return r;\n}\n";
+        let ir = koopa(text, Extensions::default());
+        assert!(ir.contains("  br %3, %land_rhs, %lor_rhs\n"));
+        assert!(ir.contains("  br %5, %if_then, %lor_rhs\n"));
+        assert!(ir.contains("  br %7, %if_then, %if_end\n"));
+        assert!(ir.contains("  br %9, %while_body, %while_end\n"));
+        assert!(!ir.contains("%condition") && !ir.contains(" ne ") && !ir.contains(" and "));
+        let results: Vec<i32> = [[1, 1], [1, 0], [-6, 0], [0, 5]].iter().map(|input| run(text, Extensions::default(), input).0).collect();
+        assert_eq!(results, [1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn value_is_materialized_only_when_used() {
+        let text = "int g = 0;\nint f() { g = 1; return 1; }\nint main() {\n  int a = getint();\n  int v = a && f();\n  return v * 10 + g;\n}\n";
+        assert!(koopa(text, Extensions::default()).contains("  store 1, %condition\n"));
+        assert_eq!(run(text, Extensions::default(), &[0]).0, 0);
+        assert_eq!(run(text, Extensions::default(), &[3]).0, 11);
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use koopa::ir::builder::LocalBuilder;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, Value};
+
+use super::symbol_table::SymbolTable;
+use super::{FloatAbi, JumpTable, INTRINSICS, INTRINSIC_PREFIX, STRING_PREFIX};
+use crate::diagnostic::Span;
+use crate::semantic::{Element, FunctionSignature, StructLayout};
+
+/*
+ * 生成 koopa 的时候需要的所有状态: 正在构建的 Program, 当前所在的函数和 basic block, 以及符号表.
+ * 所有的 eval 都拿着 `&mut Context`, 指令直接插到当前 basic block 的末尾.
+ *
+ * 每条指令都要先在 dfg 里创建, 再放进 layout, 这里把这两步包起来:
+ *     let val = ctx.new_value().load(src);
+ *     ctx.push_inst(val);
+ * 整数常量和 aggregate 只在 dfg 里创建, 不进 layout. 在函数外面的时候它们属于 Program, 用作全局变量的初始值.
+ *
+ * loops 是从外到内的循环栈, 每个循环压入 (continue_target, break_target),
+ * `break` 和 `continue` 直接跳到栈顶对应的 basic block. `switch` 也压一层, continue_target 沿用外面的循环.
+ *
+ * float_abi 记下每个函数哪些参数和返回值是 `float`, 交给后端. intrinsics 是 float 运算对应的声明,
+ * 文本形式的 koopa 要求函数先声明再使用, 所以它们和库函数一起在最前面声明, 最后去掉没有用到的, 见 `declare_intrinsics`.
+ * jump_tables 是 `switch` 的跳转表, 同样交给后端.
+ * strings 是字符串字面量对应的全局数组, 同样的字符串只有一份.
+ * structs 是 struct 扩展的布局, 按名字查, 见 `semantic/structs.rs`.
+ * timer_lines 是没有声明过的 `starttime()`/`stoptime()` 调用所在的行号, 按调用的 span 查.
+ */
+pub struct Context {
+    pub program: Program,
+    pub scope: SymbolTable,
+    pub loops: Vec<(BasicBlock, BasicBlock)>, // (continue_target, break_target) of the enclosing loops.
+    pub float_abi: HashMap<Function, FloatAbi>,
+    pub jump_tables: HashMap<BasicBlock, JumpTable>,
+    pub structs: HashMap<String, StructLayout>,
+    pub timer_lines: HashMap<Span, i32>,
+    intrinsics: HashMap<String, (Function, bool)>, // whether it's called.
+    strings: HashMap<Vec<u8>, Value>,
+    func: Option<Function>,
+    bb: Option<BasicBlock>,
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context {
+            program: Program::new(), scope: SymbolTable::new(), loops: Vec::new(),
+            float_abi: HashMap::new(), jump_tables: HashMap::new(), structs: HashMap::new(), timer_lines: HashMap::new(), intrinsics: HashMap::new(), strings: HashMap::new(), func: None, bb: None,
+        }
+    }
+
+    // add a function (or a declaration) to the program, and remember where its `float`s are.
+    pub fn new_func(&mut self, data: FunctionData, signature: &FunctionSignature) -> Function {
+        let func = self.program.new_func(data);
+        self.float_abi.insert(func, FloatAbi::new(signature));
+        func
+    }
+
+    // the current function returns a `float`.
+    pub fn returns_float(&self) -> bool {
+        self.float_abi[&self.func.unwrap()].ret
+    }
+
+    // every intrinsic returns an `i32`, the bits of a `float` or an `int`.
+    //     decl @__sysy_fadd(i32, i32): i32
+    // text koopa wants a `decl` before its use and the functions can't be reordered once created,
+    // so all of them are declared before the first function, `remove_unused_intrinsics` drops the rest.
+    pub fn declare_intrinsics(&mut self) {
+        for (name, argc) in INTRINSICS {
+            let params = match name {
+                "ptoi" => vec![Type::get_pointer(Type::get_i32())],
+                _ => vec![Type::get_i32(); argc],
+            };
+            let data = FunctionData::new_decl(format!("{}{}", INTRINSIC_PREFIX, name), params, Type::get_i32());
+            let func = self.program.new_func(data);
+            self.intrinsics.insert(name.to_string(), (func, false));
+        }
+    }
+
+    // a program without `float` and pointers declares no intrinsic.
+    pub fn remove_unused_intrinsics(&mut self) {
+        for (func, used) in self.intrinsics.values() {
+            if !used {
+                self.program.remove_func(*func);
+            }
+        }
+        self.intrinsics.retain(|_, (_, used)| *used);
+    }
+
+    // %1 = call @__sysy_fadd(%0, 1065353216)
+    pub fn call_intrinsic(&mut self, name: &str, args: Vec<Value>) -> Value {
+        let (func, used) = self.intrinsics.get_mut(name).unwrap();
+        *used = true;
+        let func = *func;
+        let call = self.new_value().call(func, args);
+        self.push_inst(call);
+        call
+    }
+
+    // the global of a string literal, a byte an `i32`, ended by `0`.
+    //     global @__sysy_str_0 = alloc [i32, 3], {104, 105, 0}
+    pub fn string(&mut self, bytes: &[u8]) -> Value {
+        if let Some(value) = self.strings.get(bytes) {
+            return *value;
+        }
+        let elems = bytes.iter().chain([&0]).map(|byte| self.program.new_value().integer(*byte as i32)).collect();
+        let init = self.program.new_value().aggregate(elems);
+        let alloc = self.program.new_value().global_alloc(init);
+        self.program.set_value_name(alloc, Some(format!("{}{}", STRING_PREFIX, self.strings.len())));
+        self.strings.insert(bytes.to_vec(), alloc);
+        alloc
+    }
+
+    // the `i32`s an element of a field takes.
+    pub fn element_size(&self, element: &Element) -> i32 {
+        match element {
+            Element::Basic(_) => 1,
+            Element::Struct(name) => self.structs[name].size,
+        }
+    }
+
+    // not inside any function, declarations are global.
+    pub fn is_global(&self) -> bool {
+        self.func.is_none()
+    }
+
+    pub fn enter_function(&mut self, func: Function) {
+        self.func = Some(func);
+    }
+
+    pub fn exit_function(&mut self) {
+        self.func = None;
+        self.bb = None;
+    }
+
+    pub fn func_data(&mut self) -> &mut FunctionData {
+        self.program.func_mut(self.func.unwrap())
+    }
+
+    // builder of a local value in the current function.
+    pub fn new_value(&mut self) -> LocalBuilder<'_> {
+        self.func_data().dfg_mut().new_value()
+    }
+
+    // the type of a local or a global value.
+    pub fn value_type(&mut self, value: Value) -> Type {
+        if value.is_global() {
+            self.program.borrow_value(value).ty().clone()
+        } else {
+            self.func_data().dfg().value(value).ty().clone()
+        }
+    }
+
+    pub fn integer(&mut self, value: i32) -> Value {
+        if self.is_global() {
+            self.program.new_value().integer(value)
+        } else {
+            self.new_value().integer(value)
+        }
+    }
+
+    pub fn aggregate(&mut self, elems: Vec<Value>) -> Value {
+        if self.is_global() {
+            self.program.new_value().aggregate(elems)
+        } else {
+            self.new_value().aggregate(elems)
+        }
+    }
+
+    pub fn zero_init(&mut self, ty: Type) -> Value {
+        if self.is_global() {
+            self.program.new_value().zero_init(ty)
+        } else {
+            self.new_value().zero_init(ty)
+        }
+    }
+
+    // `name` must start with `%`, koopa makes it unique when printing.
+    pub fn new_bb(&mut self, name: &str) -> BasicBlock {
+        self.func_data().dfg_mut().new_bb().basic_block(Some(name.to_string()))
+    }
+
+    // drop a block that turned out to be unused, it was never appended.
+    pub fn remove_bb(&mut self, bb: BasicBlock) {
+        self.func_data().dfg_mut().remove_bb(bb);
+    }
+
+    // append the block to the function, later instructions go there.
+    pub fn set_bb(&mut self, bb: BasicBlock) {
+        self.func_data().layout_mut().bbs_mut().push_key_back(bb).unwrap();
+        self.bb = Some(bb);
+    }
+
+    // the block instructions go to now.
+    pub fn current_bb(&self) -> BasicBlock {
+        self.bb.unwrap()
+    }
+
+    // go back to a block appended before, to finish it.
+    pub fn resume_bb(&mut self, bb: BasicBlock) {
+        self.bb = Some(bb);
+    }
+
+    pub fn push_inst(&mut self, inst: Value) {
+        let bb = self.bb.unwrap();
+        self.func_data().layout_mut().bb_mut(bb).insts_mut().push_key_back(inst).unwrap();
+    }
+
+    // allocate at the beginning of the entry block, it dominates every use in the function.
+    pub fn alloc_in_entry(&mut self, ty: Type) -> Value {
+        let alloc = self.new_value().alloc(ty);
+        let entry = self.func_data().layout().entry_bb().unwrap();
+        self.func_data().layout_mut().bb_mut(entry).insts_mut().push_key_front(alloc).unwrap();
+        alloc
+    }
+
+    pub fn jump(&mut self, target: BasicBlock) {
+        let jump = self.new_value().jump(target);
+        self.push_inst(jump);
+    }
+
+    pub fn branch(&mut self, cond: Value, true_bb: BasicBlock, false_bb: BasicBlock) {
+        let branch = self.new_value().branch(cond, true_bb, false_bb);
+        self.push_inst(branch);
+    }
+
+    // `name` must start with `@` or `%`, koopa makes it unique when printing.
+    pub fn set_value_name(&mut self, value: Value, name: &str) {
+        if self.is_global() {
+            self.program.set_value_name(value, Some(name.to_string()));
+        } else {
+            self.func_data().dfg_mut().set_value_name(value, Some(name.to_string()));
+        }
+    }
+}
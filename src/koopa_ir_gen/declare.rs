@@ -1,277 +1,305 @@
+use koopa::ir::Value;
+use koopa::ir::builder_traits::*;
+
 use crate::ast::*;
 
 
 // use super::ret_types::*;
-use std::collections::HashMap;
-use crate::koopa_ir_gen::initialvalue::InitValue;
+use crate::koopa_ir_gen::initialvalue::eval_initializer;
+use crate::koopa_ir_gen::expression::{null_pointer, ExpResult};
 use crate::koopa_ir_gen::{*};
+use crate::semantic::{Element, StructLayout};
 use super::ret_types::InitRetType;
 
 /*
  * decl, 声明变量.
  * 例如数组, integer变量等.
- * 这里对于global和non-global还得分开处理...
+ * 这里对于global和non-global还得分开处理, 用 ctx.is_global() 区分.
  */
 
 pub trait DeclResult {
-    fn eval(&self, scope: &mut HashMap<String, (i32, i32)>, size: i32, is_global: bool) -> DeclRetType;
+    fn eval(&self, ctx: &mut Context);
 }
 
 
 impl DeclResult for BlockItem {
-    fn eval(&self, scope: &mut HashMap<String, (i32, i32)>, size: i32, is_global: bool) -> DeclRetType {
+    fn eval(&self, ctx: &mut Context) {
         // BlockItem ::= Decl | Stmt;
         match self {
-            BlockItem::Statement(stmt) => {
-                let statement = stmt.eval(&scope, size);
-                return DeclRetType {
-                    size: statement.size,
-                    program: statement.program,
-                };
-            },
-            BlockItem::Decl(decl) => {
-                let decl_ret_val = decl.eval(scope, size, is_global);
-                return decl_ret_val;
-            }
+            BlockItem::Statement(stmt) => stmt.eval(ctx),
+            BlockItem::Decl(decl) => decl.eval(ctx),
         }
     }
 }
 
 // Decl ::= ConstDecl | VarDecl;
 impl DeclResult for Decl {
-    fn eval(&self, scope: &mut HashMap<String, (i32, i32)>, size: i32, is_global: bool) -> DeclRetType {
+    fn eval(&self, ctx: &mut Context) {
         match self {
-            Decl::Constdecl(constdecl) => {
-                let ret_val = constdecl.eval(scope, size, is_global);
-                return ret_val;
-            },
-            Decl::Vardecl(vardecl) => {
-                let ret_val = vardecl.eval(scope, size, is_global);
-                return ret_val;
-            },
+            Decl::Constdecl(constdecl) => constdecl.eval(ctx),
+            Decl::Vardecl(vardecl) => vardecl.eval(ctx),
+            Decl::Structdecl(structdecl) => structdecl.eval(ctx),
+            Decl::Error() => panic!("Syntax error reached the generator."),
         }
     }
 }
 
 // ConstDecl ::= "const" BType ConstDef {"," ConstDef} ";";
 impl DeclResult for ConstDecl {
-    fn eval(&self, scope: &mut HashMap<String, (i32, i32)>, size: i32, is_global: bool) -> DeclRetType {
-        let mut size = size;
-        let mut program = "".to_string();
-
+    fn eval(&self, ctx: &mut Context) {
         for def in &self.constdefs {
-            let ret_val = def.eval(scope, size, is_global);
-            program.push_str(&ret_val.program);
-            size = ret_val.size;
+            def.eval(self.btype, ctx);
         }
-        return DeclRetType{size, program};
     }
 }
 
 // VarDecl ::= BType VarDef {"," VarDef} ";";
 impl DeclResult for VarDecl {
-    fn eval(&self, scope: &mut HashMap<String, (i32, i32)>, size: i32, is_global: bool) -> DeclRetType {
-        let mut size = size;
-        let mut program = "".to_string();
-
+    fn eval(&self, ctx: &mut Context) {
         for def in &self.vardefs {
-            let ret_val = def.eval(scope, size, is_global);
-            program.push_str(&ret_val.program);
-            size = ret_val.size;
+            def.eval(self.btype, ctx);
         }
-        return DeclRetType{size, program};
     }
 }
 
+// StructDef ::= "struct" IDENT "{" FieldDecl {FieldDecl} "}" ";"
+// only the layout, koopa has no struct type. the dimensions may use the constants before.
+impl DeclResult for StructDef {
+    fn eval(&self, ctx: &mut Context) {
+        let mut layout = StructLayout::default();
+        for field in &self.fields {
+            let dims = evaluate_dimension(&field.dims, ctx);
+            let element = match &field.ty {
+                FieldType::Basic(btype) => Element::Basic(*btype),
+                FieldType::Struct(name, _) => Element::Struct(name.clone()),
+            };
+            let size = ctx.element_size(&element);
+            layout.push(&field.ident, element, dims, size);
+        }
+        ctx.structs.insert(self.ident.clone(), layout);
+    }
+}
 
+// StructDecl ::= "struct" IDENT VarDef {"," VarDef} ";"
+// a struct is `[i32, size]`, an array of them is an array of that. global ones are zero initialized.
+//     @s = alloc [i32, 5]
+//     @arr = alloc [[i32, 5], 10]
+impl DeclResult for StructDecl {
+    fn eval(&self, ctx: &mut Context) {
+        let size = ctx.structs[&self.ident].size;
+        for def in &self.vardefs {
+            let VarDef::Ident(ident, dims, _) = def else {
+                panic!("Struct initializer reached the generator.");
+            };
+            let dims = evaluate_dimension(dims, ctx);
+            let ty = array_of(koopa::ir::Type::get_array(koopa::ir::Type::get_i32(), size as usize), &dims);
+            let alloc = allocate(ctx, ident, ty, None);
+            ctx.scope.insert(ident, Symbol::Struct(alloc, self.ident.clone(), dims));
+        }
+    }
+}
 
 
 // calculate a list of `ConstExp`.
-pub fn evaluate_dimension(size: &mut i32, exps: & Vec<ConstExp>, scope: &HashMap<String, (i32, i32)>) -> (Vec<i32>, String) {
+pub fn evaluate_dimension(exps: &Vec<ConstExp>, ctx: &mut Context) -> Vec<i32> {
     let mut dims = Vec::new();
-    let mut is_first = true;
-    let mut program = "".to_string();
 
     for const_exp in exps {
-        let ret_val = const_exp.eval(scope, *size);
-        *size = ret_val.size;
+        let ret_val = const_exp.eval(ctx);
 
-        assert!(ret_val.val.len() == 1);
-        assert!(ret_val.val[0].0); // must be constant.
-        dims.push(ret_val.val[0].1); // add length of this dimension.
-    }
-    for x in dims.iter().rev() { // the order is reversed.
-        if is_first {
-            program = format!("[i32, {}]", x);
-        } else {
-            program = format!("[{}, {}]", program, x);
-        }
-        is_first = false;
+        assert!(ret_val.is_constant); // must be constant.
+        dims.push(ret_val.exp_res_id); // add length of this dimension.
     }
-    (dims, program)
+    dims
 }
 
 
 
 // 吧这个东西铺开... {{}, {}, {}} ...
-fn get_const_init_value_str(p: &InitRetType, dims: &Vec<i32>) -> String {
+// 运行时才知道的元素先当作 0, 之后由 `store_dynamic` 单独 store. global 的元素一定是常量.
+fn init_value(ctx: &mut Context, p: &InitRetType, dims: &[i32]) -> Value {
     if p.is_allzero {
-        return "zeroinit".to_string();
+        return ctx.zero_init(array_type(dims));
     }
 
-    fn dfs(i: usize, l: usize, r: usize, p: &InitRetType, dims: &Vec<i32>) -> String {
-        // println!("index: {}, left: {}, right: {}\n", i, l, r);
+    fn dfs(ctx: &mut Context, i: usize, l: usize, r: usize, p: &InitRetType, dims: &[i32]) -> Value {
         if l == r {
-            assert!(p.val[l].0);
-            return format!("{}", p.val[l].1);
+            assert!(p.val[l].is_constant || !ctx.is_global());
+            let val = if p.val[l].is_constant { p.val[l].exp_res_id } else { 0 };
+            return ctx.integer(val);
         }
         let len = (r - l + 1) / (dims[i] as usize);
-        let mut program = "".to_string();
-        let mut is_first = true;
+        let mut elems = Vec::new();
         for j in 0..dims[i] {
-            let x = dfs(i + 1, l + (j as usize) * len, l + (j as usize) * len + len - 1, p, dims);
-            if is_first {
-                program.push('{');
-                program.push_str(&format!("{}", x));
-                is_first = false;
-            } else {
-                program.push_str(&format!(", {}", x));
-            }
+            let x = dfs(ctx, i + 1, l + (j as usize) * len, l + (j as usize) * len + len - 1, p, dims);
+            elems.push(x);
         }
-        program.push('}');
 
-        program
+        ctx.aggregate(elems)
     }
 
-    dfs(0, 0, p.val.len() - 1, p, dims)
+    dfs(ctx, 0, 0, p.val.len() - 1, p, dims)
 }
 
 
-// ConstDef ::= IDENT {"[" ConstExp "]"} "=" ConstInitVal
-impl DeclResult for ConstDef {
-    fn eval(&self, scope: &mut HashMap<String, (i32, i32)>, size: i32, is_global: bool) -> DeclRetType {
-        let mut size = size;
-        let dim_pair = evaluate_dimension(&mut size, &self.dims, scope);
-        let dims = dim_pair.0;
-        let dim_str = dim_pair.1;
+// local array: store every element known only at run time, in source order.
+//     %ptr1 = getelemptr @arr, 1
+//     %ptr2 = getelemptr %ptr1, 2
+//     store %x, %ptr2
+fn store_dynamic(ctx: &mut Context, alloc: Value, p: &InitRetType, dims: &[i32]) {
+    for (pos, val) in p.val.iter().enumerate() {
+        if val.is_constant {
+            continue;
+        }
+        let mut ptr = alloc;
+        let mut len = p.val.len();
+        for dim in dims {
+            len /= *dim as usize;
+            let index = ctx.integer((pos / len % *dim as usize) as i32);
+            ptr = ctx.new_value().get_elem_ptr(ptr, index);
+            ctx.push_inst(ptr);
+        }
+        let store = ctx.new_value().store(val.value.unwrap(), ptr);
+        ctx.push_inst(store);
+    }
+}
+
+
+// 分配变量的空间并命名为 `@ident`.
+// global 的初始值直接写在 alloc 里, 没有的话就是 zeroinit.
+// local 的 alloc 放在 entry block 的开头, store 留在声明的地方: `switch` 的 case 可以跳过声明, 后面的 case 照样能用这个变量.
+fn allocate(ctx: &mut Context, ident: &str, ty: koopa::ir::Type, init: Option<Value>) -> Value {
+    let alloc = if ctx.is_global() {
+        // global @x = alloc [i32, 2], {10, 20}
+        let init = match init {
+            Some(init) => init,
+            None => ctx.zero_init(ty),
+        };
+        ctx.program.new_value().global_alloc(init)
+    } else {
+        // @arr = alloc [i32, 5]        (in %entry)
+        // store {1, 2, 3, 0, 0}, @arr
+        let alloc = ctx.alloc_in_entry(ty);
+        if let Some(init) = init {
+            let store = ctx.new_value().store(init, alloc);
+            ctx.push_inst(store);
+        }
+        alloc
+    };
+    ctx.set_value_name(alloc, &format!("@{}", ident));
+    alloc
+}
 
-        let ret_val = self.constinitval.eval(scope, size, &dims[0..dims.len()]);
-        let size = ret_val.size;
 
-        if self.dims.len() == 0 { // `int` variable.
+// ConstDef ::= IDENT {"[" ConstExp "]"} "=" ConstInitVal
+impl ConstDef {
+    fn eval(&self, btype: BType, ctx: &mut Context) {
+        let dims = evaluate_dimension(&self.dims, ctx);
+        let ret_val = eval_initializer(&self.constinitval, ctx, &dims, btype == BType::Float);
+
+        if self.dims.is_empty() { // `int` or `float` variable.
             assert!(ret_val.val.len() == 1);
             // the constant's value is the expression.
-            scope.insert(format!("{}", self.ident), (CONSTANT_INT, ret_val.val[0].1));
-            return DeclRetType {size, program: ret_val.program};
+            ctx.scope.insert(&self.ident, Symbol::Const(btype, ret_val.val[0].exp_res_id));
+            return;
         }
 
         // array.
-        // array_init(&dims, is_global);
-
         // @arr = alloc [[i32, 3], 2]    // @arr 的类型是 *[[i32, 3], 2]
         // %ptr1 = getelemptr @arr, 1    // %ptr1 的类型是 *[i32, 3]
         // %ptr2 = getelemptr %ptr1, 2   // %ptr2 的类型是 *i32
         // %value = load %ptr2           // %value 的类型是 i32
-        let mut program = "".to_string();
-        let init_value_str = get_const_init_value_str(&ret_val, &dims);
-
-        // wrong???? 把常量数组当作变量数组.
-        scope.insert(format!("{}", self.ident), (VARIABLE_ARRAY, size + 1));
-        if is_global {
-            // global @x = alloc [i32, 2], {10, 20}
-            program.push_str(&format!("global @var_{} = alloc {}, {}\n", size + 1, &dim_str, init_value_str));
-        } else {
-            // @arr = alloc [i32, 5]
-            // store {1, 2, 3, 0, 0}, @arr
-            program.push_str(&format!("    @var_{} = alloc {}\n", size + 1, &dim_str));
-            program.push_str(&format!("    store {}, @var_{}\n", init_value_str, size + 1));
-        }
-        return DeclRetType {size: size + 1, program: program};
+        let init = init_value(ctx, &ret_val, &dims);
+
+        // 常量数组也要分配空间, 下标不是常量的时候还是 load. 下标都是常量就直接读 values.
+        let alloc = allocate(ctx, &self.ident, array_type(&dims), Some(init));
+        let values = ret_val.val.iter().map(|x| x.exp_res_id).collect();
+        ctx.scope.insert(&self.ident, Symbol::ConstArray(alloc, btype, dims, values));
     }
 }
 
 // VarDef ::= IDENT {"[" ConstExp "]"}
 //          | IDENT {"[" ConstExp "]"} "=" InitVal
-impl DeclResult for VarDef {
-    fn eval(&self, scope: &mut HashMap<String, (i32, i32)>, size: i32, is_global: bool) -> DeclRetType {
-        let mut size = size;
-        let mut program = "".to_string();
-
+//          | "*" IDENT ["=" Exp]
+impl VarDef {
+    fn eval(&self, btype: BType, ctx: &mut Context) {
+        let is_float = btype == BType::Float;
         match self {
-            VarDef::Ident(ident, dims) => {
-                let dim_pair = evaluate_dimension(&mut size, dims, scope);
-                let dims = dim_pair.0;
-                let dim_str = dim_pair.1;
-                
-                if dims.len() == 0 { // `int` variable.
-                    // define.
-                    scope.insert(format!("{}", ident), (VARIABLE_INT, size + 1));
-                    if !is_global {
-                        // @x = alloc i32
-                        program.push_str(&format!("    @var_{} = alloc i32\n", size + 1)); // currently only i32.
+            // a global pointer starts as null, it can only be initialized with `0`.
+            // a local one is uninitialized unless it has an initial value.
+            //     @p = alloc *i32
+            //     store %0, @p
+            VarDef::Pointer(ident, exp, _) => {
+                let ty = koopa::ir::Type::get_pointer(koopa::ir::Type::get_i32());
+                let init = exp.as_ref().filter(|_| !ctx.is_global()).map(|exp| {
+                    let ret_val = exp.eval(ctx);
+                    if ret_val.is_constant {
+                        null_pointer(ctx, ty.clone())
                     } else {
-                        // global @var = alloc i32, zeroinit
-                        program.push_str(&format!("global @var_{} = alloc i32, zeroinit\n", size + 1)); // currently only i32.
+                        get_value(ctx, &ret_val)
                     }
-                    return DeclRetType {size: size + 1, program};
-                }
+                });
+                let alloc = allocate(ctx, ident, ty, init);
+                ctx.scope.insert(ident, Symbol::Pointer(alloc, btype, vec![]));
+            },
+            VarDef::Ident(ident, dims, _) => {
+                let dims = evaluate_dimension(dims, ctx);
 
-                scope.insert(format!("{}", ident), (VARIABLE_ARRAY, size + 1));
-                // array.
-                if is_global {
-                    // global @x = alloc [i32, 2], {10, 20}
-                    program.push_str(&format!("global @var_{} = alloc {}, zeroinit\n", size + 1, &dim_str));
+                // define. global ones are zero initialized.
+                let alloc = allocate(ctx, ident, array_type(&dims), None);
+                if dims.is_empty() { // `int` or `float` variable.
+                    ctx.scope.insert(ident, Symbol::Var(alloc, btype));
                 } else {
-                    // @arr = alloc [i32, 5]
-                    // store {1, 2, 3, 0, 0}, @arr
-                    program.push_str(&format!("    @var_{} = alloc {}\n", size + 1, &dim_str));
+                    ctx.scope.insert(ident, Symbol::Array(alloc, btype, dims));
                 }
-                return DeclRetType {size: size + 1, program};
             },
 
-            VarDef::Identinitval(ident, dims, initval) => {
-                let dim_pair = evaluate_dimension(&mut size, dims, scope);
-                let dims = dim_pair.0;
-                let dim_str = dim_pair.1;
+            VarDef::Identinitval(ident, dims, initval, _) => {
+                let dims = evaluate_dimension(dims, ctx);
 
-                if dims.len() == 0 {
-                    let ret_val = initval.eval(scope, size, &[1]);
-                    size = ret_val.size + 1;
+                if dims.is_empty() {
+                    let ret_val = eval_initializer(initval, ctx, &[], is_float);
                     assert!(ret_val.val.len() == 1);
-                    let name = get_name(ret_val.val[0].1, ret_val.val[0].0);
-    
-                    if !is_global {
-                        program.push_str(&ret_val.program);
-                        // define.
-                        scope.insert(format!("{}", ident), (VARIABLE_INT, size + 1));
-                        // @x = alloc i32
-                        program.push_str(&format!("    @var_{} = alloc i32\n", size + 1)); // currently only i32.
-                        // assignment: store %1, @x
-                        program.push_str(&format!("    store {}, @var_{}\n", name, size + 1)); // currently only i32.
-                    } else { // global的初始值必须是constant.
-                        assert!(ret_val.val[0].0 == true); // must be constant.
-                        // define.
-                        scope.insert(format!("{}", ident), (VARIABLE_INT, size + 1));
-                        // @x = alloc i32
-                        program.push_str(&format!("global @var_{} = alloc i32, {}\n", size + 1, ret_val.val[0].1)); // currently only i32.
+                    if ctx.is_global() { // global的初始值必须是constant.
+                        assert!(ret_val.val[0].is_constant); // must be constant.
                     }
-                    return DeclRetType {size: size + 1, program};
+                    let init = get_value(ctx, &ret_val.val[0]);
+
+                    // define after the initial value, `int x = x;` reads the outer `x`.
+                    let alloc = allocate(ctx, ident, array_type(&dims), Some(init));
+                    ctx.scope.insert(ident, Symbol::Var(alloc, btype));
+                    return;
                 }
 
-                // array.
-                let ret_val = initval.eval(scope, size, &dims);
-                let init_value_str = get_const_init_value_str(&ret_val, &dims);
+                // array. the elements are evaluated in source order before the alloc.
+                let ret_val = eval_initializer(initval, ctx, &dims, is_float);
+                let init = init_value(ctx, &ret_val, &dims);
 
-                scope.insert(format!("{}", ident), (VARIABLE_ARRAY, size + 1));
-                if is_global {
-                    program.push_str(&format!("global @var_{} = alloc {}, {}\n", size + 1, &dim_str, init_value_str));
-                } else {
-                    program.push_str(&format!("    @var_{} = alloc {}\n", size + 1, &dim_str));
-                    program.push_str(&format!("    store {}, @var_{}\n", init_value_str, size + 1));
+                let alloc = allocate(ctx, ident, array_type(&dims), Some(init));
+                if !ret_val.is_allzero {
+                    store_dynamic(ctx, alloc, &ret_val, &dims);
                 }
-
-                return DeclRetType {size: size + 1, program};
+                ctx.scope.insert(ident, Symbol::Array(alloc, btype, dims));
             },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::semantic::Extensions;
+    use crate::testing::{koopa, run};
+
+    #[test]
+    fn run_time_elements_are_stored_after_the_constants() {
+        let text = "int f(int x) {\n  putint(x);\n  return getint();\n}\nint main() {\n\
+            int a[2][3] = {{f(1), 2}, {3, f(2)}};\n  return a[0][0] * 100 + a[1][1] + a[0][1] * 1000 + a[1][2];\n}\n";
+        // the elements are evaluated in source order, each one lands in its place, the rest are zero.
+        assert_eq!(run(text, Extensions::default(), &[7, 9]), (2709, "12".to_string()));
+        let ir = koopa(text, Extensions::default());
+        let stores = ["%2 = call @f(1)", "%3 = call @f(2)", "store {{0, 2, 0}, {3, 0, 0}}, @a",
+            "%4 = getelemptr @a, 0", "%5 = getelemptr %4, 0", "store %2, %5",
+            "%6 = getelemptr @a, 1", "%7 = getelemptr %6, 1", "store %3, %7"];
+        assert!(ir.contains(&stores.map(|inst| format!("  {}\n", inst)).concat()), "{}", ir);
+    }
+}
@@ -1,154 +1,312 @@
-use std::collections::HashMap;
+use koopa::ir::BinaryOp;
+use koopa::ir::builder_traits::*;
+
+use super::condition::CondResult;
+use super::context::Context;
+use super::symbol_table::Symbol;
 
 use crate::ast::*;
 use crate::koopa_ir_gen::{*};
+use crate::semantic::{fold, fold_float, variadic_slot, Element, Field, TIMERS};
 // how to maintain the expression result?
-// 1. assign unique id to node for unique variable name.
-// 2. attach a `ret` to struct store the result ID.
+// 1. every instruction is a koopa value, no name is needed.
+// 2. attach a `ret` to struct store the result value.
 
 /*
  * 这里应该非常straight forward没有什么难度.
- * 主要是处理指针, 由于我们scope里面维护了var的类型, 枚举各种情况作对应的操作即可.
- * 
+ * 主要是处理指针: LVal 求值得到的是地址, 用到它的值的时候看地址指向的 koopa 类型,
+ * 指向数组就退化成指向第一个元素的指针, 否则 load 出来 (`int`, `float` 或者一个指针变量里存的地址).
+ * `int` 和 `float` 之间的隐式转换由 `convert` 完成, 规则和 semantic 的检查一致.
+ * 指针的 `is_float` 总是 false, 它的元素是不是 `float` 由 `FloatType` 决定.
+ * 常量 0 存到指针里或者传给指针参数的时候是空指针, 指针当作条件的时候比较的是它的地址.
+ * struct 是 `[i32, size]`, `s.t.a[i]` 把一路的偏移加起来, 最后只有一条 `getelemptr`.
  */
 pub trait ExpResult {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size: i32, is_pt: bool) -> ExpRetType;
+    fn eval(&self, ctx: &mut Context) -> ExpRetType;
+}
+
+// convert to `float` or to `int`, `float` to `int` truncates.
+//     %1 = call @__sysy_itof(%0)
+pub fn convert(ctx: &mut Context, val: &ExpRetType, to_float: bool) -> ExpRetType {
+    if val.is_float == to_float {
+        return *val;
+    }
+    if val.is_constant {
+        return if to_float {
+            ExpRetType::float_constant(val.exp_res_id as f32)
+        } else {
+            ExpRetType::constant(val.as_f32() as i32)
+        };
+    }
+    let value = val.value.unwrap();
+    if to_float {
+        ExpRetType::float_value(ctx.call_intrinsic("itof", vec![value]))
+    } else {
+        ExpRetType::value(ctx.call_intrinsic("ftoi", vec![value]))
+    }
+}
+
+// a `float` used as a condition, `x != 0.0`. -0.0 is false as well, so the bits can't be tested directly.
+pub fn float_to_bool(ctx: &mut Context, val: &ExpRetType) -> ExpRetType {
+    if val.is_constant {
+        return ExpRetType::constant((val.as_f32() != 0.0) as i32);
+    }
+    let zero = ctx.integer(0);
+    ExpRetType::value(ctx.call_intrinsic("fne", vec![val.value.unwrap(), zero]))
+}
+
+// a pointer used as a condition or with `!`, the address is tested. the others are unchanged.
+pub fn pointer_to_bool(ctx: &mut Context, val: &ExpRetType) -> ExpRetType {
+    if !is_pointer(ctx, val) {
+        return *val;
+    }
+    ExpRetType::value(pointer_to_int(ctx, val.value.unwrap()))
+}
+
+// the null pointer of koopa type `ty`. koopa only has it as an initializer, it goes through a slot of its own.
+//     store zeroinit, %null
+//     %1 = load %null
+pub fn null_pointer(ctx: &mut Context, ty: koopa::ir::Type) -> Value {
+    let slot = ctx.alloc_in_entry(ty.clone());
+    ctx.set_value_name(slot, "%null");
+    let zero = ctx.new_value().zero_init(ty);
+    let store = ctx.new_value().store(zero, slot);
+    ctx.push_inst(store);
+    let val = ctx.new_value().load(slot);
+    ctx.push_inst(val);
+    val
+}
+
+// the koopa type a pointer points to.
+pub fn pointee(ctx: &mut Context, ptr: Value) -> koopa::ir::Type {
+    match ctx.value_type(ptr).kind() {
+        koopa::ir::TypeKind::Pointer(base) => base.clone(),
+        _ => panic!("Not a pointer."),
+    }
+}
+
+// the value stored at an address, an array decays to the pointer to its first element.
+//     %1 = getelemptr %0, 0    // %0 的类型是 *[i32, 3]
+//     %1 = load %0             // %0 的类型是 *i32 或者 **i32
+fn load_or_decay(ctx: &mut Context, pos: Value) -> Value {
+    let val = if let koopa::ir::TypeKind::Array(_, _) = pointee(ctx, pos).kind() {
+        let zero = ctx.integer(0);
+        ctx.new_value().get_elem_ptr(pos, zero)
+    } else {
+        ctx.new_value().load(pos)
+    };
+    ctx.push_inst(val);
+    val
+}
+
+// the struct an lval is, `s`, `arr[i]` or `s.t[j]`.
+fn struct_name(lval: &LVal, ctx: &Context) -> String {
+    match lval {
+        LVal::Ident(ident, _, _) => match ctx.scope.get(ident) {
+            Symbol::Struct(_, name, _) => name.clone(),
+            _ => panic!("Not a struct."),
+        },
+        LVal::Member(base, name, _, _) => match field_of(base, name, ctx).element {
+            Element::Struct(name) => name,
+            Element::Basic(_) => panic!("Not a struct."),
+        },
+        LVal::Deref(_, _) => panic!("Not a struct."),
+    }
 }
 
+// the field `name` of the struct `base`.
+fn field_of(base: &LVal, name: &str, ctx: &Context) -> Field {
+    ctx.structs[&struct_name(base, ctx)].field(name).unwrap().clone()
+}
+
+// the address of the outermost struct, and where `lval` starts in it, in `i32`s.
+//     %0 = getelemptr @arr, %i    // *[i32, 5]
+//     %1 = mul %j, 2
+//     %2 = add 1, %1
+fn struct_position(lval: &LVal, ctx: &mut Context) -> (Value, ExpRetType) {
+    let LVal::Member(base, name, exps, _) = lval else {
+        let ret_val = lval.eval(ctx);
+        return (ret_val.value.unwrap(), ExpRetType::constant(0));
+    };
+    let field = field_of(base, name, ctx);
+    let element_size = ctx.element_size(&field.element);
+    let (pos, offset) = struct_position(base, ctx);
+    let mut offset = binary_operation(ctx, "add", &offset, &ExpRetType::constant(field.offset));
+    for (k, exp) in exps.iter().enumerate() {
+        let index = exp.eval(ctx);
+        let step = binary_operation(ctx, "mul", &index, &ExpRetType::constant(field.stride(k, element_size)));
+        offset = binary_operation(ctx, "add", &offset, &step);
+    }
+    (pos, offset)
+}
+
+impl LVal {
+    // a one-dimensional field without its subscript, the address is the pointer to the first element already.
+    fn is_array_field(&self, ctx: &Context) -> bool {
+        match self {
+            LVal::Member(base, name, exps, _) => exps.len() < field_of(base, name, ctx).dims.len(),
+            _ => false,
+        }
+    }
+}
 
 // --------------------------------------- lv3 ------------------------------------------------
 // 参数的赋值, 可能为数组的某一个值. 枚举情况处理, 没什么好说的.
-// LVal          ::= IDENT {"[" Exp "]"};
+// 返回的是地址, 要值的话还需要 load.
+// LVal          ::= IDENT {"[" Exp "]"} | "*" UnaryExp | LVal "." IDENT {"[" Exp "]"};
 impl ExpResult for LVal {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size: i32, _is_pt: bool) -> ExpRetType {
-        let mut size = size;
-        let mut program = String::from("");
-
-        let var = scope.get(&format!("{}", self.ident)).unwrap();
-
-        if var.0 == CONSTANT_INT { // constant variable.
-            return ExpRetType {
-                size: size,
-                program: program,
-                exp_res_id: var.1,
-                is_constant: true,
-            };
-        }
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
+        let (ident, exps) = match self {
+            LVal::Ident(ident, exps, _) => (ident, exps),
+            // the pointer itself is the address.
+            LVal::Deref(exp, _) => {
+                let is_float = exp.is_float(ctx);
+                let ret_val = exp.eval(ctx);
+                let pos = ret_val.value.unwrap();
+                let is_scalar = pointee(ctx, pos) == koopa::ir::Type::get_i32();
+                return ExpRetType { is_float: is_float && is_scalar, ..ExpRetType::value(pos) };
+            },
+            // %3 = getelemptr %0, %2    // *i32
+            LVal::Member(base, name, exps, _) => {
+                let field = field_of(base, name, ctx);
+                let (pos, offset) = struct_position(self, ctx);
+                let index = get_value(ctx, &offset);
+                let ptr = ctx.new_value().get_elem_ptr(pos, index);
+                ctx.push_inst(ptr);
+                let is_float = field.element == Element::Basic(BType::Float) && exps.len() == field.dims.len();
+                return ExpRetType { is_float, ..ExpRetType::value(ptr) };
+            },
+        };
+        let indices: Vec<ExpRetType> = exps.iter().map(|exp| exp.eval(ctx)).collect();
+        let (mut pos, is_pointer, btype, len) = match ctx.scope.get(ident) { // the position.
+            Symbol::Const(btype, value) => { // constant variable.
+                return ExpRetType { is_float: *btype == BType::Float, ..ExpRetType::constant(*value) };
+            },
+            Symbol::Var(alloc, btype) => (*alloc, false, *btype, 0),
+            Symbol::Array(alloc, btype, dims) => {
+                assert!(exps.len() <= dims.len());
+                (*alloc, false, *btype, dims.len())
+            },
+            // an element at constant indices is a constant, the semantic check has done the bounds.
+            Symbol::ConstArray(_, btype, dims, values) if indices.len() == dims.len() && indices.iter().all(|x| x.is_constant) => {
+                let flat = indices.iter().zip(dims).fold(0, |flat, (index, len)| flat * len + index.exp_res_id);
+                return ExpRetType { is_float: *btype == BType::Float, ..ExpRetType::constant(values[flat as usize]) };
+            },
+            Symbol::ConstArray(alloc, btype, dims, _) => {
+                assert!(exps.len() <= dims.len());
+                (*alloc, false, *btype, dims.len())
+            },
+            Symbol::Pointer(alloc, btype, dims) => {
+                assert!(exps.len() <= dims.len() + 1); // the first `[]` is not in `dims`.
+                (*alloc, true, *btype, dims.len() + 1)
+            },
+            Symbol::Struct(alloc, _, dims) => {
+                assert!(exps.len() <= dims.len());
+                (*alloc, false, BType::Int, dims.len())
+            },
+            Symbol::Function(_, _) => panic!("Function used as a variable."),
+        };
 
-        // %2 = load @x
-        let mut pos = var.1; // the position.
+        // %ptr1 = getelemptr @arr, 1
         let mut is_first = true;
-        for exp in &self.exps {
-            let ret_val = exp.eval(scope, size, false); // we want the number.
-            size = ret_val.size;
-            program.push_str(&ret_val.program); // code for evaluation.
-
-            let name = get_name(ret_val.exp_res_id, ret_val.is_constant);
-            if var.0 == VARIABLE_ARRAY {
-                if is_first { // `array point` begin with @, but variable begin with `%`.
-                    is_first = false;
-                    program.push_str(&format!("    %var_{} = getelemptr @var_{}, {}\n", size + 1, pos, name));
-                } else {
-                    program.push_str(&format!("    %var_{} = getelemptr %var_{}, {}\n", size + 1, pos, name));
-                }
+        for ret_val in &indices {
+            let index = get_value(ctx, ret_val); // we want the number.
+
+            if is_pointer && is_first { // a **type !
+                // %0 = load %arr
+                let load = ctx.new_value().load(pos);
+                ctx.push_inst(load);
+                pos = ctx.new_value().get_ptr(load, index);
             } else {
-                if is_first { // a **type !
-                    is_first = false;
-                    // %0 = load %arr
-                    size += 1;
-                    program.push_str(&format!("    %var_{} = load @var_{}\n", size, pos));
-                    program.push_str(&format!("    %var_{} = getptr %var_{}, {}\n", size + 1, size, name));
-                } else {
-                    program.push_str(&format!("    %var_{} = getelemptr %var_{}, {}\n", size + 1, pos, name));
-                }
+                pos = ctx.new_value().get_elem_ptr(pos, index);
             }
-            size += 1;
-            pos = size;
+            ctx.push_inst(pos);
+            is_first = false;
         }
 
-        return ExpRetType {
-            size: size,
-            program: program,
-            exp_res_id: pos,
-            is_constant: false,
-        };
+        // the address, `is_float` tells what is stored there. a pointer variable holds an address.
+        let is_float = btype == BType::Float && exps.len() == len;
+        ExpRetType { is_float, ..ExpRetType::value(pos) }
     }
 }
 
-// Exp ::= LOrExp;
+// Exp ::= CondExp;
 impl ExpResult for Exp {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size:i32, is_pt: bool) -> ExpRetType {
-        let ret_val = self.lorexp.eval(scope, size, is_pt);
-        return ret_val;
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
+        self.condexp.eval(ctx)
     }
 }
 
-// PrimaryExp ::= "(" Exp ")" | LVal | Number;
+// ConstExp ::= Exp;
+impl ExpResult for ConstExp {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
+        self.exp.eval(ctx)
+    }
+}
+
+// an argument of `putf` after the format, it takes one `i32` slot, or two for a `float`:
+// like C, a `float` is promoted to `double`, the low half comes first and starts at an even slot.
+//     %1 = call @__sysy_ftod_lo(%0)
+//     %2 = call @__sysy_ftod_hi(%0)
+//     call @putf(%fmt, 0, %1, %2)
+fn variadic_argument(ctx: &mut Context, exp: &Exp, args: &mut Vec<Value>) {
+    let ret_val = exp.eval(ctx);
+    let ty = if ret_val.is_float { Type::Float } else { Type::Int };
+    let (first, _) = variadic_slot(args.len(), &ty);
+    while args.len() < first {
+        let zero = ctx.integer(0);
+        args.push(zero);
+    }
+    if !ret_val.is_float {
+        args.push(get_value(ctx, &ret_val));
+    } else if ret_val.is_constant {
+        let bits = (ret_val.as_f32() as f64).to_bits();
+        let lo = ctx.integer(bits as i32);
+        let hi = ctx.integer((bits >> 32) as i32);
+        args.extend([lo, hi]);
+    } else {
+        let value = ret_val.value.unwrap();
+        let lo = ctx.call_intrinsic("ftod_lo", vec![value]);
+        let hi = ctx.call_intrinsic("ftod_hi", vec![value]);
+        args.extend([lo, hi]);
+    }
+}
+
+// PrimaryExp ::= "(" Exp ")" | LVal | Number | String;
 impl ExpResult for PrimaryExp {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size:i32, is_pt: bool) -> ExpRetType {
-        let mut size = size;
-        let mut program = String::from("");
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
         match self {
             PrimaryExp::Exp(exp) => {
-                let ret_val = (*exp).eval(scope, size, is_pt);
-
-                return ExpRetType {
-                    size: ret_val.size + 1,
-                    program: ret_val.program,
-                    exp_res_id: ret_val.exp_res_id,
-                    is_constant: ret_val.is_constant,
-                };
+                (*exp).eval(ctx)
             },
             // 最基本的情况, 是一个数字.
             PrimaryExp::Num(num) => {
                 // constant, we don't need variable.
-                return ExpRetType {
-                    size: size + 1,
-                    program: program,
-                    exp_res_id: *num,
-                    is_constant: true, // 标记为1.
-                };
-            }
+                ExpRetType::constant(*num)
+            },
+            // only `2147483648` of `-2147483648` passes the check, it wraps to `i32::MIN`.
+            PrimaryExp::BigNum(num, _) => {
+                ExpRetType::constant(*num as i32)
+            },
+            PrimaryExp::FloatNum(num, _) => {
+                ExpRetType::float_constant(*num)
+            },
+            // a pointer to the first byte.
+            //     %0 = getelemptr @__sysy_str_0, 0
+            PrimaryExp::Str(bytes, _) => {
+                let string = ctx.string(bytes);
+                let zero = ctx.integer(0);
+                let ptr = ctx.new_value().get_elem_ptr(string, zero);
+                ctx.push_inst(ptr);
+                ExpRetType::value(ptr)
+            },
             PrimaryExp::Lval(lval) => {
-                let ret_val = lval.eval(scope, size, is_pt);
-                size = ret_val.size;
-                if ret_val.is_constant {
+                let ret_val = lval.eval(ctx);
+                if ret_val.is_constant || lval.is_array_field(ctx) {
                     return ret_val;
-                } else {
-                    program.push_str(&ret_val.program);
-                    if is_pt == false { // if we don't want a pointer.
-                        if lval.exps.len() == 0 {
-                            program.push_str(&format!("    %var_{} = load @var_{}\n", size + 1, ret_val.exp_res_id));
-                        } else {
-                            program.push_str(&format!("    %var_{} = load %var_{}\n", size + 1, ret_val.exp_res_id));
-                        }
-                    } else { // we want a pointer.
-                        // special judge....
-                        let pair = scope.get(&format!("{}", lval.ident)).unwrap();
-                        if pair.0 == VARIABLE_ARRAY {
-                            if lval.exps.len() == 0 {
-                                program.push_str(&format!("    %var_{} = getelemptr @var_{}, 0\n", size + 1, ret_val.exp_res_id));
-                            } else {
-                                program.push_str(&format!("    %var_{} = getelemptr %var_{}, 0\n", size + 1, ret_val.exp_res_id));
-                            }
-                        } else {
-                            assert!(pair.0 == PARAMETER_ARRAY);
-                            if lval.exps.len() == 0 {
-                                size += 1;
-                                program.push_str(&format!("    %var_{} = load @var_{}\n", size, ret_val.exp_res_id));
-                                program.push_str(&format!("    %var_{} = getptr %var_{}, 0\n", size + 1, size));
-                            } else {
-                                program.push_str(&format!("    %var_{} = getelemptr %var_{}, 0\n", size + 1, ret_val.exp_res_id));
-                            }
-                        }
-                    }
                 }
-                return ExpRetType {
-                    size: size + 1,
-                    program: program,
-                    exp_res_id: size + 1,
-                    is_constant: false,
-                };
+                let val = load_or_decay(ctx, ret_val.value.unwrap());
+                ExpRetType { is_float: ret_val.is_float, ..ExpRetType::value(val) }
             }
         }
     }
@@ -157,116 +315,117 @@ impl ExpResult for PrimaryExp {
 // UnaryExp ::= PrimaryExp 
 //            | UnaryOp UnaryExp
 //            | IDENT "(" [FuncRParams] ")"
+//            | "&" LVal
 impl ExpResult for UnaryExp {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size:i32, is_pt: bool) -> ExpRetType {
-        let mut size = size;
-        let mut program = String::from("");
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
         match self {
             UnaryExp::Primaryexp(primaryexp) => {
-                let ret_val = primaryexp.eval(scope, size, is_pt);
-
-                return ExpRetType {
-                    size: ret_val.size + 1,
-                    program: ret_val.program,
-                    exp_res_id: ret_val.exp_res_id,
-                    is_constant: ret_val.is_constant,
-                };
+                primaryexp.eval(ctx)
+            },
+            // the address of the lval, not loaded.
+            UnaryExp::AddrOf(lval, _) => {
+                let ret_val = lval.eval(ctx);
+                ExpRetType::value(ret_val.value.unwrap())
+            },
+            // `starttime()` without `sylib.h`, like the macro it passes the line.
+            //     call @_sysy_starttime(3)
+            UnaryExp::Funcall(ident, None, span) if ctx.timer_lines.contains_key(span) => {
+                let (_, entry) = TIMERS.iter().find(|(timer, _)| timer == ident).unwrap();
+                let func = ctx.scope.get_function(entry).0;
+                let line = ctx.integer(ctx.timer_lines[span]);
+                let call = ctx.new_value().call(func, vec![line]);
+                ctx.push_inst(call);
+                ExpRetType::none()
             },
             // 这里在处理function call.
-            // 先evaluate每个传入的参数, 这里要根据参数的类型, 之后排版调用即可.
-            UnaryExp::Funcall(ident, params) => {
+            // 先evaluate每个传入的参数, 这里要根据参数的类型, 之后调用即可.
+            UnaryExp::Funcall(ident, params, _) => {
                 // %0 = call @half(10)
                 // is it return type `void` or `int`?
-                // println!("query: {}_function\n", &ident);
-                let is_void = scope.get(&format!("{}_function", &ident)).unwrap();
-                let mut bitset = is_void.0 >> TYPE_BITS; // get the function bits.
-
-                let mut params_str = format!("call @{}(", &ident);
-                match params {
-                    None => {},
-                    Some(v) => {
-                        let mut is_first = true;
-                        for exp in &v.params {
-                            let ret_val = exp.eval(scope, size, (bitset & 1) != 0);
-                            let name = get_name(ret_val.exp_res_id, ret_val.is_constant);
-                            size = ret_val.size;
-
-                            // we should first evaluate all the value, then use it.
-                            program.push_str(&ret_val.program);
-                            if is_first {
-                                params_str.push_str(&format!("{}", &name));
-                            } else {
-                                params_str.push_str(&format!(", {}", &name));
-                            }
-                            is_first = false;
-                            bitset >>= 1;
+                let (func, signature) = {
+                    let (func, signature) = ctx.scope.get_function(ident);
+                    (func, signature.clone())
+                };
+
+                let mut args = Vec::new();
+                if let Some(v) = params {
+                    for (i, exp) in v.params.iter().enumerate() {
+                        let ty = match signature.params.get(i) {
+                            Some(ty) => ty,
+                            None => { // after the `...`.
+                                variadic_argument(ctx, exp, &mut args);
+                                continue;
+                            },
+                        };
+                        // arrays are passed as pointers.
+                        let mut ret_val = exp.eval(ctx);
+                        if ty.is_number() {
+                            ret_val = convert(ctx, &ret_val, *ty == Type::Float);
+                        } else if ret_val.is_constant { // `0` passed to a pointer.
+                            let null = null_pointer(ctx, ir_type(ty));
+                            args.push(null);
+                            continue;
                         }
-                    },
+                        // we should first evaluate all the value, then use it.
+                        args.push(get_value(ctx, &ret_val));
+                    }
                 }
-                params_str.push_str(")\n");
-
-                if (is_void.0 & ((1 << TYPE_BITS) - 1)) == VARIABLE_INT { // it is `int`
-                    params_str = format!("    %var_{} = {}", size + 1, params_str);
-                    program.push_str(&params_str);
-                    return ExpRetType {
-                        size: size + 1,
-                        program: program,
-                        exp_res_id: size + 1,
-                        is_constant: false,
+                // the slots the widest call needs are declared, the others are `0`.
+                if signature.variadic {
+                    while args.len() < ctx.float_abi[&func].params.len() {
+                        let zero = ctx.integer(0);
+                        args.push(zero);
                     }
                 }
-                
-                program.push_str(&format!("    {}", &params_str));
-                return ExpRetType {
-                    size: size,
-                    program: program,
-                    exp_res_id: -1,
-                    is_constant: false,
+
+                let call = ctx.new_value().call(func, args);
+                ctx.push_inst(call);
+                match signature.ret {
+                    Type::Int => ExpRetType::value(call),
+                    Type::Float => ExpRetType::float_value(call),
+                    _ => ExpRetType::none(),
                 }
             },
             UnaryExp::Unaryexp(unaryop, unaryexp) => {
-                let ret_val = unaryexp.eval(scope, size, is_pt);
+                let ret_val = unaryexp.eval(ctx);
+                let ret_val = pointer_to_bool(ctx, &ret_val); // only `!` takes a pointer.
+                if ret_val.is_float {
+                    return float_unary(ctx, unaryop, &ret_val);
+                }
 
                 match unaryop {
                     UnaryOp::Add => {
-                        return ExpRetType {
-                            size: ret_val.size + 1,
-                            program: ret_val.program,
-                            exp_res_id: ret_val.exp_res_id,
-                            is_constant: ret_val.is_constant,
-                        };
+                        ret_val
                     },
                     UnaryOp::Sub => {
-                        let size = ret_val.size + 1;
-                        let mut val = -ret_val.exp_res_id;
-                        if !ret_val.is_constant { // constant don't need it.
-                            program.push_str(&ret_val.program);
-                            program.push_str(&format!("    %var_{} = sub 0, %var_{}\n", size, ret_val.exp_res_id));
-                            val = size;
+                        if ret_val.is_constant { // constant don't need it.
+                            return ExpRetType::constant(ret_val.exp_res_id.wrapping_neg());
                         }
-
-                        return ExpRetType {
-                            size: size,
-                            program: program,
-                            exp_res_id: val,
-                            is_constant: ret_val.is_constant,
-                        };
+                        // %1 = sub 0, %0
+                        let zero = ctx.integer(0);
+                        let val = ctx.new_value().binary(BinaryOp::Sub, zero, ret_val.value.unwrap());
+                        ctx.push_inst(val);
+                        ExpRetType::value(val)
                     },
                     UnaryOp::Not => {
-                        let size = ret_val.size + 1;
-                        let mut val = (ret_val.exp_res_id == 0) as i32;
-                        if !ret_val.is_constant { // constant don't need it.
-                            program.push_str(&ret_val.program);
-                            program.push_str(&format!("    %var_{} = eq 0, %var_{}\n", size, ret_val.exp_res_id));
-                            val = size;
+                        if ret_val.is_constant { // constant don't need it.
+                            return ExpRetType::constant((ret_val.exp_res_id == 0) as i32);
                         }
-
-                        return ExpRetType {
-                            size: size,
-                            program: program,
-                            exp_res_id: val,
-                            is_constant: ret_val.is_constant,
-                        };
+                        // %1 = eq 0, %0
+                        let zero = ctx.integer(0);
+                        let val = ctx.new_value().binary(BinaryOp::Eq, zero, ret_val.value.unwrap());
+                        ctx.push_inst(val);
+                        ExpRetType::value(val)
+                    },
+                    UnaryOp::BitNot => {
+                        if ret_val.is_constant {
+                            return ExpRetType::constant(!ret_val.exp_res_id);
+                        }
+                        // %1 = xor %0, -1
+                        let ones = ctx.integer(-1);
+                        let val = ctx.new_value().binary(BinaryOp::Xor, ret_val.value.unwrap(), ones);
+                        ctx.push_inst(val);
+                        ExpRetType::value(val)
                     },
                 }
             },
@@ -277,91 +436,175 @@ impl ExpResult for UnaryExp {
 
 
 
+// `-` flips the sign bit, `!` is `x == 0.0`.
+fn float_unary(ctx: &mut Context, unaryop: &UnaryOp, val: &ExpRetType) -> ExpRetType {
+    match unaryop {
+        UnaryOp::Add => *val,
+        UnaryOp::Sub => {
+            if val.is_constant {
+                return ExpRetType::float_constant(-val.as_f32());
+            }
+            // %1 = xor %0, -2147483648
+            let sign = ctx.integer(i32::MIN);
+            let neg = ctx.new_value().binary(BinaryOp::Xor, val.value.unwrap(), sign);
+            ctx.push_inst(neg);
+            ExpRetType::float_value(neg)
+        },
+        UnaryOp::Not => {
+            if val.is_constant {
+                return ExpRetType::constant((val.as_f32() == 0.0) as i32);
+            }
+            let zero = ctx.integer(0);
+            ExpRetType::value(ctx.call_intrinsic("feq", vec![val.value.unwrap(), zero]))
+        },
+        UnaryOp::BitNot => panic!("`~` on a float reached the generator."),
+    }
+}
+
+
+
 // --------------------------------------------------------------------
 /*
  * 这下面就是普通的一元/二元运算, 二元运算唯一的不同是operator不同, 因此抽象出
  * `binary_operation` 这个函数降低代码复杂度.
  * 同时rust在match中也非常好的支持了match情况的合并, 因此大大增加了代码的可读性.
  */
-fn binary_operation(program: &mut String, size: &mut i32, op: &str, val1: &ExpRetType, val2: &ExpRetType) -> i32 {
-
-    // 太尼玛丑了.
-    let mut val = {
-        if op == "mul" {
-            val1.exp_res_id * val2.exp_res_id
-        } else if op == "div" {
-            val1.exp_res_id / val2.exp_res_id
-        } else if op == "mod" {
-            val1.exp_res_id % val2.exp_res_id
-        } else if op == "add" {
-            val1.exp_res_id + val2.exp_res_id
-        } else if op == "sub" {
-            val1.exp_res_id - val2.exp_res_id
-        } else if op == "eq" {
-            (val1.exp_res_id == val2.exp_res_id) as i32
-        } else if op == "ne" {
-            (val1.exp_res_id != val2.exp_res_id) as i32
-        } else if op == "lt" {
-            (val1.exp_res_id < val2.exp_res_id) as i32
-        } else if op == "gt" {
-            (val1.exp_res_id > val2.exp_res_id) as i32
-        } else if op == "le" {
-            (val1.exp_res_id <= val2.exp_res_id) as i32
-        } else if op == "ge" {
-            (val1.exp_res_id >= val2.exp_res_id) as i32
-        } else {
-            0
-        }
+pub fn binary_operation(ctx: &mut Context, op: &str, val1: &ExpRetType, val2: &ExpRetType) -> ExpRetType {
+    if is_pointer(ctx, val1) || is_pointer(ctx, val2) {
+        return pointer_operation(ctx, op, val1, val2);
+    }
+    if val1.is_float || val2.is_float { // the `int` side is converted first.
+        let val1 = convert(ctx, val1, true);
+        let val2 = convert(ctx, val2, true);
+        return float_operation(ctx, op, &val1, &val2);
+    }
+    // folded the same as the semantic check. a division by zero isn't a constant, it's left to run time,
+    // only reachable in a function: where a constant is required the check reports it.
+    let by_zero = matches!(op, "div" | "mod") && val2.is_constant && val2.exp_res_id == 0;
+    if val1.is_constant && val2.is_constant && !by_zero {
+        return ExpRetType::constant(fold(op, val1.exp_res_id, val2.exp_res_id));
+    }
+
+    let op = match op {
+        "mul" => BinaryOp::Mul,
+        "div" => BinaryOp::Div,
+        "mod" => BinaryOp::Mod,
+        "add" => BinaryOp::Add,
+        "sub" => BinaryOp::Sub,
+        "eq" => BinaryOp::Eq,
+        "ne" => BinaryOp::NotEq,
+        "lt" => BinaryOp::Lt,
+        "gt" => BinaryOp::Gt,
+        "le" => BinaryOp::Le,
+        "ge" => BinaryOp::Ge,
+        "and" => BinaryOp::And,
+        "or" => BinaryOp::Or,
+        "xor" => BinaryOp::Xor,
+        "shl" => BinaryOp::Shl,
+        "sar" => BinaryOp::Sar,
+        _ => panic!("No operator named this."),
     };
+    let lhs = get_value(ctx, val1);
+    let rhs = get_value(ctx, val2);
+    let val = ctx.new_value().binary(op, lhs, rhs);
+    ctx.push_inst(val);
+    ExpRetType::value(val)
+}
+
+fn is_pointer(ctx: &mut Context, val: &ExpRetType) -> bool {
+    match val.value {
+        Some(value) if !val.is_constant => matches!(ctx.value_type(value).kind(), koopa::ir::TypeKind::Pointer(_)),
+        _ => false,
+    }
+}
 
-    if !val1.is_constant || !val2.is_constant {
-        let name1 = get_name(val1.exp_res_id, val1.is_constant);
-        let name2 = get_name(val2.exp_res_id, val2.is_constant);
+// the address of a pointer as an `int`, `*[i32, 3]` is cast to `*i32` first, it's the same address.
+//     %1 = getelemptr %0, 0
+//     %2 = call @__sysy_ptoi(%1)
+fn pointer_to_int(ctx: &mut Context, mut ptr: Value) -> Value {
+    while pointee(ctx, ptr) != koopa::ir::Type::get_i32() {
+        let zero = ctx.integer(0);
+        ptr = ctx.new_value().get_elem_ptr(ptr, zero);
+        ctx.push_inst(ptr);
+    }
+    ctx.call_intrinsic("ptoi", vec![ptr])
+}
 
-        // if any value is constant, then there shouldn't be any code.
-        if !val1.is_constant {
-            program.push_str(&val1.program);
-        }
-        if !val2.is_constant {
-            program.push_str(&val2.program);
-        }
-        program.push_str(&format!("    %var_{} = {} {}, {}\n", size, op, name1, name2));
-        val = *size; // it is not a constant, so return variable id.
+// the pointer extension. `p + i` and `p - i` move by whole elements with `getptr`,
+// `p - q` and the comparisons work on the addresses, `p - q` is in elements too.
+// `&&` and `||` of pointers only get here outside a function, which can't happen.
+//     %1 = getptr %p, %i
+fn pointer_operation(ctx: &mut Context, op: &str, val1: &ExpRetType, val2: &ExpRetType) -> ExpRetType {
+    let (ptr, offset) = match (is_pointer(ctx, val1), is_pointer(ctx, val2)) {
+        (true, true) => {
+            let ptr = val1.value.unwrap();
+            let size = pointee(ctx, ptr).size() as i32;
+            let lhs = pointer_to_int(ctx, ptr);
+            let rhs = pointer_to_int(ctx, val2.value.unwrap());
+            let val = binary_operation(ctx, op, &ExpRetType::value(lhs), &ExpRetType::value(rhs));
+            if op != "sub" {
+                return val;
+            }
+            return binary_operation(ctx, "div", &val, &ExpRetType::constant(size));
+        },
+        // `p == 0` and `p != 0`, the other side is the null pointer.
+        (true, false) | (false, true) if op != "add" && op != "sub" => {
+            let ptr = if val1.is_constant { val2 } else { val1 };
+            let addr = pointer_to_int(ctx, ptr.value.unwrap());
+            return binary_operation(ctx, op, &ExpRetType::value(addr), &ExpRetType::constant(0));
+        },
+        (true, false) => (val1.value.unwrap(), val2),
+        (false, _) => (val2.value.unwrap(), val1),
+    };
+    let offset = if op == "sub" {
+        binary_operation(ctx, "sub", &ExpRetType::constant(0), offset)
+    } else {
+        *offset
+    };
+    let index = get_value(ctx, &offset);
+    let val = ctx.new_value().get_ptr(ptr, index);
+    ctx.push_inst(val);
+    ExpRetType::value(val)
+}
+
+// both are `float`, folded the same as the semantic check.
+//     %2 = call @__sysy_fadd(%0, %1)
+fn float_operation(ctx: &mut Context, op: &str, val1: &ExpRetType, val2: &ExpRetType) -> ExpRetType {
+    if val1.is_constant && val2.is_constant {
+        let (ty, val) = fold_float(op, val1.as_f32(), val2.as_f32());
+        return ExpRetType { is_float: ty == Type::Float, ..ExpRetType::constant(val) };
     }
 
-    val
+    let (name, is_float) = match op {
+        "mul" => ("fmul", true),
+        "div" => ("fdiv", true),
+        "add" => ("fadd", true),
+        "sub" => ("fsub", true),
+        "eq" => ("feq", false),
+        "ne" => ("fne", false),
+        "lt" => ("flt", false),
+        "gt" => ("fgt", false),
+        "le" => ("fle", false),
+        "ge" => ("fge", false),
+        _ => panic!("No operator named this."),
+    };
+    let lhs = get_value(ctx, val1);
+    let rhs = get_value(ctx, val2);
+    let val = ctx.call_intrinsic(name, vec![lhs, rhs]);
+    ExpRetType { is_float, ..ExpRetType::value(val) }
 }
 
 // MulExp ::= UnaryExp | MulExp ("*" | "/" | "%") UnaryExp;
 impl ExpResult for MulExp {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size:i32, is_pt: bool) -> ExpRetType {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
         match self {
-            MulExp::Unaryexp(unaryexp) => {
-                let ret_val = unaryexp.eval(scope, size, is_pt); 
-
-                return ExpRetType {
-                    size: ret_val.size + 1,
-                    program: ret_val.program,
-                    exp_res_id: ret_val.exp_res_id,
-                    is_constant: ret_val.is_constant,
-                };
-            },
+            MulExp::Unaryexp(unaryexp) => unaryexp.eval(ctx),
             MulExp::Mulexp(mulexp, unaryexp, op) |
-            MulExp::Divexp(mulexp, unaryexp, op) | 
-            MulExp::Modexp(mulexp, unaryexp, op) => {
-                let ret_val1 = (*mulexp).eval(scope, size, is_pt);
-                let ret_val2 = unaryexp.eval(scope, ret_val1.size, is_pt);
-                let mut size = ret_val2.size + 1;
-                let mut program = String::from("");
-
-                let val = binary_operation(&mut program, &mut size, op, &ret_val1, &ret_val2);
-
-                return ExpRetType {
-                    size: size,
-                    program: program,
-                    exp_res_id: val,
-                    is_constant: ret_val1.is_constant && ret_val2.is_constant,
-                };
+            MulExp::Divexp(mulexp, unaryexp, op, _) | 
+            MulExp::Modexp(mulexp, unaryexp, op, _) => {
+                let ret_val1 = (*mulexp).eval(ctx);
+                let ret_val2 = unaryexp.eval(ctx);
+                binary_operation(ctx, op, &ret_val1, &ret_val2)
             },
         }
     }
@@ -369,69 +612,46 @@ impl ExpResult for MulExp {
 
 // AddExp ::= MulExp | AddExp ("+" | "-") MulExp;
 impl ExpResult for AddExp {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size:i32, is_pt: bool) -> ExpRetType {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
         match self {
-            AddExp::Mulexp(mulexp) => {
-                let ret_val = mulexp.eval(scope, size, is_pt);
-
-                return ExpRetType {
-                    size: ret_val.size + 1,
-                    program: ret_val.program,
-                    exp_res_id: ret_val.exp_res_id,
-                    is_constant: ret_val.is_constant,
-                };
-            },
+            AddExp::Mulexp(mulexp) => mulexp.eval(ctx),
             AddExp::Addexp(addexp, mulexp, op) |
             AddExp::Subexp(addexp, mulexp, op) => {
-                let ret_val1 = (*addexp).eval(scope, size, is_pt);
-                let ret_val2 = (*mulexp).eval(scope, ret_val1.size, is_pt);
-                let mut size = ret_val2.size + 1;
-                let mut program = String::from("");
-
-                let val = binary_operation(&mut program, &mut size, op, &ret_val1, &ret_val2);
-
-                return ExpRetType {
-                    size: size,
-                    program: program,
-                    exp_res_id: val,
-                    is_constant: ret_val1.is_constant && ret_val2.is_constant,
-                };
+                let ret_val1 = (*addexp).eval(ctx);
+                let ret_val2 = (*mulexp).eval(ctx);
+                binary_operation(ctx, op, &ret_val1, &ret_val2)
             },
         }
     }
 }
 
-// RelExp ::= AddExp | RelExp ("<" | ">" | "<=" | ">=") AddExp;
-impl ExpResult for RelExp {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size:i32, is_pt: bool) -> ExpRetType {
+// ShiftExp ::= AddExp | ShiftExp ("<<" | ">>") AddExp;
+impl ExpResult for ShiftExp {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
         match self {
-            RelExp::Addexp(addexp) => {
-                let ret_val = addexp.eval(scope, size, is_pt); 
-
-                return ExpRetType {
-                    size: ret_val.size + 1,
-                    program: ret_val.program,
-                    exp_res_id: ret_val.exp_res_id,
-                    is_constant: ret_val.is_constant,
-                };
+            ShiftExp::Addexp(addexp) => addexp.eval(ctx),
+            ShiftExp::Shlexp(shiftexp, addexp, op) |
+            ShiftExp::Sarexp(shiftexp, addexp, op) => {
+                let ret_val1 = (*shiftexp).eval(ctx);
+                let ret_val2 = addexp.eval(ctx);
+                binary_operation(ctx, op, &ret_val1, &ret_val2)
             },
-            RelExp::Ltexp(relexp, addexp, op) |
-            RelExp::Gtexp(relexp, addexp, op) |
-            RelExp::Geexp(relexp, addexp, op) | 
-            RelExp::Leexp(relexp, addexp, op) => {
-                let ret_val1 = (*relexp).eval(scope, size, is_pt);
-                let ret_val2 = addexp.eval(scope, ret_val1.size, is_pt); 
-                let mut size = ret_val2.size + 1;
-                let mut program = String::from("");
-
-                let val = binary_operation(&mut program, &mut size, op, &ret_val1, &ret_val2);
-
-                return ExpRetType {
-                    size: size,
-                    program: program,
-                    exp_res_id: val,
-                    is_constant: ret_val1.is_constant && ret_val2.is_constant,
-                };
+        }
+    }
+}
+
+// RelExp ::= ShiftExp | RelExp ("<" | ">" | "<=" | ">=") ShiftExp;
+impl ExpResult for RelExp {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
+        match self {
+            RelExp::Shiftexp(shiftexp) => shiftexp.eval(ctx),
+            RelExp::Ltexp(relexp, shiftexp, op) |
+            RelExp::Gtexp(relexp, shiftexp, op) |
+            RelExp::Geexp(relexp, shiftexp, op) | 
+            RelExp::Leexp(relexp, shiftexp, op) => {
+                let ret_val1 = (*relexp).eval(ctx);
+                let ret_val2 = shiftexp.eval(ctx);
+                binary_operation(ctx, op, &ret_val1, &ret_val2)
             }
         }
     }
@@ -439,166 +659,421 @@ impl ExpResult for RelExp {
 
 // EqExp ::= RelExp | EqExp ("==" | "!=") RelExp;
 impl ExpResult for EqExp {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size:i32, is_pt: bool) -> ExpRetType {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
         match self {
-            EqExp::Relexp(relexp) => {
-                let ret_val = relexp.eval(scope, size, is_pt); 
-
-                return ExpRetType {
-                    size: ret_val.size + 1,
-                    program: ret_val.program,
-                    exp_res_id: ret_val.exp_res_id,
-                    is_constant: ret_val.is_constant,
-                };
-            },
+            EqExp::Relexp(relexp) => relexp.eval(ctx),
             EqExp::Eqexp(eqexp, relexp, op) |
             EqExp::Neqexp(eqexp, relexp, op) => {
-                let ret_val1 = (*eqexp).eval(scope, size, is_pt);
-                let ret_val2 = relexp.eval(scope, ret_val1.size + 1, is_pt);
-                let mut size = ret_val2.size + 1;
-                let mut program = String::from("");
-
-                let val = binary_operation(&mut program, &mut size, op, &ret_val1, &ret_val2);
-
-                return ExpRetType {
-                    size: size,
-                    program: program,
-                    exp_res_id: val,
-                    is_constant: ret_val1.is_constant && ret_val2.is_constant,
-                };
+                let ret_val1 = (*eqexp).eval(ctx);
+                let ret_val2 = relexp.eval(ctx);
+                binary_operation(ctx, op, &ret_val1, &ret_val2)
             },
         }
     }
 }
 
-// we need to add short-circuit evaluation.
-// LAndExp       ::= EqExp | LAndExp "&&" EqExp;
-impl ExpResult for LAndExp {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size:i32, is_pt: bool) -> ExpRetType {
+// BitAndExp ::= EqExp | BitAndExp "&" EqExp;
+impl ExpResult for BitAndExp {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
         match self {
-            LAndExp::Eqexp(eqexp) => {
-                let ret_val = eqexp.eval(scope, size, is_pt);
-
-                return ExpRetType {
-                    size: ret_val.size + 1,
-                    program: ret_val.program,
-                    exp_res_id: ret_val.exp_res_id,
-                    is_constant: ret_val.is_constant,
-                };
+            BitAndExp::Eqexp(eqexp) => eqexp.eval(ctx),
+            BitAndExp::Andexp(bitandexp, eqexp, op) => {
+                let ret_val1 = (*bitandexp).eval(ctx);
+                let ret_val2 = eqexp.eval(ctx);
+                binary_operation(ctx, op, &ret_val1, &ret_val2)
             },
-            LAndExp::Andexp(landexp, eqexp) => {
-                let mut program = String::from("");
-                let ret_val1 = (*landexp).eval(scope, size, is_pt);
-                let ret_val2 = eqexp.eval(scope, ret_val1.size, is_pt);
-                let name1 = get_name(ret_val1.exp_res_id, ret_val1.is_constant);
-                let name2 = get_name(ret_val2.exp_res_id, ret_val2.is_constant);
-
-                if ret_val1.is_constant && ret_val2.is_constant { // if it's constant.
-                    return ExpRetType {
-                        size: ret_val2.size,
-                        program: program,
-                        exp_res_id: ((ret_val1.exp_res_id != 0) && (ret_val2.exp_res_id != 0)) as i32,
-                        is_constant: true,
-                    };
-                }
+        }
+    }
+}
 
-                let size = ret_val2.size + 1;
-
-                // init condition to zero.
-                program.push_str(&format!("    @condition_{} = alloc i32\n", size));
-                program.push_str(&format!("    store 0, @condition_{}\n", size));
-
-                // first evaluate the left.
-                program.push_str(&ret_val1.program); // jump according to ret_val.
-                program.push_str(&format!("    br {}, %entry_{}, %entry_{}\n", &name1, size, size + 2));
-                
-                // if first is true, then jump to here.
-                program.push_str(&format!("\n%entry_{}:\n", size));
-                // then evaluate the left.
-                program.push_str(&ret_val2.program); // jump according to ret_val.
-                program.push_str(&format!("    br {}, %entry_{}, %entry_{}\n", &name2, size + 1, size + 2));
-
-                // both is true.
-                program.push_str(&format!("\n%entry_{}:\n", size + 1));
-                program.push_str(&format!("    store 1, @condition_{}\n", size));
-                program.push_str(&format!("    jump %entry_{}\n", size + 2));
-
-                program.push_str(&format!("\n%entry_{}:\n", size + 2));
-                program.push_str(&format!("    %var_{} = load @condition_{}\n", size + 2, size));
-
-                return ExpRetType {
-                    size: size + 2,
-                    program: program,
-                    exp_res_id: size + 2,
-                    is_constant: ret_val1.is_constant && ret_val2.is_constant,
-                };
+// BitXorExp ::= BitAndExp | BitXorExp "^" BitAndExp;
+impl ExpResult for BitXorExp {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
+        match self {
+            BitXorExp::Bitandexp(bitandexp) => bitandexp.eval(ctx),
+            BitXorExp::Xorexp(bitxorexp, bitandexp, op) => {
+                let ret_val1 = (*bitxorexp).eval(ctx);
+                let ret_val2 = bitandexp.eval(ctx);
+                binary_operation(ctx, op, &ret_val1, &ret_val2)
+            },
+        }
+    }
+}
+
+// BitOrExp ::= BitXorExp | BitOrExp "|" BitXorExp;
+impl ExpResult for BitOrExp {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
+        match self {
+            BitOrExp::Bitxorexp(bitxorexp) => bitxorexp.eval(ctx),
+            BitOrExp::Orexp(bitorexp, bitxorexp, op) => {
+                let ret_val1 = (*bitorexp).eval(ctx);
+                let ret_val2 = bitxorexp.eval(ctx);
+                binary_operation(ctx, op, &ret_val1, &ret_val2)
             },
         }
     }
 }
 
+/*
+ * 短路求值, 结果要当作 int 用的时候: 条件的部分交给 `condition.rs`, 跳到 true / false 两个 block,
+ * 分别把 1 / 0 存进 %condition, 最后再 load 出来. 条件是常量的话直接得到常量, 什么都不生成.
+ */
+fn materialize<C: CondResult>(ctx: &mut Context, exp: &C) -> ExpRetType {
+    let true_bb = ctx.new_bb("%cond_true");
+    let false_bb = ctx.new_bb("%cond_false");
+    let end_bb = ctx.new_bb("%cond_end");
+
+    if let Some(val) = exp.cond(ctx, true_bb, false_bb) {
+        for bb in [true_bb, false_bb, end_bb] {
+            ctx.remove_bb(bb);
+        }
+        return ExpRetType::constant(val as i32);
+    }
+
+    let condition = ctx.alloc_in_entry(koopa::ir::Type::get_i32());
+    ctx.set_value_name(condition, "%condition");
+    for (bb, val) in [(true_bb, 1), (false_bb, 0)] {
+        ctx.set_bb(bb);
+        let val = ctx.integer(val);
+        let store = ctx.new_value().store(val, condition);
+        ctx.push_inst(store);
+        ctx.jump(end_bb);
+    }
+
+    ctx.set_bb(end_bb);
+    let val = ctx.new_value().load(condition);
+    ctx.push_inst(val);
+    ExpRetType::value(val)
+}
+
+// we need to add short-circuit evaluation.
+// LAndExp       ::= BitOrExp | LAndExp "&&" BitOrExp;
+impl ExpResult for LAndExp {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
+        match self {
+            LAndExp::Bitorexp(bitorexp) => bitorexp.eval(ctx),
+            // no blocks outside a function, both sides are constants there.
+            LAndExp::Andexp(landexp, bitorexp) if ctx.is_global() => {
+                let ret_val1 = (*landexp).eval(ctx);
+                let ret_val2 = bitorexp.eval(ctx);
+                binary_operation(ctx, "land", &ret_val1, &ret_val2)
+            },
+            LAndExp::Andexp(_, _) => materialize(ctx, self),
+        }
+    }
+}
+
 // LOrExp ::= LAndExp | LOrExp "||" LAndExp;
 impl ExpResult for LOrExp {
-    fn eval(&self, scope: &HashMap<String, (i32, i32)>, size:i32, is_pt: bool) -> ExpRetType {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
         match self {
-            LOrExp::Landexp(landexp) => {
-                let ret_val = landexp.eval(scope, size, is_pt);
-
-                return ExpRetType {
-                    size: ret_val.size + 1,
-                    program: ret_val.program,
-                    exp_res_id: ret_val.exp_res_id,
-                    is_constant: ret_val.is_constant,
-                };
+            LOrExp::Landexp(landexp) => landexp.eval(ctx),
+            LOrExp::Orexp(lorexp, landexp) if ctx.is_global() => {
+                let ret_val1 = (*lorexp).eval(ctx);
+                let ret_val2 = landexp.eval(ctx);
+                binary_operation(ctx, "lor", &ret_val1, &ret_val2)
             },
-            LOrExp::Orexp(lorexp, landexp) => {
-                let mut program = String::from("");
-                let ret_val1 = (*lorexp).eval(scope, size, is_pt);
-                let ret_val2 = landexp.eval(scope, ret_val1.size, is_pt);
-                if ret_val1.is_constant && ret_val2.is_constant { // if it's constant.
-                    return ExpRetType {
-                        size: ret_val2.size,
-                        program: program,
-                        exp_res_id: (ret_val1.exp_res_id != 0 || ret_val2.exp_res_id != 0) as i32,
-                        is_constant: true,
-                    };
-                }
+            LOrExp::Orexp(_, _) => materialize(ctx, self),
+        }
+    }
+}
 
-                let name1 = get_name(ret_val1.exp_res_id, ret_val1.is_constant);
-                let name2 = get_name(ret_val2.exp_res_id, ret_val2.is_constant);
-
-                let size = ret_val2.size + 1;
-
-
-                // init condition to zero.
-                program.push_str(&format!("    @condition_{} = alloc i32\n", size));
-                program.push_str(&format!("    store 0, @condition_{}\n", size));
-
-                // first evaluate the left.
-                program.push_str(&ret_val1.program); // jump according to ret_val.
-                program.push_str(&format!("    br {}, %entry_{}, %entry_{}\n", &name1, size + 1, size));
-                
-                // if first is false, then jump to here.
-                program.push_str(&format!("\n%entry_{}:\n", size));
-                // then evaluate the left.
-                program.push_str(&ret_val2.program); // jump according to ret_val.
-                program.push_str(&format!("    br {}, %entry_{}, %entry_{}\n", &name2, size + 1, size + 2));
-                
-                // exist one condition is true.
-                program.push_str(&format!("\n%entry_{}:\n", size + 1));
-                program.push_str(&format!("    store 1, @condition_{}\n", size));
-                program.push_str(&format!("    jump %entry_{}\n", size + 2));
-
-                program.push_str(&format!("\n%entry_{}:\n", size + 2));
-                program.push_str(&format!("    %var_{} = load @condition_{}\n", size + 2, size));
-
-                return ExpRetType {
-                    size: size + 2,
-                    program: program,
-                    exp_res_id: size + 2,
-                    is_constant: ret_val1.is_constant && ret_val2.is_constant,
-                };
+/*
+ * `c ? a : b` 只求值被选中的那一边: 条件交给 `condition.rs` 跳到两个 arm, 结果存进 %ternary 再 load 出来.
+ * 条件是常量的时候只生成被选中的 arm, 函数外面(全局的初始化, 数组维度)也就能用了.
+ * 结果的类型由两边一起决定, 没有生成的那一边用 `FloatType` 看一眼它的类型, 不生成任何指令.
+ */
+// CondExp ::= LOrExp | LOrExp "?" Exp ":" CondExp;
+impl ExpResult for CondExp {
+    fn eval(&self, ctx: &mut Context) -> ExpRetType {
+        let (lorexp, exp, condexp) = match self {
+            CondExp::Lorexp(lorexp) => return lorexp.eval(ctx),
+            CondExp::Condexp(lorexp, exp, condexp, _) => (lorexp, exp, condexp),
+        };
+        let is_float = self.is_float(ctx);
+
+        // no blocks outside a function, the condition is a constant there.
+        if ctx.is_global() {
+            let mut cond = lorexp.eval(ctx);
+            if cond.is_float {
+                cond = float_to_bool(ctx, &cond);
+            }
+            let ret_val = if cond.exp_res_id != 0 { exp.eval(ctx) } else { condexp.eval(ctx) };
+            return convert(ctx, &ret_val, is_float);
+        }
+
+        let true_bb = ctx.new_bb("%ternary_true");
+        let false_bb = ctx.new_bb("%ternary_false");
+        let end_bb = ctx.new_bb("%ternary_end");
+        if let Some(val) = lorexp.cond(ctx, true_bb, false_bb) {
+            for bb in [true_bb, false_bb, end_bb] {
+                ctx.remove_bb(bb);
+            }
+            let ret_val = if val { exp.eval(ctx) } else { condexp.eval(ctx) };
+            return convert(ctx, &ret_val, is_float);
+        }
+
+        // a pointer arm makes `%ternary` a pointer, the other arm may be the null pointer `0`.
+        // so both arms are generated first, the stores come after the type is known.
+        let mut arms = Vec::new();
+        for (bb, arm) in [(true_bb, exp.as_ref() as &dyn ExpResult), (false_bb, condexp.as_ref())] {
+            ctx.set_bb(bb);
+            let ret_val = arm.eval(ctx);
+            let ret_val = convert(ctx, &ret_val, is_float);
+            arms.push((ret_val, ctx.current_bb()));
+        }
+        let ty = arms.iter().find(|(ret_val, _)| is_pointer(ctx, ret_val))
+            .map_or(koopa::ir::Type::get_i32(), |(ret_val, _)| ctx.value_type(ret_val.value.unwrap()));
+        let result = ctx.alloc_in_entry(ty.clone());
+        ctx.set_value_name(result, "%ternary");
+        for (ret_val, bb) in arms {
+            ctx.resume_bb(bb);
+            let val = if ret_val.is_constant && matches!(ty.kind(), koopa::ir::TypeKind::Pointer(_)) {
+                null_pointer(ctx, ty.clone())
+            } else {
+                get_value(ctx, &ret_val)
+            };
+            let store = ctx.new_value().store(val, result);
+            ctx.push_inst(store);
+            ctx.jump(end_bb);
+        }
+
+        ctx.set_bb(end_bb);
+        let val = ctx.new_value().load(result);
+        ctx.push_inst(val);
+        ExpRetType { is_float, ..ExpRetType::value(val) }
+    }
+}
+
+
+// --------------------------------------------------------------------
+// the type of an expression without evaluating it, the same rules as `binary_operation` and the semantic check.
+pub trait FloatType {
+    fn is_float(&self, ctx: &Context) -> bool;
+}
+
+impl FloatType for Exp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        self.condexp.is_float(ctx)
+    }
+}
+
+impl FloatType for CondExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            CondExp::Lorexp(lorexp) => lorexp.is_float(ctx),
+            CondExp::Condexp(_, exp, condexp, _) => exp.is_float(ctx) || condexp.is_float(ctx),
+        }
+    }
+}
+
+// logical operators, comparisons, bitwise operators and shifts give an `int`.
+impl FloatType for LOrExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            LOrExp::Landexp(landexp) => landexp.is_float(ctx),
+            LOrExp::Orexp(_, _) => false,
+        }
+    }
+}
+
+impl FloatType for LAndExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            LAndExp::Bitorexp(bitorexp) => bitorexp.is_float(ctx),
+            LAndExp::Andexp(_, _) => false,
+        }
+    }
+}
+
+impl FloatType for BitOrExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            BitOrExp::Bitxorexp(bitxorexp) => bitxorexp.is_float(ctx),
+            BitOrExp::Orexp(_, _, _) => false,
+        }
+    }
+}
+
+impl FloatType for BitXorExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            BitXorExp::Bitandexp(bitandexp) => bitandexp.is_float(ctx),
+            BitXorExp::Xorexp(_, _, _) => false,
+        }
+    }
+}
+
+impl FloatType for BitAndExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            BitAndExp::Eqexp(eqexp) => eqexp.is_float(ctx),
+            BitAndExp::Andexp(_, _, _) => false,
+        }
+    }
+}
+
+impl FloatType for EqExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            EqExp::Relexp(relexp) => relexp.is_float(ctx),
+            EqExp::Eqexp(_, _, _) | EqExp::Neqexp(_, _, _) => false,
+        }
+    }
+}
+
+impl FloatType for RelExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            RelExp::Shiftexp(shiftexp) => shiftexp.is_float(ctx),
+            RelExp::Ltexp(_, _, _) | RelExp::Gtexp(_, _, _) |
+            RelExp::Geexp(_, _, _) | RelExp::Leexp(_, _, _) => false,
+        }
+    }
+}
+
+impl FloatType for ShiftExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            ShiftExp::Addexp(addexp) => addexp.is_float(ctx),
+            ShiftExp::Shlexp(_, _, _) | ShiftExp::Sarexp(_, _, _) => false,
+        }
+    }
+}
+
+// arithmetic is `float` if one side is, `%` only takes `int`.
+impl FloatType for AddExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            AddExp::Mulexp(mulexp) => mulexp.is_float(ctx),
+            AddExp::Addexp(addexp, mulexp, _) |
+            AddExp::Subexp(addexp, mulexp, _) => addexp.is_float(ctx) || mulexp.is_float(ctx),
+        }
+    }
+}
+
+impl FloatType for MulExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            MulExp::Unaryexp(unaryexp) => unaryexp.is_float(ctx),
+            MulExp::Mulexp(mulexp, unaryexp, _) |
+            MulExp::Divexp(mulexp, unaryexp, _, _) => mulexp.is_float(ctx) || unaryexp.is_float(ctx),
+            MulExp::Modexp(_, _, _, _) => false,
+        }
+    }
+}
+
+impl FloatType for UnaryExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            UnaryExp::Primaryexp(primaryexp) => primaryexp.is_float(ctx),
+            UnaryExp::AddrOf(lval, _) => lval.is_float(ctx),
+            UnaryExp::Unaryexp(UnaryOp::Not, _) | UnaryExp::Unaryexp(UnaryOp::BitNot, _) => false,
+            UnaryExp::Unaryexp(_, unaryexp) => unaryexp.is_float(ctx),
+            UnaryExp::Funcall(_, None, span) if ctx.timer_lines.contains_key(span) => false,
+            UnaryExp::Funcall(ident, _, _) => ctx.scope.get_function(ident).1.ret == Type::Float,
+        }
+    }
+}
+
+impl FloatType for PrimaryExp {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            PrimaryExp::Exp(exp) => exp.is_float(ctx),
+            PrimaryExp::Num(_) | PrimaryExp::BigNum(_, _) => false,
+            PrimaryExp::FloatNum(_, _) => true,
+            PrimaryExp::Str(_, _) => false,
+            PrimaryExp::Lval(lval) => lval.is_float(ctx),
+        }
+    }
+}
+
+// an array or a pointer is `float` if its elements are, for `*p` and `&x`.
+impl FloatType for LVal {
+    fn is_float(&self, ctx: &Context) -> bool {
+        match self {
+            LVal::Ident(ident, _, _) => match ctx.scope.get(ident) {
+                Symbol::Const(btype, _) | Symbol::Var(_, btype) |
+                Symbol::Array(_, btype, _) | Symbol::ConstArray(_, btype, _, _) |
+                Symbol::Pointer(_, btype, _) => *btype == BType::Float,
+                Symbol::Struct(_, _, _) => false,
+                Symbol::Function(_, _) => panic!("Function used as a variable."),
             },
+            LVal::Deref(exp, _) => exp.is_float(ctx),
+            LVal::Member(base, name, _, _) => field_of(base, name, ctx).element == Element::Basic(BType::Float),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::semantic::Extensions;
+    use crate::testing::{koopa, run};
+
+    const POINTERS: Extensions = Extensions { pointers: true, structs: false };
+
+    #[test]
+    fn bitwise_and_shifts() {
+        // C precedence: `>>` before `&` before `^` before `|`.
+        let text = "const int k = (1 << 4) | 3 ^ ~0 & 6 >> 1;\nint b[k];\nint main() {\n  int a = getint();\n\
+            return (a << 2) + (a >> 1) + (a & 6) + (a | 1) + (a ^ 3) + ~a;\n}\n";
+        let ir = koopa(text, Extensions::default());
+        assert!(ir.contains("global @b = alloc [i32, 16], zeroinit\n"));
+        for inst in ["shl %1, 2", "sar %3, 1", "and %6, 6", "or %9, 1", "xor %12, 3", "xor %15, -1"] {
+            assert!(ir.contains(inst), "{}", inst);
+        }
+        let a = -9;
+        assert_eq!(run(text, Extensions::default(), &[a]).0, (a << 2) + (a >> 1) + (a & 6) + (a | 1) + (a ^ 3) + !a);
+    }
+
+    #[test]
+    fn ternary_evaluates_only_the_chosen_arm() {
+        let text = "const int c = 0 ? 4 : 1 ? 5 : 6;\nint a[1 ? c : 3];\nint f(int v) {\n  putint(v);\n  return v;\n}\n\
+            int main() {\n  int x = getint();\n  return x ? f(1) : f(2);\n}\n";
+        assert_eq!(run(text, Extensions::default(), &[7]), (1, "1".to_string()));
+        assert_eq!(run(text, Extensions::default(), &[0]), (2, "2".to_string()));
+        let ir = koopa(text, Extensions::default());
+        assert!(ir.contains("global @a = alloc [i32, 5], zeroinit\n"));
+        assert!(ir.contains("  br %3, %ternary_true, %ternary_false\n"));
+        assert!(ir.contains("%ternary_true:\n  %4 = call @f(1)\n  store %4, %ternary\n  jump %ternary_end\n"));
+    }
+
+    #[test]
+    fn pointers() {
+        let text = "int rows(int q[][3]) {\n  return &q[2] - &q[0];\n}\nint main() {\n\
+            int x = 1, a[2][3] = {{1, 2, 3}, {4, 5, 6}};\n  int *p = &x, *n = 0, *e = &a[1][2];\n  *p = 5;\n\
+            int *q = &a[0][0];\n  *(q + 4) = 50;\n  q = e - 2;\n\
+            putint(x); putch(32); putint(e - q); putch(32); putint(q < e); putch(32); putint(*q); putch(32);\n\
+            putint(a[1][1]); putch(32); putint(rows(a)); putint(&a[1] - &a[0]); putch(32); putint(!n); putint(n == 0);\n\
+            if (p && !n) return *p + 1;\n  return 0;\n}\n";
+        assert_eq!(run(text, POINTERS, &[]), (6, "5 2 1 4 50 21 11".to_string()));
+        let text = koopa(text, POINTERS);
+        assert!(text.contains("getptr ") && text.contains("store zeroinit, %null"));
+    }
+
+    #[test]
+    fn pointer_arms() {
+        let text = "int main() {\n  int x = 5, y = 7;\n  int *p = &x, *q = &y;\n\
+            int *t = getint() ? p : q;\n  int *u = getint() ? 0 : q;\n  return *t * 10 + (u == 0);\n}\n";
+        assert_eq!(run(text, POINTERS, &[1, 1]).0, 51);
+        assert_eq!(run(text, POINTERS, &[0, 0]).0, 70);
+        assert!(koopa(text, POINTERS).contains("%ternary = alloc *i32\n"));
+    }
+
+    #[test]
+    fn structs() {
+        let structs = Extensions { pointers: false, structs: true };
+        let text = "struct T { int x; float f; };\nstruct S { int a, b[3]; struct T t[2]; int g[2][3]; };\n\
+            struct S gs, garr[3];\nint sum(int p[], int n) { int i = 0, s = 0; while (i < n) { s = s + p[i]; i = i + 1; } return s; }\n\
+            int main() {\n  struct S s, arr[2];\n  int i = getint();\n  s.a = 1;\n  s.b[0] = 2; s.b[1] = 3; s.b[2] = 4;\n\
+            arr[i].t[i].x = 10;\n  arr[1].t[0].f = 2.5;\n  s.g[1][0] = 1; s.g[1][1] = 2; s.g[1][2] = 6;\n\
+            garr[2].t[1].x = gs.a + 7;\n  putint(s.a + sum(s.b, 3)); putch(32); putint(arr[1].t[1].x); putch(32);\n\
+            putint(arr[1].t[0].f * 2); putch(32); putint(sum(s.g[1], 3)); putch(32); putint(garr[2].t[1].x);\n\
+            return gs.t[1].x;\n}\n";
+        assert_eq!(run(text, structs, &[1]), (0, "10 10 5 9 7".to_string()));
+        // a struct is `[i32, size]`, 1 + 3 + 2 * 2 + 6 here, an array of them an array of that.
+        let text = koopa(text, structs);
+        assert!(text.contains("global @garr = alloc [[i32, 14], 3], zeroinit\n"));
+        assert!(text.contains("@s = alloc [i32, 14]\n"));
+    }
+}
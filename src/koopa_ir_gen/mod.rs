@@ -3,11 +3,20 @@ mod expression;
 mod declare;
 mod statement;
 mod initialvalue;
+mod symbol_table;
+mod context;
+mod condition;
 
+use std::collections::HashMap;
+
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Value};
 use ret_types::*;
 use crate::ast::*;
-use core::{panic};
-use std::{collections::HashMap};
+use crate::diagnostic::Span;
+use crate::semantic::{CallGraph, Checked, FunctionSignature, Type};
+use context::Context;
+use symbol_table::Symbol;
 use crate::koopa_ir_gen::declare::DeclResult;
 use crate::koopa_ir_gen::declare::evaluate_dimension;
 // use self::expression::ExpResult;
@@ -15,296 +24,391 @@ use crate::koopa_ir_gen::declare::evaluate_dimension;
 /*
  * 最外层的compiler, 我们应该top-down看. 
  *  
- * 最重要的数据结构: scope: HashMap<String, (i32, i32)>
- * 根据名字(string), 得到(type, param). type指明了类型, 例如integer, array, void, consntant等.
- * 第二维param维护额外信息, 例如type为integer, param指定var_param为地址.
- *                          type为function, param指定参数的类型是否为array.
+ * 生成的结果直接是内存里的 koopa::ir::Program, 用 koopa 的 builder 一条条插指令, 见 `context.rs`.
+ * 文本形式只在最后需要输出的时候由 KoopaGenerator 打印出来.
+ *
+ * 最重要的数据结构: ctx.scope: SymbolTable, 见 `symbol_table.rs`.
+ * 根据名字(string), 得到 Symbol, 指明了类型, 例如integer, array, constant, function等,
+ * 以及额外信息, 例如变量的地址, 常量的值, 数组的维度, 函数的签名.
+ *
+ * koopa 没有 float 类型, `float` 的值在 IR 里就是 i32, 存它的 bit pattern.
+ * float 的运算是对 `@__sysy_fadd` 这样的 intrinsic 的调用, 后端把它们换成对应的 RV32F 指令, 见 `context.rs`.
+ * 没有任何运行时库实现这些函数, 所以用到 float 或者指针运算的 `-koopa` 输出只能交给这里的后端, 不能拿去别处运行.
+ * 函数的哪些参数和返回值是 `float` 在 IR 里看不出来, 和 Program 一起交给后端, 见 FloatAbi.
  */
 
-// return variable name based on whether it's constant or not.
-fn get_name(id: i32, is_constant: bool) -> String {
-    if is_constant {
-        id.to_string()
+// float operations are calls to declared functions with this prefix.
+pub const INTRINSIC_PREFIX: &str = "@__sysy_";
+// the intrinsics and how many arguments they take, `ptoi` takes a pointer, the others `i32`s.
+pub const INTRINSICS: [(&str, usize); 15] = [
+    ("fadd", 2), ("fsub", 2), ("fmul", 2), ("fdiv", 2),
+    ("feq", 2), ("fne", 2), ("flt", 2), ("fgt", 2), ("fle", 2), ("fge", 2),
+    ("itof", 1), ("ftoi", 1),
+    ("ftod_lo", 1), ("ftod_hi", 1), // the halves of the `double` a variadic `float` is promoted to.
+    ("ptoi", 1), // the address as an `int`, koopa can't compare or subtract pointers.
+];
+// the name of the intrinsic a function is, without the prefix. the semantic check keeps the prefix
+// out of the program, only these names are intrinsics anyway.
+pub fn intrinsic(name: &str) -> Option<&str> {
+    name.strip_prefix(INTRINSIC_PREFIX).filter(|name| INTRINSICS.iter().any(|(intrinsic, _)| intrinsic == name))
+}
+
+// the globals of string literals, the backend lays them out as bytes.
+pub const STRING_PREFIX: &str = "@__sysy_str_";
+
+// which parameters and whether the return value of a function are `float`, koopa only sees `i32`.
+#[derive(Debug, Clone)]
+pub struct FloatAbi {
+    pub params: Vec<bool>,
+    pub ret: bool,
+}
+
+impl FloatAbi {
+    fn new(signature: &FunctionSignature) -> FloatAbi {
+        FloatAbi {
+            params: signature.params.iter().map(|ty| *ty == Type::Float).collect(),
+            ret: signature.ret == Type::Float,
+        }
+    }
+}
+
+// a `switch` with dense labels, koopa has no indirect jump. The IR compares `value` in `blocks`,
+// the backend jumps through `targets` instead: `targets[value - low]`, `default` when out of range.
+#[derive(Debug, Clone)]
+pub struct JumpTable {
+    pub value: Value,
+    pub low: i32,
+    pub targets: Vec<BasicBlock>,
+    pub default: BasicBlock,
+    pub blocks: Vec<BasicBlock>,
+}
+
+// the koopa value of an expression, constants are created when used.
+fn get_value(ctx: &mut Context, val: &ExpRetType) -> Value {
+    if val.is_constant {
+        ctx.integer(val.exp_res_id)
     } else {
-        format!("%var_{}", id)
+        val.value.unwrap()
+    }
+}
+
+// `[[i32, 3], 2]` for dimensions `[2][3]`, `i32` when there is no dimension.
+fn array_type(dims: &[i32]) -> koopa::ir::Type {
+    array_of(koopa::ir::Type::get_i32(), dims)
+}
+
+// the same with any element type, a struct is `[i32, size]`.
+fn array_of(element: koopa::ir::Type, dims: &[i32]) -> koopa::ir::Type {
+    dims.iter().rev().fold(element, |ty, len| koopa::ir::Type::get_array(ty, *len as usize))
+}
+
+fn ir_type(ty: &Type) -> koopa::ir::Type {
+    match ty {
+        Type::Int | Type::Float => koopa::ir::Type::get_i32(),
+        Type::Void => koopa::ir::Type::get_unit(),
+        Type::Pointer(_, dims) => koopa::ir::Type::get_pointer(array_type(dims)),
+        Type::Str => koopa::ir::Type::get_pointer(koopa::ir::Type::get_i32()),
+        Type::Struct(_) | Type::StructArray(_, _) => panic!("Struct parameter reached the generator."),
     }
 }
 
-// 计算函数的bitset.
-fn calc_bitset(node: &FuncDef) -> i32 {
-    match &node.params {
-        None => {0},
-        Some(v) => {
-            let mut bitset = 0;
-            let mut bin = 1;
-            for x in &v.params {
-                // maybe pointer in the future????  yes.
-                match x {
-                    FuncFParam::Integer(_) => {} ,
-                    FuncFParam::Array(_, _) => bitset |= bin,
+// 计算函数的签名, 数组参数要带上第一维之后的维度.
+fn function_signature(node: &FuncDef, ctx: &mut Context) -> FunctionSignature {
+    let mut params = Vec::new();
+    if let Some(v) = &node.params {
+        for x in &v.params {
+            match x {
+                FuncFParam::Scalar(btype, _, _) => params.push(Type::from(*btype)),
+                FuncFParam::Array(btype, _, dims, _) => params.push(Type::Pointer(*btype, evaluate_dimension(dims, ctx))),
+                FuncFParam::Pointer(btype, _, _) => params.push(Type::Pointer(*btype, vec![])),
+                FuncFParam::Error() => panic!("Syntax error reached the generator."),
+            }
+        }
+    }
+    FunctionSignature::new(params, Type::from(node.func_type))
+}
+
+// insert the function into the scope, a prototype without a definition is a `decl`.
+//     decl @f(*i32, i32): i32
+fn declare_function(func: &FuncDef, ctx: &mut Context) {
+    let signature = function_signature(func, ctx);
+    let data = match &func.block {
+        Some(_) => {
+            let mut params = Vec::new();
+            if let Some(v) = &func.params {
+                for (x, ty) in v.params.iter().zip(&signature.params) {
+                    let ident = match x {
+                        FuncFParam::Scalar(_, ident, _) | FuncFParam::Array(_, ident, _, _) |
+                        FuncFParam::Pointer(_, ident, _) => ident,
+                        FuncFParam::Error() => panic!("Syntax error reached the generator."),
+                    };
+                    params.push((Some(format!("@{}", ident)), ir_type(ty)));
                 }
-                bin = bin << 1;
             }
-            bitset
+            FunctionData::with_param_names(format!("@{}", func.ident), params, ir_type(&signature.ret))
         },
-    }
+        None => {
+            let params = signature.params.iter().map(ir_type).collect();
+            FunctionData::new_decl(format!("@{}", func.ident), params, ir_type(&signature.ret))
+        },
+    };
+    let func_id = ctx.new_func(data, &signature);
+    ctx.scope.insert_function(&func.ident, func_id, signature);
 }
 
 enum TreePoint<'a> {
-    CompUnit(CompUnit),
+    CompUnit(CompUnit, &'a CallGraph),
     FuncDef(&'a FuncDef),
-    FuncType(&'a FuncType),
-    FuncFParams(&'a FuncFParams),
     Block(&'a Block),
 }
 
 
 // tranverse the syntax tree to translate.
-// return (size, Program), size for unique identify of the node.
-fn dfs(pt: TreePoint, par: &HashMap<String, (i32, i32)>, size: i32) -> BodyRetType {
-    // consider the indent!
-    let mut size = size;
-    let mut program = String::from("");
-    // 变量作用域的主要实现: 每递归一层, clone scope, 之后插入的variable会发生replace.
-    let mut scope: HashMap<String, (i32, i32)> = par.clone(); // inherit the variables from parent.
-
+// instructions are appended to `ctx`, see `context.rs`.
+fn dfs(pt: TreePoint, ctx: &mut Context) {
     match pt {
 
-        // CompUnit ::= [CompUnit] FuncDef;
-        TreePoint::CompUnit(node) => {
-            fn insert_function(scope: &mut HashMap<String, (i32, i32)>, func_def: &FuncDef, bitset: i32) {
-                // insert the function definition.
-                // println!("insert: {}\n", &func_def.ident);
-                match func_def.func_type {
-                    0 => { // int
-                        scope.insert(format!("{}_function", &func_def.ident), (VARIABLE_INT | (bitset << TYPE_BITS), 0));
-                    },
-                    1 => {
-                        scope.insert(format!("{}_function", &func_def.ident), (VOID | (bitset << TYPE_BITS), 1));
-                    },
-                    _ => {panic!("No function type labeled this.");}
-                }
-            }
+        // CompUnit ::= [CompUnit] (Decl | FuncDef);
+        // all the functions are declared before any body, a call may come before the definition.
+        TreePoint::CompUnit(node, calls) => {
+            // the global declarations first, the dimensions of array parameters may use the constants.
+            // the koopa function takes the parameter names of the definition, a prototype only gives the types.
+            let mut functions = HashMap::new();
             for pair in &node.funcs {
                 match &pair {
                     DeclFuncPair::Func(func) => {
-                        let bitset = calc_bitset(&func);
-                        insert_function(&mut scope, &func, bitset);
-                        let func_val = dfs(TreePoint::FuncDef(func), &scope, size);
-                        program.push_str(&func_val.program);
-
-                        // 保证计算param bitset的正确性.
-                        assert!(bitset == func_val.exp_res_id);
-
-                        size = func_val.size;
+                        if func.block.is_some() || !functions.contains_key(&func.ident) {
+                            functions.insert(func.ident.clone(), func);
+                        }
                     },
-                    DeclFuncPair::Decl(decl) => {
-                        let decl_val = decl.eval(&mut scope, size, true);
+                    DeclFuncPair::Decl(decl) => decl.eval(ctx),
+                    DeclFuncPair::Struct(def) => def.eval(ctx),
+                    DeclFuncPair::Error() => panic!("Syntax error reached the generator."),
+                }
+            }
 
-                        program.push_str(&decl_val.program);
-                        size = decl_val.size;
-                    },
+            // koopa text only knows the functions printed before, so a callee is created before its callers.
+            let (order, _) = calls.callees_first(&crate::semantic::function_names(&node));
+            for name in &order {
+                if !ctx.scope.contains_function(name) { // a prototype of the runtime library.
+                    declare_function(functions[name], ctx);
                 }
             }
 
-            return BodyRetType {
-                size: size, 
-                program, 
-                exp_res_id: -1,
-            };
-        },
-        
-        // FuncDef     ::= FuncType IDENT "(" [FuncFParams] ")" Block;
-        TreePoint::FuncDef(node) => {
-            let mut load_params = "".to_string();
-            program.push_str(&format!("\n\nfun @{}(", node.ident));
-
-            let mut bitset = 0;
-            // if we have parameter, we have to create variables.
-            match &node.params {
-                None => {},
-                Some(v) => {
-                    for x in &v.params {
-                        // %x = alloc i32
-                        // store @x, %x
-                        // we only have i32, hhhh.
-                        match x {
-                            // 使用之后的参数, 我们先把参数和普通变量一样定义, 这样之后就不用区分了.
-                            // 增加了overhead...
-                            FuncFParam::Integer(ident) => {
-                                size += 1;
-                                load_params.push_str(&format!("    @var_{} = alloc i32\n", size));
-                                load_params.push_str(&format!("    store @{}, @var_{}\n", ident , size));
-        
-                                // add parameter to scope. And parameter is variable.
-                                scope.insert(format!("{}", ident), (VARIABLE_INT, size));
-                            },
-                            FuncFParam::Array(ident, dims) => {
-                                size += 1;
-                                let dim_pair = evaluate_dimension(&mut size, &dims, &scope);
-                                if dims.len() == 0 {
-                                    load_params.push_str(&format!("    @var_{} = alloc *i32\n", size));
-                                } else {
-                                    load_params.push_str(&format!("    @var_{} = alloc *{}\n", size, dim_pair.1));
-                                }
-                                load_params.push_str(&format!("    store @{}, @var_{}\n", ident, size));
-
-                                // wrong!!! 如何区分参数到底是数组还是数字?
-                                scope.insert(format!("{}", ident), (PARAMETER_ARRAY, size));
-                            },
-                        }
+            for pair in &node.funcs {
+                if let DeclFuncPair::Func(func) = pair {
+                    if func.block.is_some() {
+                        dfs(TreePoint::FuncDef(func), ctx);
                     }
-                    let param_val = dfs(TreePoint::FuncFParams(v), &scope, size);
-                    program.push_str(&param_val.program);
-                    bitset = param_val.exp_res_id;
-                    
-                    size = param_val.size;
-                },
+                }
             }
-            program.push_str(")");
-
+        },
 
-            let ftype = match node.func_type {
-                0 => FuncType::Int,
-                1 => FuncType::Void,
-                _ => panic!("No function type labeled this."),
+        // FuncDef     ::= FuncType IDENT "(" [FuncFParams] ")" Block;
+        TreePoint::FuncDef(node) => {
+            let (func, signature) = {
+                let (func, signature) = ctx.scope.get_function(&node.ident);
+                (func, signature.clone())
             };
-
-            // get the type of return value.
-            let ret_val = dfs(TreePoint::FuncType(&ftype), &scope, size);
-
-
-            program.push_str(&ret_val.program);
-            // begin the structure of body.
-            program.push_str(" {\n");
+            ctx.enter_function(func);
 
             // first label of the function.
-            program.push_str(&format!("\n%entry_{}:\n", ret_val.size + 1));
-            program.push_str(&load_params); // load the parameters.
+            let entry = ctx.new_bb("%entry");
+            ctx.set_bb(entry);
+
+            // parameters are in their own scope, outside the body.
+            ctx.scope.push_scope();
+            // 使用之后的参数, 我们先把参数和普通变量一样定义, 这样之后就不用区分了.
+            // 增加了overhead...
+            //     %x = alloc i32
+            //     store @x, %x
+            if let Some(v) = &node.params {
+                let values = ctx.func_data().params().to_vec();
+                for ((x, value), ty) in v.params.iter().zip(values).zip(&signature.params) {
+                    let alloc = ctx.new_value().alloc(ir_type(ty));
+                    ctx.push_inst(alloc);
+                    let store = ctx.new_value().store(value, alloc);
+                    ctx.push_inst(store);
+
+                    // add parameter to scope. And parameter is variable.
+                    match (x, ty) {
+                        (FuncFParam::Scalar(btype, ident, _), _) => {
+                            ctx.set_value_name(alloc, &format!("%{}", ident));
+                            ctx.scope.insert(ident, Symbol::Var(alloc, *btype));
+                        },
+                        (FuncFParam::Array(btype, ident, _, _), Type::Pointer(_, dims)) |
+                        (FuncFParam::Pointer(btype, ident, _), Type::Pointer(_, dims)) => {
+                            ctx.set_value_name(alloc, &format!("%{}", ident));
+                            ctx.scope.insert(ident, Symbol::Pointer(alloc, *btype, dims.clone()));
+                        },
+                        _ => panic!("Syntax error reached the generator."),
+                    }
+                }
+            }
 
             // get the body of the function.
-            let body = dfs(TreePoint::Block(&node.block), &scope, ret_val.size + 1);
-            program.push_str(&body.program);
+            dfs(TreePoint::Block(node.block.as_ref().unwrap()), ctx);
+            ctx.scope.pop_scope();
 
-            // supplement a final return value.
-            if ret_val.exp_res_id == -1 {
-                program.push_str(&format!("    ret 0\n"));
+            // supplement a final return value, 0 is also the bits of `0.0`.
+            let ret = if signature.ret != Type::Void {
+                let zero = ctx.integer(0);
+                ctx.new_value().ret(Some(zero))
             } else {
-                program.push_str(&format!("    ret\n"));
-            }
-            program.push_str("}\n\n\n");
-
-            return BodyRetType {
-                size: body.size,
-                program: program,
-                exp_res_id: bitset, // return the bitset to CompUnit, where functions were defined.
+                ctx.new_value().ret(None)
             };
+            ctx.push_inst(ret);
+            ctx.exit_function();
         },
 
-        // generate parameter.
-        TreePoint::FuncFParams(node) => {
-            let mut is_first = true;
-            let mut bitset = 0;
-            let mut bin = 1;
-            for x in &node.params {
-                // maybe pointer in the future????  yes.
-                match x {
-                    FuncFParam::Integer(ident) => {
-                        if is_first {
-                            program.push_str(&format!("@{}: i32", ident));
-                        } else {
-                            program.push_str(&format!(", @{}: i32", ident));
-                        }
-                    }
-                    FuncFParam::Array(ident, dims) => {
-                        let dim_pair = evaluate_dimension(&mut size, &dims, &scope);
-                        if dims.len() == 0 { // zero special judge.
-                            if is_first {
-                                program.push_str(&format!("@{}: *i32", ident));
-                            } else {
-                                program.push_str(&format!(", @{}: *i32", ident));
-                            }
-                        } else {
-                            if is_first {
-                                program.push_str(&format!("@{}: *{}", ident, dim_pair.1));
-                            } else {
-                                program.push_str(&format!(", @{}: *{}", ident, dim_pair.1));
-                            }
-                        }
-                        bitset |= bin; // this parame is 
-                    }
-                }
-                bin = bin << 1;
-                is_first = false;
-            }
-
-            return BodyRetType {
-                size: size,
-                program: program,
-                exp_res_id: bitset, // return this for function define use.
-            }
-        },
-
-        TreePoint::FuncType(node) => {
-            match node {
-                FuncType::Int => BodyRetType {
-                    size: size + 1,
-                    program: ": i32".to_string(),
-                    exp_res_id: -1,
-                },
-                FuncType::Void => BodyRetType {
-                    size: size + 1,
-                    program: "".to_string(),
-                    exp_res_id: -2,
-                },
-            }
-        },
-        
         // Block ::= "{" {BlockItem} "}";
         TreePoint::Block(node) => {
+            ctx.scope.push_scope();
             for item in &node.items { // enumerate the blocks in body.
-                // set the label.
-                let block = item.eval(&mut scope, size, false);
-                size = block.size;
-                program.push_str(&block.program);
+                item.eval(ctx);
             }
-            return BodyRetType {
-                size: size,
-                program: program,
-                exp_res_id: -1,
-            };
+            ctx.scope.pop_scope();
         },
     }
 }
 
 
-pub fn generator(start: CompUnit) -> String {
-    let size = 0;
-
-// extern variable我们需要手动定义一下.
-    let mut program = "
-decl @getint(): i32
-decl @getch(): i32
-decl @getarray(*i32): i32
-decl @putint(i32)
-decl @putch(i32)
-decl @putarray(i32, *i32)
-decl @starttime()
-decl @stoptime()\n\n\n\n".to_string();
-    let mut scope: HashMap<String, (i32, i32)> = HashMap::new();
-
-    // add std::functions to scope.
-    scope.insert("getint_function".to_string(), (VARIABLE_INT, 0));
-    scope.insert("getch_function".to_string(), (VARIABLE_INT, 0));
-    scope.insert("getarray_function".to_string(), (VARIABLE_INT + (1 << TYPE_BITS), 0));
-    scope.insert("putint_function".to_string(), (VOID, 1));
-    scope.insert("putch_function".to_string(), (VOID, 1));
-    scope.insert("putarray_function".to_string(), (VOID + (2 << TYPE_BITS), 1));
-    scope.insert("starttime_function".to_string(), (VOID, 1));
-    scope.insert("stoptime_function".to_string(), (VOID, 1));
-
-    let result = dfs(TreePoint::CompUnit(start), &scope, size);
-    program.push_str(&result.program);
-
-    return program;
-}
\ No newline at end of file
+// the tables are keyed by the first block of the comparisons.
+// `runtime` is the table of runtime functions, each becomes a `decl`.
+// `checked` is found by the semantic check: the `i32`s of the widest call to each variadic function, and the calls ordering the functions.
+// `timer_lines` are the lines of the calls to an undeclared `starttime()`/`stoptime()`.
+pub fn generator(start: CompUnit, runtime: &[(String, FunctionSignature)], checked: &Checked, timer_lines: HashMap<Span, i32>) -> (Program, HashMap<Function, FloatAbi>, HashMap<BasicBlock, JumpTable>) {
+    let mut ctx = Context::new();
+    ctx.timer_lines = timer_lines;
+
+    // extern variable我们需要手动定义一下, 同时加到 scope 里.
+    for (name, signature) in runtime {
+        // koopa has no `...`, a variadic function takes `i32`s up to its widest call.
+        //     decl @putf(*i32, i32, i32, i32)
+        let mut declared = signature.params.clone();
+        if signature.variadic {
+            let slots = checked.variadic_slots.get(name).copied().unwrap_or(declared.len());
+            declared.resize(slots, Type::Int);
+        }
+        let params = declared.iter().map(ir_type).collect();
+        let data = FunctionData::new_decl(format!("@{}", name), params, ir_type(&signature.ret));
+        let func = ctx.new_func(data, &FunctionSignature::new(declared, signature.ret.clone()));
+        ctx.scope.insert_function(name, func, signature.clone());
+    }
+    ctx.declare_intrinsics();
+
+    dfs(TreePoint::CompUnit(start, &checked.calls), &mut ctx);
+    ctx.remove_unused_intrinsics();
+
+    (ctx.program, ctx.float_abi, ctx.jump_tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use koopa::front::Driver;
+    use crate::semantic::Extensions;
+    use crate::testing::{errors, koopa, riscv, run};
+
+    // the text `-koopa` writes can be read back.
+    fn round_trip(text: &str) -> String {
+        let text = koopa(text, Extensions::default());
+        assert!(Driver::from(text.as_str()).generate_program().is_ok(), "{}", text);
+        text
+    }
+
+    #[test]
+    fn the_program_prints_as_text() {
+        let text = round_trip("int g[2] = {1};\nint f(int a[]) {\n  return a[1];\n}\nint main() {\n  int x = getint();\n\
+            if (x > 1) g[1] = x;\n  return f(g);\n}\n");
+        assert!(text.contains("global @g = alloc [i32, 2], {1, 0}\n"), "{}", text);
+        assert!(text.contains("decl @getint(): i32\n"), "{}", text);
+        assert!(text.contains("fun @f(@a: *i32): i32 {\n"), "{}", text);
+    }
+
+    #[test]
+    fn shadowed_names_get_their_own_storage() {
+        let text = koopa("int a = 1;\nint main() {\n  int a = 2;\n  {\n    int a = 3;\n    a = a + 1;\n  }\n  return a;\n}\n",
+            Extensions::default());
+        let lines: Vec<&str> = text.lines().map(str::trim).collect();
+        assert_eq!(lines.iter().filter(|line| line.contains("alloc i32")).count(), 3);
+        // `main` returns the `a` initialized to 2, the inner `a` is another alloc.
+        let outer = lines.iter().find_map(|line| line.strip_prefix("store 2, ")).unwrap();
+        let inner = lines.iter().find_map(|line| line.strip_prefix("store 3, ")).unwrap();
+        assert_ne!(outer, inner);
+        let ret = lines.iter().position(|line| line.starts_with("ret ")).unwrap();
+        assert!(lines[ret - 1].ends_with(&format!("= load {}", outer)), "{}", text);
+    }
+
+    #[test]
+    fn floats_run_through_the_intrinsics() {
+        let text = "int main() {\n  float x = 1.5;\n  float y = x * 3 + 0x1p-1;\n  int n = y / 2;\n\
+            if (y > 4.9 && n != 2.5) n = n + 10;\n  return n;\n}\n";
+        assert_eq!(run(text, Extensions::default(), &[]).0, 12);
+    }
+
+    #[test]
+    fn calls_before_the_definition() {
+        // `even` calls `odd` through its prototype, `main` calls `twice` defined after it.
+        let text = "int odd(int n);\nint even(int n) {\n  if (n == 0) return 1;\n  return odd(n - 1);\n}\n\
+            int odd(int n) {\n  if (n == 0) return 0;\n  return even(n - 1);\n}\n\
+            int main() {\n  return even(getint()) * 10 + twice(4);\n}\nint twice(int x) {\n  return x * 2;\n}\n";
+        assert_eq!(run(text, Extensions::default(), &[6]).0, 18);
+        assert_eq!(run(text, Extensions::default(), &[7]).0, 8);
+        let text = koopa("int f(int a[], float x);\nint main() {\n  return 0;\n}\n", Extensions::default());
+        assert!(text.contains("decl @f(*i32, i32): i32\n"), "{}", text); // a `float` is its bits.
+    }
+
+    #[test]
+    fn callees_are_printed_first() {
+        let text = round_trip("int f(int n);\nint main() { return f(3) + h(); }\nint h() { return 1; }\n\
+            int f(int n) { if (n == 0) return h(); return f(n - 1) * 2; }\n");
+        let position = |name: &str| text.find(&format!("fun @{}(", name)).unwrap();
+        assert!(position("h") < position("f") && position("f") < position("main"));
+    }
+
+    #[test]
+    fn mutual_recursion_needs_riscv() {
+        let text = "int odd(int n);\nint even(int n) {\n  if (n == 0) return 1;\n  return odd(n - 1);\n}\n\
+            int odd(int n) {\n  if (n == 0) return 0;\n  return even(n - 1);\n}\nint main() { return even(getint()); }\n";
+        let rendered = errors(text, Extensions::default());
+        assert!(rendered.contains("error: mutual recursion is not representable in Koopa text; use -riscv\n --> t.c:4:10\n"));
+        assert!(rendered.contains("^^^^^^^^^^ `even` calls back into `odd`\n"));
+        let asm = riscv(text, Extensions::default());
+        assert!(asm.contains("    call odd\n") && asm.contains("    call even\n"));
+        assert_eq!(run(text, Extensions::default(), &[7]).0, 0);
+    }
+
+    #[test]
+    fn prototype_without_definition_is_a_decl() {
+        let text = round_trip("int later(int a[], int n);\nvoid putint(int x);\nint main() { putint(1); return 0; }\n");
+        assert!(text.contains("decl @later(*i32, i32): i32\n"));
+        assert_eq!(text.matches("decl @putint(").count(), 1); // the runtime library is declared once.
+    }
+
+    #[test]
+    fn timers_take_the_line_without_include() {
+        let text = koopa("int main() {\n  starttime();\n\n  stoptime();\n  return 0;\n}\n", Extensions::default());
+        assert!(text.contains("decl @_sysy_starttime(i32)\n"));
+        assert!(text.contains("call @_sysy_starttime(2)\n"));
+        assert!(text.contains("call @_sysy_stoptime(4)\n"));
+    }
+
+    #[test]
+    fn declared_timers_are_ordinary_functions() {
+        let text = round_trip("int n = 0;\nvoid starttime() { n = n + 1; }\nint main() {\n  starttime();\n  stoptime();\n  return n;\n}\n");
+        assert!(text.contains("call @starttime()\n") && text.contains("call @_sysy_stoptime(5)\n"));
+        assert!(!text.contains("call @_sysy_starttime("));
+        // a variable hides the timer, calling it is an error.
+        let rendered = errors("int main() {\n  int stoptime = 1;\n  stoptime();\n  return stoptime;\n}\n", Extensions::default());
+        assert!(rendered.contains("error: call to undeclared function `stoptime`\n --> t.c:3:3\n"));
+    }
+
+    #[test]
+    fn only_used_intrinsics_are_declared() {
+        assert!(!round_trip("int main() { return 0; }\n").contains("@__sysy_"));
+        let text = round_trip("int main() { float f = 1.5; f = f * 2; return f; }\n");
+        assert_eq!(text.matches("decl @__sysy_").count(), 2);
+        assert!(text.contains("decl @__sysy_fmul(i32, i32): i32\n") && text.contains("decl @__sysy_ftoi(i32): i32\n"));
+        assert!(riscv("int main() { float f = 1.5; f = f * 2; return f; }\n", Extensions::default()).contains("fmul.s ft0, ft0, ft1\n"));
+    }
+}
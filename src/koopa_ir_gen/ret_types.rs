@@ -1,64 +1,66 @@
-pub const VOID: i32 = 0;
-pub const CONSTANT_INT: i32 = 1;
-pub const VARIABLE_INT: i32 = 2;
-pub const VARIABLE_ARRAY: i32 = 3;
-pub const PARAMETER_ARRAY: i32 = 4;
+use koopa::ir::Value;
 
-pub const TYPE_BITS: i32 = 3;
-
-/* 
+/*
  *定义了不同模块的返回类型, 比如
  */
 
 
 /*
- * ExpRetType中定义了对于expression的求值, 
+ * ExpRetType中定义了对于expression的求值,
  * 同时为了解决常量求值的问题, 增加is_constant field代表表达式的求值过程是否为constant.
- * 1. is_constant is true, exp_res_id 就是这个常量值, 2. is false, var{xp_res_id}存储了这个变量.
- * 
- * (size, program) 是必须有的系统常量, program就是代码的string表达.
+ * 1. is_constant is true, exp_res_id 就是这个常量值, 2. is false, value 就是存储结果的 koopa value.
+ *
+ * 常量不生成任何指令, 只有真正用到的时候才由 `get_value` 变成 koopa 的 integer.
+ * `void` 函数调用的结果既不是常量, 也没有 value.
+ * koopa 里没有 float, is_float 的值在 koopa 里是 i32, 存的是 float 的 bit pattern, 常量也一样.
  */
 // exp_res_id:
 //     if is_constant == true: store constant value.
-//     else                    store variable ID.
+//     else                    unused.
+#[derive(Clone, Copy)]
 pub struct ExpRetType {
-    pub size: i32,
-    pub program: String,
+    pub value: Option<Value>,
     pub exp_res_id: i32,
     pub is_constant: bool,
+    pub is_float: bool,
 }
 
-/* 
- * 这里主要对应于 `koopa_ir_gen/declare.rs` 中定义声明变量.
- * flag 没用删掉.
- */
-pub struct DeclRetType {
-    pub size: i32,
-    pub program: String,
-}
+impl ExpRetType {
+    pub fn constant(val: i32) -> ExpRetType {
+        ExpRetType { value: None, exp_res_id: val, is_constant: true, is_float: false }
+    }
 
+    pub fn float_constant(val: f32) -> ExpRetType {
+        ExpRetType { value: None, exp_res_id: val.to_bits() as i32, is_constant: true, is_float: true }
+    }
 
-/*
- * BodyRetType对应于 `koopa_ir_gen/mod.rs` 中程序主要body.
- * exp_res_id在大部分情况下没用, 但是当body是定义函数时, 我们需要区别array参数和integer参数.
- * 因此这里最多支持32个参数, 但是瓶颈是全局decl索引的HashMap需要3位标记decl类型, 但是这些都很容易扩展.
- */
-pub struct BodyRetType {
-    pub size: i32,
-    pub program: String,
-    pub exp_res_id: i32,
+    pub fn value(value: Value) -> ExpRetType {
+        ExpRetType { value: Some(value), exp_res_id: 0, is_constant: false, is_float: false }
+    }
+
+    pub fn float_value(value: Value) -> ExpRetType {
+        ExpRetType { value: Some(value), exp_res_id: 0, is_constant: false, is_float: true }
+    }
+
+    // the result of calling a `void` function.
+    pub fn none() -> ExpRetType {
+        ExpRetType { value: None, exp_res_id: -1, is_constant: false, is_float: false }
+    }
+
+    // the value of a `float` constant.
+    pub fn as_f32(&self) -> f32 {
+        f32::from_bits(self.exp_res_id as u32)
+    }
 }
 
 
-/* 
+/*
  * 这里主要对应于 `koopa_ir_gen/initialvalue.rs` 中的对于初始值的求值.
  * is_allzero对应了0初始化, if is_allzero is true, 我们就不需要val vector了.
- * 否则我们需要val vector中的每个值顺序对应初始化中的每个域. 
+ * 否则我们需要val vector中的每个值顺序对应初始化中的每个域.
  * val vector中包含了我们对于数组初始化 transfer后的结果, 还算是一个non-trivial的功能.
  */
 pub struct InitRetType {
-    pub size: i32,
-    pub program: String,
     pub is_allzero: bool,
-    pub val: Vec<(bool, i32)>,
-}
\ No newline at end of file
+    pub val: Vec<ExpRetType>,
+}
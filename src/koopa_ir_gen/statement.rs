@@ -1,32 +1,36 @@
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, Value};
 
+use super::condition::branch_to;
+use super::context::Context;
+use super::declare::DeclResult;
 
-use std::{collections::HashMap};
+use crate::koopa_ir_gen::{get_value, JumpTable};
+use crate::{koopa_ir_gen::expression::{binary_operation, convert, null_pointer, pointee, ExpResult}, ast::{*}};
+use crate::koopa_ir_gen::ret_types::ExpRetType;
 
-use crate::koopa_ir_gen::get_name;
-use crate::{koopa_ir_gen::expression::ExpResult, ast::{*}};
-
-use super::{TreePoint, ret_types::ExpRetType, dfs};
+use super::{TreePoint, dfs};
 
 /*
  * 这个文件里主要处理表达式原语.
- * 比如`if`, `if-else`, `while`等语句在这里处理. 
+ * 比如`if`, `if-else`, `while`等语句在这里处理.
  * 当然最普通的语句包含在Stmt中, 返回语句, 赋值语句等.
- * 值得一提的是对于`break`和`continue`的处理, 在低层次的语句中写入<replace_with_xxx>.
- *      在最高层直接用string的replace, 这样层层replace得到正确的结果, 并且极大的降低了编程复杂度.
- *      但是唯一的问题是时间复杂度的常数增加了, 多一个线性, 但是时间复杂度相同.
+ * 值得一提的是对于`break`和`continue`的处理, `while` 在生成循环体之前把 (条件, 循环之后) 两个 label
+ *      压进 ctx.loops, `break`和`continue` 直接跳到最内层循环对应的 label, 循环体结束之后再弹出.
+ *      `for` 压进去的是 (step, 循环之后), `continue` 之后还要执行 step; `do-while` 压的是 (条件, 循环之后).
+ *      跳转之后的指令都是不可达的, 放进一个新的 basic block 里, 这样每个 block 都只以一个跳转结束.
  */
 
 // statement: open_statement
 //          | closed_statement
-//          | "while" "(" Exp ")" Stmt
 impl Statement {
-    pub fn eval(&self, scope: &HashMap<String, (i32, i32)>, size: i32) -> ExpRetType {
+    pub fn eval(&self, ctx: &mut Context) {
         match self {
             Statement::Open(os) => {
-                os.eval(scope, size)
+                os.eval(ctx)
             },
             Statement::Closed(cs) => {
-                cs.eval(scope, size)
+                cs.eval(ctx)
             },
         }
     }
@@ -36,227 +40,529 @@ impl Statement {
 // open_statement: IF '(' expression ')' statement
 //               | IF '(' expression ')' closed_statement ELSE open_statement
 impl OpenStatement {
-    pub fn eval(&self, scope: &HashMap<String, (i32, i32)>, size: i32) -> ExpRetType {
-        let size = size;
-        let mut program = "".to_string();
+    pub fn eval(&self, ctx: &mut Context) {
         match self {
             OpenStatement::If(exp, stmt) => {
-                let exp_val = exp.eval(scope, size, false);
-                let stmt_val = stmt.eval(scope, exp_val.size);
-                let size = stmt_val.size + 1;
-                let name = get_name(exp_val.exp_res_id, exp_val.is_constant);
-
-                // evaluate the condition.
-                program.push_str(&exp_val.program);
-                program.push_str(&format!("    br {}, %entry_{}, %entry_{}\n", &name, size, size + 1));
+                let then_bb = ctx.new_bb("%if_then");
+                let end_bb = ctx.new_bb("%if_end");
 
-                program.push_str(&format!("\n%entry_{}:\n", size)); // body of statement.
-                program.push_str(&stmt_val.program);
-                program.push_str(&format!("    jump %entry_{}\n", size + 1));
+                // evaluate the condition, `&&` and `||` jump straight to the targets.
+                branch_to(ctx, exp, then_bb, end_bb);
 
-                program.push_str(&format!("\n%entry_{}:\n", size + 1)); // after.
+                ctx.set_bb(then_bb); // body of statement.
+                stmt.eval(ctx);
+                ctx.jump(end_bb);
 
-                return ExpRetType{size: size + 1, program, exp_res_id: 0, is_constant: false,};
+                ctx.set_bb(end_bb); // after.
             },
             OpenStatement::Ifelse(exp, cs, os) => {
-                let exp_val = exp.eval(scope, size, false);
-                let cs_val = cs.eval(scope, exp_val.size);
-                let os_val = os.eval(scope, cs_val.size);
-                let size = os_val.size + 1;
-                let name = get_name(exp_val.exp_res_id, exp_val.is_constant);
+                let then_bb = ctx.new_bb("%if_then");
+                let else_bb = ctx.new_bb("%if_else");
+                let end_bb = ctx.new_bb("%if_end");
 
-                // evaluate the condition.
-                program.push_str(&exp_val.program);
-                program.push_str(&format!("    br {}, %entry_{}, %entry_{}\n", &name, size, size + 1));
+                // evaluate the condition, `&&` and `||` jump straight to the targets.
+                branch_to(ctx, exp, then_bb, else_bb);
 
                 // first part.
-                program.push_str(&format!("\n%entry_{}:\n", size));
-                program.push_str(&cs_val.program);
-                program.push_str(&format!("    jump %entry_{}\n", size + 2));
-                
+                ctx.set_bb(then_bb);
+                cs.eval(ctx);
+                ctx.jump(end_bb);
+
                 // second part.
-                program.push_str(&format!("\n%entry_{}:\n", size + 1));
-                program.push_str(&os_val.program);
-                program.push_str(&format!("    jump %entry_{}\n", size + 2));
-                
-                // after.
-                program.push_str(&format!("\n%entry_{}:\n", size + 2));
+                ctx.set_bb(else_bb);
+                os.eval(ctx);
+                ctx.jump(end_bb);
 
-                return ExpRetType{size: size + 2, program, exp_res_id: 0, is_constant: false,};
+                // after.
+                ctx.set_bb(end_bb);
             },
-            OpenStatement::While(exp, stmt) => {
-                let exp_val = exp.eval(scope, size, false);
-                let stmt_val = stmt.eval(scope, exp_val.size);
-                let size = stmt_val.size + 1;
-                let name = get_name(exp_val.exp_res_id, exp_val.is_constant);
-
-                // replace `break` and `continue`.
-                let real_body = str::replace(&stmt_val.program, "<replace_me_with_break>", &format!("jump %entry_{}", size + 2));
-                let real_body = str::replace(&real_body, "<replace_me_with_continue>", &format!("jump %entry_{}", size));
+            OpenStatement::While(exp, stmt) => while_loop(ctx, exp, stmt),
+            OpenStatement::For(for_stmt) => for_stmt.eval(ctx),
+        }
+    }
+}
 
-                
-                // end last block and jump to condition.
-                program.push_str(&format!("    jump %entry_{}\n", size));
 
-                // condition label.
-                program.push_str(&format!("\n%entry_{}:\n", size));
-                program.push_str(&exp_val.program); // conditional jump.
-                program.push_str(&format!("    br {}, %entry_{}, %entry_{}\n", &name, size + 1, size + 2));
 
-                // body label.
-                program.push_str(&format!("\n%entry_{}:\n", size + 1));
-                program.push_str(&real_body);
-                program.push_str(&format!("    jump %entry_{}\n", size));
+// closed_statement: non_if_statement
+//                 | IF '(' expression ')' closed_statement ELSE closed_statement
+impl ClosedStatement {
+    pub fn eval(&self, ctx: &mut Context) {
+        match self {
+            ClosedStatement::Stmt(stmt) => {
+                stmt.eval(ctx)
+            },
+            ClosedStatement::Ifelse(exp, cs1, cs2) => {
+                let then_bb = ctx.new_bb("%if_then");
+                let else_bb = ctx.new_bb("%if_else");
+                let end_bb = ctx.new_bb("%if_end");
 
+                // evaluate the condition, `&&` and `||` jump straight to the targets.
+                branch_to(ctx, exp, then_bb, else_bb);
 
-                // after `while`.
-                program.push_str(&format!("\n%entry_{}:\n", size + 2));
+                ctx.set_bb(then_bb); // first part.
+                cs1.eval(ctx);
+                ctx.jump(end_bb);
 
+                ctx.set_bb(else_bb); // second part.
+                cs2.eval(ctx);
+                ctx.jump(end_bb);
 
-                return ExpRetType{size: size + 2, program, exp_res_id: 0, is_constant: false,};
+                ctx.set_bb(end_bb); // after.
             },
+            ClosedStatement::While(exp, stmt) => while_loop(ctx, exp, stmt),
+            ClosedStatement::For(for_stmt) => for_stmt.eval(ctx),
         }
     }
 }
 
 
+// "while" "(" Exp ")" Statement, the open and the closed one are the same here.
+fn while_loop(ctx: &mut Context, exp: &Exp, stmt: &Statement) {
+    let cond_bb = ctx.new_bb("%while_cond");
+    let body_bb = ctx.new_bb("%while_body");
+    let end_bb = ctx.new_bb("%while_end");
 
-// closed_statement: non_if_statement
-//                 | IF '(' expression ')' closed_statement ELSE closed_statement
-impl ClosedStatement {
-    pub fn eval(&self, scope: &HashMap<String, (i32, i32)>, size: i32) -> ExpRetType {
-        let mut program = "".to_string();
-        match self {
-            ClosedStatement::Stmt(stmt) => {
-                stmt.eval(scope, size)
-            },
-            ClosedStatement::Ifelse(exp, cs1, cs2) => {
+    // end last block and jump to condition.
+    ctx.jump(cond_bb);
 
-                let exp_val = exp.eval(scope, size, false);
-                let cs1_val = cs1.eval(scope, exp_val.size);
-                let cs2_val = cs2.eval(scope, cs1_val.size);
-                let size = cs2_val.size + 1;
-                let name = get_name(exp_val.exp_res_id, exp_val.is_constant);
+    // condition label.
+    ctx.set_bb(cond_bb);
+    branch_to(ctx, exp, body_bb, end_bb);
 
-                // evaluate the condition.
-                program.push_str(&exp_val.program);
-                program.push_str(&format!("    br {}, %entry_{}, %entry_{}\n", &name, size, size + 1));
+    // body label.
+    ctx.set_bb(body_bb);
+    ctx.loops.push((cond_bb, end_bb)); // `continue` re-checks the condition.
+    stmt.eval(ctx);
+    ctx.loops.pop();
+    ctx.jump(cond_bb);
 
-                program.push_str(&format!("\n%entry_{}:\n", size)); // first part.
-                program.push_str(&cs1_val.program);
-                program.push_str(&format!("    jump %entry_{}\n", size + 2));
+    // after `while`.
+    ctx.set_bb(end_bb);
+}
 
-                program.push_str(&format!("\n%entry_{}:\n", size + 1)); // second part.
-                program.push_str(&cs2_val.program);
-                program.push_str(&format!("    jump %entry_{}\n", size + 2));
+// ForHead ::= "for" "(" ForInit [Exp] ";" [SimpleStmt] ")";
+impl ForStmt {
+    pub fn eval(&self, ctx: &mut Context) {
+        // a name declared in the init lives until the end of the loop.
+        ctx.scope.push_scope();
+        match &self.init {
+            ForInit::Decl(decl) => decl.eval(ctx),
+            ForInit::Stmt(stmt) => stmt.eval(ctx),
+        }
 
-                program.push_str(&format!("\n%entry_{}:\n", size + 2)); // after.
+        let cond_bb = ctx.new_bb("%for_cond");
+        let body_bb = ctx.new_bb("%for_body");
+        let step_bb = ctx.new_bb("%for_step");
+        let end_bb = ctx.new_bb("%for_end");
+        ctx.jump(cond_bb);
+
+        // no condition loops forever.
+        ctx.set_bb(cond_bb);
+        match &self.cond {
+            Some(exp) => branch_to(ctx, exp, body_bb, end_bb),
+            None => ctx.jump(body_bb),
+        }
 
-                return ExpRetType{size: size + 2, program, exp_res_id: 0, is_constant: false,};
+        ctx.set_bb(body_bb);
+        ctx.loops.push((step_bb, end_bb)); // `continue` still runs the step.
+        self.body.eval(ctx);
+        ctx.loops.pop();
+        ctx.jump(step_bb);
+
+        ctx.set_bb(step_bb);
+        if let Some(step) = &self.step {
+            step.eval(ctx);
+        }
+        ctx.jump(cond_bb);
+
+        ctx.set_bb(end_bb);
+        ctx.scope.pop_scope();
+    }
+}
+
+
+
+/*
+ * `switch`: 每个 label 一个 block, case 的值都是常量. 先跳到对应的 label, 再按源码顺序生成 body,
+ * 遇到 label 就 fallthrough 进下一个 block. `break` 跳到 %switch_end.
+ * 跳到哪个 label 用比较树决定 (对排好序的值二分), koopa 里没有间接跳转.
+ * label 足够密的时候比较树放在单独的 %switch_table 里, 记一个 `JumpTable`, 后端直接查表跳过去.
+ */
+// "switch" "(" Exp ")" "{" {SwitchItem} "}"
+fn switch(ctx: &mut Context, exp: &Exp, items: &[SwitchItem]) {
+    let ret_val = exp.eval(ctx);
+    let end_bb = ctx.new_bb("%switch_end");
+
+    let mut cases = Vec::new();
+    let mut default_bb = end_bb; // no `default` leaves the `switch`.
+    let mut labels = Vec::new();
+    for item in items {
+        labels.push(match item {
+            SwitchItem::Case(exp, _) => {
+                let value = exp.eval(ctx);
+                assert!(value.is_constant);
+                let bb = ctx.new_bb("%case");
+                cases.push((value.exp_res_id, bb));
+                Some(bb)
+            },
+            SwitchItem::Default(_) => {
+                default_bb = ctx.new_bb("%default");
+                Some(default_bb)
+            },
+            SwitchItem::Item(_) => None,
+        });
+    }
+    cases.sort_by_key(|case| case.0);
+
+    if ret_val.is_constant {
+        let target = cases.iter().find(|case| case.0 == ret_val.exp_res_id).map_or(default_bb, |case| case.1);
+        ctx.jump(target);
+    } else {
+        dispatch(ctx, ret_val.value.unwrap(), &cases, default_bb);
+    }
+
+    // the code before the first label is never executed.
+    let body_bb = ctx.new_bb("%switch_body");
+    ctx.set_bb(body_bb);
+    // `continue` outside a loop is rejected by the semantic check, `end_bb` is never used for it.
+    let continue_target = ctx.loops.last().map_or(end_bb, |targets| targets.0);
+    ctx.loops.push((continue_target, end_bb));
+    ctx.scope.push_scope();
+    for (item, label) in items.iter().zip(labels) {
+        match item {
+            SwitchItem::Item(item) => item.eval(ctx),
+            _ => { // fallthrough.
+                let bb = label.unwrap();
+                ctx.jump(bb);
+                ctx.set_bb(bb);
             },
         }
     }
+    ctx.scope.pop_scope();
+    ctx.loops.pop();
+    ctx.jump(end_bb);
+
+    ctx.set_bb(end_bb);
+}
+
+// at least 4 labels, and at least one of 3 values in the range has one.
+fn is_dense(cases: &[(i32, BasicBlock)]) -> bool {
+    if cases.len() < 4 {
+        return false;
+    }
+    let range = cases[cases.len() - 1].0 as i64 - cases[0].0 as i64 + 1;
+    range <= 3 * cases.len() as i64
 }
 
+// jump to the label of `val`, `cases` are sorted.
+fn dispatch(ctx: &mut Context, val: Value, cases: &[(i32, BasicBlock)], default_bb: BasicBlock) {
+    if !is_dense(cases) {
+        compare_tree(ctx, val, cases, default_bb, &mut Vec::new());
+        return;
+    }
+
+    let head = ctx.new_bb("%switch_table");
+    ctx.jump(head);
+    ctx.set_bb(head);
+    let mut blocks = Vec::new();
+    compare_tree(ctx, val, cases, default_bb, &mut blocks);
+
+    let low = cases[0].0;
+    let high = cases[cases.len() - 1].0;
+    let mut targets = vec![default_bb; (high as i64 - low as i64 + 1) as usize];
+    for &(value, bb) in cases {
+        targets[(value as i64 - low as i64) as usize] = bb;
+    }
+    ctx.jump_tables.insert(head, JumpTable { value: val, low, targets, default: default_bb, blocks });
+}
 
+// binary search, the last few are compared one by one. the new blocks are collected in `blocks`.
+//     %0 = lt %val, 8
+//     br %0, %switch_lt, %switch_ge
+fn compare_tree(ctx: &mut Context, val: Value, cases: &[(i32, BasicBlock)], default_bb: BasicBlock, blocks: &mut Vec<BasicBlock>) {
+    if cases.len() > 3 {
+        let mid = cases.len() / 2;
+        let lt_bb = ctx.new_bb("%switch_lt");
+        let ge_bb = ctx.new_bb("%switch_ge");
+        blocks.extend([lt_bb, ge_bb]);
+
+        let pivot = ctx.integer(cases[mid].0);
+        let lt = ctx.new_value().binary(BinaryOp::Lt, val, pivot);
+        ctx.push_inst(lt);
+        ctx.branch(lt, lt_bb, ge_bb);
+
+        ctx.set_bb(lt_bb);
+        compare_tree(ctx, val, &cases[..mid], default_bb, blocks);
+        ctx.set_bb(ge_bb);
+        compare_tree(ctx, val, &cases[mid..], default_bb, blocks);
+        return;
+    }
+
+    for (i, &(value, bb)) in cases.iter().enumerate() {
+        let last = i + 1 == cases.len();
+        let next_bb = if last { default_bb } else { ctx.new_bb("%switch_next") };
+        let value = ctx.integer(value);
+        let eq = ctx.new_value().binary(BinaryOp::Eq, val, value);
+        ctx.push_inst(eq);
+        ctx.branch(eq, bb, next_bb);
+        if !last {
+            blocks.push(next_bb);
+            ctx.set_bb(next_bb);
+        }
+    }
+    if cases.is_empty() {
+        ctx.jump(default_bb);
+    }
+}
+
+// `a[f()] += x`, the address is evaluated once, `f` is called once.
+// no `exp` is `++`/`--`, the operand is 1.
+fn compound_assign(ctx: &mut Context, lval: &LVal, op: &str, exp: Option<&Exp>) {
+    let var = lval.eval(ctx);
+    assert!(!var.is_constant); // must be variable.
+    let ptr = var.value.unwrap();
+
+    // %1 = load %ptr
+    let load = ctx.new_value().load(ptr);
+    ctx.push_inst(load);
+    let old = ExpRetType { is_float: var.is_float, ..ExpRetType::value(load) };
+    let rhs = match exp {
+        Some(exp) => exp.eval(ctx),
+        None => ExpRetType::constant(1),
+    };
+
+    // the same as `a = a op x`, converted back to the type of `a`.
+    let ret_val = binary_operation(ctx, op, &old, &rhs);
+    let ret_val = convert(ctx, &ret_val, var.is_float);
+    let val = get_value(ctx, &ret_val);
+    let store = ctx.new_value().store(val, ptr);
+    ctx.push_inst(store);
+}
 
 impl Stmt {
-    pub fn eval(&self, scope: &HashMap<String, (i32, i32)>, size: i32) -> ExpRetType {
+    pub fn eval(&self, ctx: &mut Context) {
         // Stmt ::= LVal "=" Exp ";"| "return" Exp ";";
-        let mut size = size;
-        let mut program = "".to_string();
         match self {
             Stmt::LvalExp(lval, exp) => {
-                // query the scope to find variable id, and change it.
-                let var = lval.eval(scope, size, false); // assignment must be `int` variable.
-                let ret_val = exp.eval(scope, var.size, false);
-                let name = get_name(ret_val.exp_res_id, ret_val.is_constant);
-                size = ret_val.size;
-
-                assert!(var.is_constant == false); // must be variable.
-                program.push_str(&var.program);
-                program.push_str(&ret_val.program);
-
-                if lval.exps.len() == 0 {
-                    // store %1, @x
-                    program.push_str(&format!("    store {}, @var_{}\n", name, var.exp_res_id));
+                // query the scope to find variable address, and change it.
+                let var = lval.eval(ctx); // assignment must be `int`, `float` or pointer variable.
+                assert!(!var.is_constant); // must be variable.
+                let ret_val = exp.eval(ctx);
+                let ty = pointee(ctx, var.value.unwrap());
+                let val = if ret_val.is_constant && matches!(ty.kind(), koopa::ir::TypeKind::Pointer(_)) { // `p = 0`.
+                    null_pointer(ctx, ty)
                 } else {
-                    // store %1, @x
-                    program.push_str(&format!("    store {}, %var_{}\n", name, var.exp_res_id));
-                }
+                    let ret_val = convert(ctx, &ret_val, var.is_float);
+                    get_value(ctx, &ret_val)
+                };
 
-                return ExpRetType {
-                    size: size,
-                    program: program,
-                    exp_res_id: 0,
-                    is_constant: false,
-                }
+                // store %1, @x
+                let store = ctx.new_value().store(val, var.value.unwrap());
+                ctx.push_inst(store);
             },
-            Stmt::RetExp(exp) => {
-                let instrs = exp.eval(scope, size, false);
-                let name = get_name(instrs.exp_res_id, instrs.is_constant);
-                // println!("asd123www: {}", instrs.exp_res_id);
-
-                program.push_str(&instrs.program);
-                program.push_str(&format!("    ret {}\n", &name));
-                program.push_str(&format!("\n%entry_{}:\n", instrs.size + 1));
-
-                return ExpRetType {
-                    size: instrs.size + 1,
-                    program: program,
-                    exp_res_id: 0, // return stmt => -2;
-                    is_constant: false,
-                }
+            Stmt::CompoundAssign(lval, op, exp) => {
+                compound_assign(ctx, lval, op, Some(exp));
             },
-            Stmt::RetNone() => {
-                program.push_str(&format!("    ret \n",));
-                program.push_str(&format!("\n%entry_{}:\n", size + 1));
-
-                return ExpRetType {
-                    size: size + 1,
-                    program: program,
-                    exp_res_id: 0, // return stmt => -2;
-                    is_constant: false,
-                }
+            Stmt::Increment(lval, op) => {
+                compound_assign(ctx, lval, op, None);
+            },
+            Stmt::RetExp(exp, _) => {
+                let instrs = exp.eval(ctx);
+                let float = ctx.returns_float();
+                let instrs = convert(ctx, &instrs, float);
+                let val = get_value(ctx, &instrs);
+
+                let ret = ctx.new_value().ret(Some(val));
+                ctx.push_inst(ret);
+                let next = ctx.new_bb("%after_ret");
+                ctx.set_bb(next);
+            },
+            Stmt::RetNone(_) => {
+                let ret = ctx.new_value().ret(None);
+                ctx.push_inst(ret);
+                let next = ctx.new_bb("%after_ret");
+                ctx.set_bb(next);
             },
             Stmt::SingleExp(exp) => {
-                let ret_val = exp.eval(scope, size, false);
-                ret_val
+                exp.eval(ctx);
             },
             Stmt::Block(block) => {
-                let ret_val = dfs(TreePoint::Block(block), &scope, size);
-
-                return ExpRetType {
-                    size: ret_val.size,
-                    program: ret_val.program,
-                    exp_res_id: ret_val.exp_res_id, // return stmt => -2;
-                    is_constant: false,
-                };
+                dfs(TreePoint::Block(block), ctx);
             },
-            Stmt::ZeroExp() => {
-                ExpRetType {
-                    size, program, exp_res_id: 0, 
-                    is_constant: false,
-                }
+            Stmt::ZeroExp() => {},
+            Stmt::BreakKeyWord(_) => { // jump to the end of the innermost loop.
+                let (_, break_target) = *ctx.loops.last().expect("`break` outside a loop reached the generator.");
+                ctx.jump(break_target);
+                let next = ctx.new_bb("%after_break");
+                ctx.set_bb(next);
             },
-            Stmt::BreakKeyWord() => { // give `while` a hint, it'll replace it with `jump`.
-                program.push_str("    <replace_me_with_break>\n");
-                program.push_str(&format!("\n%entry_{}:\n", size + 1));
-                ExpRetType {
-                    size: size + 1, program, exp_res_id: 0, 
-                    is_constant: false,
-                }
+            Stmt::ContinueKeyWord(_) => {
+                let (continue_target, _) = *ctx.loops.last().expect("`continue` outside a loop reached the generator.");
+                ctx.jump(continue_target);
+                let next = ctx.new_bb("%after_continue");
+                ctx.set_bb(next);
+            },
+            Stmt::DoWhile(stmt, exp) => {
+                let body_bb = ctx.new_bb("%do_body");
+                let cond_bb = ctx.new_bb("%do_cond");
+                let end_bb = ctx.new_bb("%do_end");
+                ctx.jump(body_bb);
+
+                // the body runs once before the condition.
+                ctx.set_bb(body_bb);
+                ctx.loops.push((cond_bb, end_bb));
+                stmt.eval(ctx);
+                ctx.loops.pop();
+                ctx.jump(cond_bb);
+
+                ctx.set_bb(cond_bb);
+                branch_to(ctx, exp, body_bb, end_bb);
+
+                ctx.set_bb(end_bb);
             },
-            Stmt::ContinueKeyWord() => {
-                program.push_str("    <replace_me_with_continue>\n");
-                program.push_str(&format!("\n%entry_{}:\n", size + 1));
-                ExpRetType {
-                    size: size + 1, 
-                    program, 
-                    exp_res_id: 0,
-                    is_constant: false,
+            Stmt::Switch(exp, items) => switch(ctx, exp, items),
+            Stmt::Error() => panic!("Syntax error reached the generator."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::driver::Target;
+    use crate::semantic::Extensions;
+    use crate::testing::{errors, koopa, output, riscv, run};
+
+    // `switch (getint()) { case v: return i; ... }` for the `i`th value, -1 for the default.
+    fn program(values: &[i32]) -> String {
+        let cases: String = values.iter().enumerate().map(|(i, value)| format!("    case {}: return {};\n", value, i)).collect();
+        format!("int main() {{\n  switch (getint()) {{\n{}  }}\n  return -1;\n}}\n", cases)
+    }
+
+    // every value goes to its case and its neighbours to the default, returns whether there is a jump table.
+    fn dispatch(values: &[i32]) -> bool {
+        let text = program(values);
+        for (i, &value) in values.iter().enumerate() {
+            assert_eq!(run(&text, Extensions::default(), &[value]).0, i as i32, "{}", value);
+            for other in [value.wrapping_sub(1), value.wrapping_add(1)] {
+                if !values.contains(&other) {
+                    assert_eq!(run(&text, Extensions::default(), &[other]).0, -1, "{}", other);
                 }
             }
         }
+        let tables: Vec<_> = output(&text, Extensions::default(), Target::Koopa).jump_tables.into_values().collect();
+        if let [table] = tables.as_slice() {
+            let range = values.iter().max().unwrap().wrapping_sub(*values.iter().min().unwrap()) as usize + 1;
+            assert_eq!((table.low, table.targets.len()), (*values.iter().min().unwrap(), range));
+            assert_eq!(table.targets.iter().filter(|bb| **bb == table.default).count(), range - values.len());
+        }
+        tables.len() == 1
+    }
+
+    #[test]
+    fn dense_needs_four_cases() {
+        assert!(!dispatch(&[1, 2, 3]));
+        assert!(dispatch(&[3, 1, 2, 0]));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn dense_range_is_at_most_three_times_the_cases() {
+        assert!(dispatch(&[0, 1, 2, 11]));
+        assert!(!dispatch(&[0, 1, 2, 12]));
+        assert!(dispatch(&[-6, -5, -4, 5]));
+        assert!(!dispatch(&[-20, -10, 0, 10, 20]));
+    }
+
+    #[test]
+    fn extreme_labels() {
+        assert!(dispatch(&[i32::MIN, i32::MIN + 1, i32::MIN + 2, i32::MIN + 3]));
+        assert!(dispatch(&[i32::MAX - 3, i32::MAX - 2, i32::MAX - 1, i32::MAX]));
+        assert!(!dispatch(&[i32::MIN, -1, 0, 1, i32::MAX])); // the range doesn't fit in `i32`.
+    }
+
+    #[test]
+    fn default_anywhere_and_fallthrough() {
+        let text = "int main() {\n  int r = 0;\n  switch (getint()) {\n    case 1: r = 1;\n    default: r = r + 10;\n\
+            case 2: r = r + 2; break;\n    case 3: r = 3;\n    case 4: r = r + 4;\n  }\n  return r;\n}\n";
+        let results: Vec<i32> = [1, 2, 3, 4, 5].iter().map(|x| run(text, Extensions::default(), &[*x]).0).collect();
+        assert_eq!(results, [13, 2, 7, 4, 12]);
+        // no default leaves the switch, a constant scrutinee jumps straight to its label.
+        assert_eq!(run("int main() { switch (getint()) { case 1: return 1; } return 2; }", Extensions::default(), &[5]).0, 2);
+        assert_eq!(run("int main() { switch (3) { case 1: return 1; default: return 4; case 3: return 3; } }", Extensions::default(), &[]).0, 3);
+    }
+
+    #[test]
+    fn case_jumps_over_a_declaration() {
+        // `case 2` skips the declarations, the allocs are in the entry block and dominate it, only the stores are skipped.
+        let text = "int main() {\n  int r = 0;\n  switch (getint()) {\n    case 1:;\n      int y = 5, a[2] = {getint(), 7};\n\
+            r = y + a[0];\n    case 2:\n      y = 3;\n      a[1] = 4;\n      r = r + y * 10 + a[1];\n  }\n  return r;\n}\n";
+        assert_eq!(run(text, Extensions::default(), &[1, 100]).0, 139);
+        assert_eq!(run(text, Extensions::default(), &[2]).0, 34);
+        let ir = koopa(text, Extensions::default());
+        let entry = &ir[ir.find("%entry:\n").unwrap()..ir.find("\n\n%").unwrap()];
+        assert!(entry.contains("@y = alloc i32\n") && entry.contains("@a = alloc [i32, 2]\n"));
+        assert!(riscv(text, Extensions::default()).contains("main:\n"));
+    }
+
+    #[test]
+    fn jump_table_in_rodata() {
+        let asm = riscv(&program(&[10, 11, 12, 14]), Extensions::default());
+        assert!(asm.contains("    li t2, 10\n    sub t1, t1, t2\n    li t2, 5\n    bgeu t1, t2, "));
+        assert!(asm.contains("    lw t2, 0(t2)\n    jr t2\n"));
+        let table = &asm[asm.find("    .section .rodata\n    .p2align 2\n").unwrap()..];
+        assert_eq!(table.lines().filter(|line| line.starts_with("    .word ")).take(5).count(), 5);
+        assert!(!riscv(&program(&[1, 2, 3]), Extensions::default()).contains("jr t2"));
+    }
+
+    #[test]
+    fn break_and_continue_take_the_innermost_loop() {
+        // `break` leaves only the inner loop, `continue` checks the condition of its own loop.
+        let text = "int main() {\n  int s = 0;\n  int i = 0;\n  while (i < 4) {\n    int j = 0;\n\
+            while (1) {\n      j = j + 1;\n      if (j == 2) continue;\n      if (j > 3) break;\n      s = s + 10;\n    }\n\
+            i = i + 1;\n    if (i == 2) continue;\n    s = s + 1;\n  }\n  return s;\n}\n";
+        assert_eq!(run(text, Extensions::default(), &[]).0, 4 * 20 + 3);
+        let ir = koopa(text, Extensions::default());
+        assert!(ir.contains("%if_then:\n  jump %while_cond_0\n"));
+        assert!(ir.contains("%if_then_0:\n  jump %while_end_0\n"));
+        assert!(ir.contains("%if_then_1:\n  jump %while_cond\n"));
+    }
+
+    #[test]
+    fn break_and_continue_outside_a_loop() {
+        let rendered = errors("int main() {\n  if (1) break;\n  { continue; }\n  return 0;\n}\n", Extensions::default());
+        assert!(rendered.contains("error: `break` statement not within a loop or switch\n --> t.c:2:10\n"));
+        assert!(rendered.contains("error: `continue` statement not within a loop\n --> t.c:3:5\n"));
+    }
+
+    #[test]
+    fn continue_in_for_goes_to_the_step() {
+        let text = "int main() {\n  int s = 0;\n  for (int i = 0; i < 10; i = i + 1) {\n    if (i % 2) continue;\n    s = s + i;\n  }\n\
+            int j = 0;\n  do {\n    j = j + 1;\n    if (j < 3) continue;\n    s = s + 100;\n  } while (j < 5);\n  return s;\n}\n";
+        assert_eq!(run(text, Extensions::default(), &[]).0, 320);
+        let ir = koopa(text, Extensions::default());
+        assert!(ir.contains("%if_then:\n  jump %for_step\n"));
+        assert!(ir.contains("%for_step:\n  %7 = load @i\n  %8 = add %7, 1\n  store %8, @i\n  jump %for_cond\n"));
+        assert!(ir.contains("%if_then_0:\n  jump %do_cond\n"));
+        assert!(ir.contains("  br %16, %do_body, %do_end\n"));
+    }
+
+    #[test]
+    fn for_scope_and_dangling_else() {
+        // the init declaration is gone after the loop, the `else` belongs to the `if` in the body.
+        let text = "int main() {\n  int i = 7, r = 0;\n  for (int i = 0; i < 3; i = i + 1) if (i == 1) r = r + 10; else r = r + 1;\n\
+            for (;;) { r = r + 100; break; }\n  return r + i * 1000;\n}\n";
+        assert_eq!(run(text, Extensions::default(), &[]).0, 7112);
+    }
+
+    #[test]
+    fn compound_assignment_computes_the_address_once() {
+        let text = "int n = 0;\nint f() {\n  n = n + 1;\n  return 1;\n}\nint main() {\n  int a[3] = {}, i = 0;\n\
+            a[i] += 5; i++;\n  a[f()] *= 2;\n  ++i; i--;\n  for (i = 0; i < 3; i++) a[i] -= 1;\n\
+            return a[0] * 100 + i * 10 + n;\n}\n";
+        assert_eq!(run(text, Extensions::default(), &[]).0, 431);
+        let ir = koopa(text, Extensions::default());
+        assert_eq!(ir.matches("call @f()").count(), 1);
+        assert!(ir.contains("  %8 = call @f()\n  %9 = getelemptr @a, %8\n  %10 = load %9\n  %11 = mul %10, 2\n  store %11, %9\n"));
+        assert!(ir.contains("%for_step:\n  %22 = load @i\n  %23 = add %22, 1\n  store %23, @i\n"));
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use koopa::ir::{Function, Value};
+use crate::ast::BType;
+use crate::semantic::FunctionSignature;
+
+/*
+ * 符号表: 每个 block 一层 HashMap, 进入 block 的时候 push, 离开的时候 pop.
+ * 查找从最里层往外找, 里层的定义自然就遮住了外层的同名定义.
+ * 函数只能定义在最外层, 所以函数签名放在第 0 层, 查函数的时候只看这一层.
+ *
+ * 变量和数组里的 Value 是它的 alloc (局部) 或者 global alloc (全局), 也就是它的地址.
+ * 指针变量和数组参数一样, alloc 里存的是一个地址, 要先 load 出来才能用.
+ * koopa 里 `float` 也是 i32, 所以每个符号都记下自己 (或者元素) 是 `int` 还是 `float`.
+ * struct 变量和 struct 的数组记下 struct 的名字, 布局在 ctx.structs 里.
+ */
+pub enum Symbol {
+    Const(BType, i32), // the value itself, no storage. a `float` is kept as its bits.
+    Var(Value, BType), // local or global variable.
+    Array(Value, BType, Vec<i32>), // local or global array, with all the dimensions.
+    ConstArray(Value, BType, Vec<i32>, Vec<i32>), // stored like an array, the flattened values are for constant indices.
+    Pointer(Value, BType, Vec<i32>), // a pointer variable or an array parameter, with the dimensions after the first `[]`.
+    Struct(Value, String, Vec<i32>), // a struct variable or an array of structs, with all the dimensions.
+    Function(Function, FunctionSignature),
+}
+
+pub struct SymbolTable {
+    scopes: Vec<HashMap<String, Symbol>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable { scopes: vec![HashMap::new()] }
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+        assert!(!self.scopes.is_empty()); // the global scope is never popped.
+    }
+
+    // define a name in the innermost scope.
+    pub fn insert(&mut self, name: &str, symbol: Symbol) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), symbol);
+    }
+
+    pub fn insert_function(&mut self, name: &str, func: Function, signature: FunctionSignature) {
+        self.scopes[0].insert(name.to_string(), Symbol::Function(func, signature));
+    }
+
+    // the semantic check guarantees every name is defined before use.
+    pub fn get(&self, name: &str) -> &Symbol {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).unwrap()
+    }
+
+    pub fn contains_function(&self, name: &str) -> bool {
+        matches!(self.scopes[0].get(name), Some(Symbol::Function(_, _)))
+    }
+
+    pub fn get_function(&self, name: &str) -> (Function, &FunctionSignature) {
+        match self.scopes[0].get(name) {
+            Some(Symbol::Function(func, signature)) => (*func, signature),
+            _ => panic!("No function named this."),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+/*
+ * 字面量里 rust 标准库处理不了的部分, 由 `sysy.lalrpop` 调用.
+ * 十六进制浮点数 `0x1.8p3` = 0x18 * 2^(3 - 4), 尾数的每一位十六进制小数是 4 个 bit.
+ */
+
+// an integer literal, `Err` with its value if `int` can't hold it. like C, a decimal one has to fit in `int`,
+// an octal or hexadecimal one in `unsigned int`, whose bits `int` keeps: `0xffffffff` is -1.
+pub fn int_literal(digits: &str, radix: u32) -> Result<i32, u64> {
+    let value = digits.chars().fold(0u64, |acc, c| {
+        acc.saturating_mul(radix as u64).saturating_add(c.to_digit(radix).unwrap() as u64)
+    });
+    let max = if radix == 10 { i32::MAX as u64 } else { u32::MAX as u64 };
+    if value <= max {
+        return Ok(value as u32 as i32);
+    }
+    Err(value)
+}
+
+// `0x1.8p3`, the lexer guarantees the shape.
+// the exponent saturates like the digits of `int_literal`, a huge one is an infinity or 0 as `"1e99999"` is.
+pub fn hex_float(text: &str) -> f32 {
+    let text = &text[2..];
+    let p = text.find(['p', 'P']).unwrap();
+    let digits = &text[..p];
+    let (negative, exp_digits) = match text[p + 1..].strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text[p + 1..].trim_start_matches('+')),
+    };
+    let exp = exp_digits.chars().fold(0i32, |acc, c| (acc * 10 + c.to_digit(10).unwrap() as i32).min(1 << 20));
+    let exp = if negative { -exp } else { exp };
+
+    let mut mantissa: u64 = 0;
+    let mut shift = exp;
+    let mut after_point = false;
+    for c in digits.chars() {
+        if c == '.' {
+            after_point = true;
+            continue;
+        }
+        if mantissa >> 56 != 0 { // more digits than a double keeps, only the position counts.
+            if !after_point {
+                shift += 4;
+            }
+            continue;
+        }
+        mantissa = mantissa * 16 + c.to_digit(16).unwrap() as u64;
+        if after_point {
+            shift -= 4;
+        }
+    }
+    if mantissa == 0 {
+        return 0.0;
+    }
+    (mantissa as f64 * 2f64.powi(shift)) as f32
+}
+
+// the bytes between the quotes of a string or a character literal, with C's escapes.
+// `\ooo` takes at most 3 octal digits, `\x` all the hex digits after it, an unknown escape is the character itself.
+fn unescape(body: &str) -> Vec<u8> {
+    let bytes = body.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            result.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        let c = bytes[i];
+        i += 1;
+        result.push(match c {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'v' => 0x0b,
+            b'0'..=b'7' => {
+                let mut value = (c - b'0') as u32;
+                for _ in 0..2 {
+                    match bytes.get(i) {
+                        Some(d @ b'0'..=b'7') => value = value * 8 + (d - b'0') as u32,
+                        _ => break,
+                    }
+                    i += 1;
+                }
+                value as u8 // `\777` keeps the low 8 bits, the same as gcc.
+            },
+            b'x' => {
+                let mut value = 0u32;
+                while let Some(d) = bytes.get(i).and_then(|d| (*d as char).to_digit(16)) {
+                    value = value.wrapping_mul(16) + d;
+                    i += 1;
+                }
+                value as u8
+            },
+            c => c, // `\\`, `\'`, `\"`, `\?`.
+        });
+    }
+    result
+}
+
+// `"hello\n"`, the lexer guarantees the quotes. there is no `\0` at the end.
+pub fn string_literal(text: &str) -> Vec<u8> {
+    unescape(&text[1..text.len() - 1])
+}
+
+// `'a'` and `'\n'` are `int`, the byte is unsigned like `char` of risc-v.
+pub fn char_literal(text: &str) -> i32 {
+    let bytes = unescape(&text[1..text.len() - 1]);
+    bytes[0] as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimal_fits_int() {
+        assert_eq!(int_literal("0", 10), Ok(0));
+        assert_eq!(int_literal("2147483647", 10), Ok(i32::MAX));
+        assert_eq!(int_literal("2147483648", 10), Err(1 << 31)); // only `-2147483648` takes it.
+    }
+
+    #[test]
+    fn octal_and_hex_keep_the_bits() {
+        assert_eq!(int_literal("17", 8), Ok(15));
+        assert_eq!(int_literal("7fffffff", 16), Ok(i32::MAX));
+        assert_eq!(int_literal("80000000", 16), Ok(i32::MIN));
+        assert_eq!(int_literal("ffffffff", 16), Ok(-1));
+        assert_eq!(int_literal("100000000", 16), Err(1 << 32));
+    }
+
+    #[test]
+    fn huge_literal_saturates() {
+        assert_eq!(int_literal("99999999999999999999999999", 10), Err(u64::MAX));
+    }
+
+    #[test]
+    fn hex_float_value() {
+        assert_eq!(hex_float("0x1.8p3"), 12.0);
+        assert_eq!(hex_float("0X.8P+1"), 1.0);
+        assert_eq!(hex_float("0x10p-4"), 1.0);
+        assert_eq!(hex_float("0x1.fffffffffffffffffffp0"), 2.0); // rounds like gcc.
+    }
+
+    #[test]
+    fn hex_float_out_of_range() {
+        assert_eq!(hex_float("0x1p999999999999"), f32::INFINITY);
+        assert_eq!(hex_float("0x1p-999999999999"), 0.0);
+        assert_eq!(hex_float("0x0.0p99"), 0.0);
+    }
+
+    #[test]
+    fn simple_escapes() {
+        assert_eq!(string_literal(r#""a\n\t\\\"b""#), b"a\n\t\\\"b");
+        assert_eq!(string_literal(r#""\q""#), b"q"); // unknown escape.
+        assert_eq!(char_literal(r"'\''"), '\'' as i32);
+    }
+
+    #[test]
+    fn numeric_escapes() {
+        assert_eq!(string_literal(r#""\101\0""#), b"A\0");
+        assert_eq!(string_literal(r#""\1012""#), b"A2"); // at most 3 octal digits.
+        assert_eq!(string_literal(r#""\x41\x4a""#), b"AJ");
+        assert_eq!(char_literal(r"'\777'"), 0xff);
+        assert_eq!(char_literal(r"'\x141'"), 0x41);
+    }
+
+    #[test]
+    fn char_is_unsigned() {
+        assert_eq!(char_literal(r"'\xff'"), 255);
+        assert_eq!(char_literal("'a'"), 97);
+    }
+}
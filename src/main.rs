@@ -1,7 +1,17 @@
 mod ast;
+mod diagnostic;
+mod driver;
 mod koopa_ir_gen;
+mod literal;
+mod preprocess;
 mod riscv_target_gen;
+mod semantic;
+#[cfg(test)]
+mod testing;
 
+use diagnostic::SourceFile;
+use driver::Target;
+use semantic::{Extensions, FunctionSignature};
 use lalrpop_util::lalrpop_mod;
 use std::env::args;
 // use std::fs;
@@ -14,7 +24,7 @@ use std::io::Write;
 
 // 引用 lalrpop 生成的解析器
 // 因为我们刚刚创建了 sysy.lalrpop, 所以模块名是 sysy
-lalrpop_mod!(sysy);
+lalrpop_mod!(#[allow(clippy::all)] sysy);
 
 fn main() -> Result<()> {
     // 解析命令行参数
@@ -25,31 +35,77 @@ fn main() -> Result<()> {
     args.next();
     let output = args.next().unwrap();
 
+    // 运行时库的函数表, `-runtime "int getcycles(); void putdigits(int a[], int n);"` 可以再加原型.
+    // `-fpointers` 打开指针扩展, 原型里也能用, 所以所有的参数读完之后再处理 `-runtime`. `-fstructs` 打开 struct 扩展.
+    // `-I dir` 加一个 `#include` 的搜索目录.
+    let mut runtime = semantic::runtime_library();
+    let mut extensions = Extensions::default();
+    let mut runtime_texts = Vec::new();
+    let mut include_dirs = Vec::new();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-runtime" => runtime_texts.push(flag_value(&mut args, &flag)),
+            "-I" => include_dirs.push(flag_value(&mut args, &flag)),
+            "-fpointers" => extensions.pointers = true,
+            "-fstructs" => extensions.structs = true,
+            _ => {},
+        }
+    }
+    for text in runtime_texts {
+        let prototypes = runtime_prototypes(text, &runtime, extensions);
+        runtime.extend(prototypes);
+    }
+
     println!("mode is {}.",mode);
     println!("input is {}.", input);
     println!("output is {}.", output);
 
-    // 读取输入文件
-    let input = read_to_string(input)?;
-
-    // 调用生成的parser: sysy, 指定start non-terminal: CompUnit(结尾默认加入Parser).
-    let ast = sysy::CompUnitParser::new().parse(&input).unwrap();
-    let koopa_program = koopa_ir_gen::generator(ast);
-    
-    if mode == "-riscv" { // risc-v
-        let driver = koopa::front::Driver::from(koopa_program);
-        let program = driver.generate_program().unwrap();
-        let riscv_program = riscv_target_gen::generate(program);
-        let mut file = File::create(output)?;
-        file.write_all(riscv_program.as_bytes())?;
-    } else {
-        let mut file = File::create(output)?;
-        file.write_all(koopa_program.as_bytes())?;
-    }
+    // 读取输入文件, 预处理, parse, 语义检查, 生成 koopa, 见 `driver.rs`.
+    // 直接得到内存里的 koopa Program, 不再打印成文本再 parse 回来.
+    let target = if mode == "-riscv" { Target::Riscv } else { Target::Koopa };
+    let input = SourceFile::new(input.clone(), read_to_string(&input)?);
+    let (source, result) = driver::compile(input, &include_dirs, &runtime, extensions, target);
+    let compiled = match result {
+        Ok(compiled) => compiled,
+        Err(diagnostics) => {
+            diagnostic::emit(&diagnostics, &source);
+            std::process::exit(1);
+        },
+    };
+
+    let text = match target { // 只有要输出 koopa 的时候才生成文本.
+        Target::Riscv => compiled.riscv(),
+        Target::Koopa => compiled.koopa(),
+    };
+    let mut file = File::create(output)?;
+    file.write_all(text.as_bytes())?;
 
     Ok(())
 }
 
+// the prototypes of a `-runtime`, an error stops the compiler like an error of the input.
+fn runtime_prototypes(text: String, runtime: &[(String, FunctionSignature)], extensions: Extensions) -> Vec<(String, FunctionSignature)> {
+    let (source, result) = driver::runtime_prototypes(text, runtime, extensions);
+    match result {
+        Ok(prototypes) => prototypes,
+        Err(diagnostics) => {
+            diagnostic::emit(&diagnostics, &source);
+            std::process::exit(1);
+        },
+    }
+}
+
+// the argument after `flag`, a usage error if `flag` is the last one.
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> String {
+    match args.next() {
+        Some(value) => value,
+        None => {
+            eprintln!("error: `{}` needs a value after it", flag);
+            std::process::exit(1);
+        },
+    }
+}
+
 // cargo run -- -koopa hello.c -o hello.koopa
 // autotest -koopa -s lv1 /home/compiler
 
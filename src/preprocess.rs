@@ -0,0 +1,864 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostic::{Diagnostic, SourceFile, SourceMap, Span};
+
+/*
+ * 预处理, CompUnitParser 之前先把 `#include`, `#define`, `#if` 这些处理掉, 得到一整段展开之后的源码.
+ * 注释先换成等长的空格 (换行保留), 所以没有宏的地方输出和输入逐字节对应.
+ * 输出的每一段都记在 SourceMap 里: 原样复制的部分一个字节对应一个字节, 宏展开的结果对应整个宏调用,
+ * 之后报错的时候 span 再映射回原来的文件和行.
+ * `#` 开头的行和 `#if` 没有选中的行只输出一个换行, `#include` 的文件接在这一行的位置.
+ *
+ * 宏展开按 C 的规则: 实参先完全展开再代入, 结果和后面的 token 一起重新扫描,
+ * 每个 token 带着展开出它的那些宏的名字 (hide set), 这些宏不会再展开, 不会无限递归.
+ * 不支持 `#` 和 `##` 运算符, 也不支持可变参数的宏.
+ *
+ * `#include "sylib.h"` 找不到这个文件的时候直接跳过, 运行时库的函数本来就都声明过了.
+ * 它定义的 `starttime()`/`stoptime()` 两个宏不用预定义: 测试程序从来不 include 它, 这样的调用由语义检查和生成 koopa 的时候处理,
+ * 传入同样的行号, 见 `semantic::TIMERS`. 程序自己声明了这个名字的时候就是普通的函数.
+ */
+
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Ident,
+    Number,
+    Literal,
+    Punct,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: Kind,
+    text: String,
+    span: Span,        // in the current file, a token from an expansion has the span of the whole invocation.
+    expanded: bool,
+    hide: Vec<String>, // the macros it comes from.
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Option<Vec<String>>, // None for an object-like macro.
+    body: Vec<Token>,
+}
+
+// one level of `#if`, `active` is whether the lines of the current branch are kept.
+struct Cond {
+    parent: bool,
+    active: bool,
+    taken: bool,
+    has_else: bool,
+    span: Span,
+}
+
+struct Preprocessor<'a> {
+    map: SourceMap,
+    macros: HashMap<String, Macro>,
+    include_dirs: &'a [String],
+    errors: Vec<Diagnostic>,
+}
+
+// preprocess `file`, the result is what the parser reads.
+pub fn run(file: SourceFile, include_dirs: &[String]) -> (SourceMap, Vec<Diagnostic>) {
+    let mut pp = Preprocessor { map: SourceMap::empty(), macros: HashMap::new(), include_dirs, errors: Vec::new() };
+    let file = pp.map.add_file(file);
+    pp.run_file(file, 0);
+    (pp.map, pp.errors)
+}
+
+impl<'a> Preprocessor<'a> {
+    fn error(&mut self, file: usize, span: Span, message: String) {
+        self.errors.push(Diagnostic::error(span, message).in_file(file));
+    }
+
+    fn run_file(&mut self, file: usize, depth: usize) {
+        let text = blank_comments(&self.map.files[file].text);
+        // every line with its line break.
+        let mut lines = Vec::new();
+        let mut lo = 0;
+        for (i, c) in text.char_indices() {
+            if c == '\n' {
+                lines.push((lo, i + 1));
+                lo = i + 1;
+            }
+        }
+        if lo < text.len() {
+            lines.push((lo, text.len()));
+        }
+
+        let mut conds: Vec<Cond> = Vec::new();
+        let mut block: Option<usize> = None; // beginning of the text lines not expanded yet.
+        let mut i = 0;
+        while i < lines.len() {
+            let (lo, hi) = lines[i];
+            let active = conds.last().is_none_or(|c| c.active);
+            if !text[lo..hi].trim_start().starts_with('#') {
+                if active {
+                    block.get_or_insert(lo);
+                } else {
+                    self.map.push_newline();
+                }
+                i += 1;
+                continue;
+            }
+            if let Some(block_lo) = block.take() {
+                self.expand_text(file, &text, block_lo, lo);
+            }
+
+            // a directive goes on after a `\` at the end of the line.
+            let mut last = i;
+            while last + 1 < lines.len() && text[lines[last].0..lines[last].1].trim_end_matches(['\n', '\r']).ends_with('\\') {
+                last += 1;
+            }
+            let hi = lines[last].1;
+            self.directive(file, &text, Span::new(lo, hi), &mut conds, depth);
+            for _ in i..=last {
+                self.map.push_newline();
+            }
+            i = last + 1;
+        }
+        if let Some(block_lo) = block.take() {
+            self.expand_text(file, &text, block_lo, text.len());
+        }
+        for cond in conds {
+            self.error(file, cond.span, "unterminated conditional directive".to_string());
+        }
+    }
+
+    // expand the macros of the text lines `[lo, hi)` and append them to the output.
+    fn expand_text(&mut self, file: usize, text: &str, lo: usize, hi: usize) {
+        let tokens = tokenize(text, lo, hi);
+        let tokens = self.expand(file, tokens);
+
+        let mut cursor = lo;
+        let mut i = 0;
+        while i < tokens.len() {
+            if !tokens[i].expanded {
+                self.map.push_verbatim(file, text, cursor, tokens[i].span.hi);
+                cursor = tokens[i].span.hi;
+                i += 1;
+                continue;
+            }
+            // the tokens of one invocation become one piece, spaces keep them apart.
+            let span = tokens[i].span;
+            let mut expansion = String::from(" ");
+            while i < tokens.len() && tokens[i].expanded && tokens[i].span == span {
+                expansion.push_str(&tokens[i].text);
+                expansion.push(' ');
+                i += 1;
+            }
+            self.map.push_verbatim(file, text, cursor, span.lo);
+            self.map.push_expansion(file, span, &expansion);
+            cursor = std::cmp::max(cursor, span.hi);
+        }
+        self.map.push_verbatim(file, text, cursor, hi);
+    }
+
+    fn expand(&mut self, file: usize, tokens: Vec<Token>) -> Vec<Token> {
+        let mut input: VecDeque<Token> = tokens.into();
+        let mut output = Vec::new();
+        while let Some(mut tok) = input.pop_front() {
+            if tok.kind != Kind::Ident || tok.hide.contains(&tok.text) {
+                output.push(tok);
+                continue;
+            }
+            match tok.text.as_str() {
+                "__LINE__" => {
+                    let (line, _) = self.map.files[file].line_col(tok.span.lo);
+                    tok.kind = Kind::Number;
+                    tok.text = line.to_string();
+                    tok.expanded = true;
+                    output.push(tok);
+                    continue;
+                },
+                "__FILE__" => {
+                    let name = self.map.files[file].name.replace('\\', "\\\\").replace('"', "\\\"");
+                    tok.kind = Kind::Literal;
+                    tok.text = format!("\"{}\"", name);
+                    tok.expanded = true;
+                    output.push(tok);
+                    continue;
+                },
+                _ => {},
+            }
+            let mac = match self.macros.get(&tok.text) {
+                Some(mac) => mac.clone(),
+                None => {
+                    output.push(tok);
+                    continue;
+                },
+            };
+
+            let mut span = tok.span;
+            let mut args = Vec::new();
+            if let Some(params) = &mac.params {
+                // the name of a function-like macro without `(` is just an identifier.
+                if input.front().map(|t| t.text.as_str()) != Some("(") {
+                    output.push(tok);
+                    continue;
+                }
+                let close = match collect_args(&mut input, &mut args) {
+                    Some(close) => close,
+                    None => {
+                        self.error(file, tok.span, format!("unterminated argument list invoking macro `{}`", tok.text));
+                        break;
+                    },
+                };
+                // `f()` has one empty argument.
+                if params.is_empty() && args.len() == 1 && args[0].is_empty() {
+                    args.clear();
+                }
+                span.hi = close.span.hi;
+                if args.len() != params.len() {
+                    self.error(file, span, format!("macro `{}` requires {} arguments, but {} given", tok.text, params.len(), args.len()));
+                    continue;
+                }
+            }
+
+            let mut hide = tok.hide.clone();
+            hide.push(tok.text.clone());
+            let mut result = Vec::new();
+            for body in &mac.body {
+                let param = mac.params.as_ref().and_then(|params| params.iter().position(|p| *p == body.text));
+                match param {
+                    Some(k) if body.kind == Kind::Ident => {
+                        let arg = args[k].clone();
+                        result.extend(self.expand(file, arg));
+                    },
+                    _ => result.push(body.clone()),
+                }
+            }
+            // an empty expansion still covers the invocation in the output.
+            if result.is_empty() {
+                output.push(Token { kind: Kind::Punct, text: String::new(), span, expanded: true, hide });
+                continue;
+            }
+            // rescan with the rest of the input.
+            for mut t in result.into_iter().rev() {
+                t.span = span;
+                t.expanded = true;
+                for name in &hide {
+                    if !t.hide.contains(name) {
+                        t.hide.push(name.clone());
+                    }
+                }
+                input.push_front(t);
+            }
+        }
+        output
+    }
+
+    fn directive(&mut self, file: usize, text: &str, span: Span, conds: &mut Vec<Cond>, depth: usize) {
+        // `\` and the line break become spaces, the offsets stay those of the file.
+        let line = text[span.lo..span.hi].replace("\\\r\n", "   ").replace("\\\n", "  ");
+        let tokens: Vec<Token> = tokenize(&line, 0, line.len()).into_iter().map(|mut t| {
+            t.span = Span::new(t.span.lo + span.lo, t.span.hi + span.lo);
+            t
+        }).collect();
+        // the line itself, without the line break.
+        let span = Span::new(tokens[0].span.lo, tokens.last().unwrap().span.hi);
+        let (name, args) = match tokens.get(1) {
+            Some(name) if name.kind == Kind::Ident => (name.clone(), &tokens[2..]),
+            Some(tok) => {
+                if conds.last().is_none_or(|c| c.active) {
+                    self.error(file, tok.span, "invalid preprocessing directive".to_string());
+                }
+                return;
+            },
+            None => return, // a lone `#`.
+        };
+        let active = conds.last().is_none_or(|c| c.active);
+
+        match name.text.as_str() {
+            "if" | "ifdef" | "ifndef" => {
+                let taken = active && self.condition(file, &name, args);
+                conds.push(Cond { parent: active, active: taken, taken, has_else: false, span });
+            },
+            "elif" | "else" => {
+                let cond = match conds.last() {
+                    Some(cond) => cond,
+                    None => return self.error(file, span, format!("`#{}` without `#if`", name.text)),
+                };
+                if cond.has_else {
+                    return self.error(file, span, format!("`#{}` after `#else`", name.text));
+                }
+                let chosen = cond.parent && !cond.taken && (name.text == "else" || self.condition(file, &name, args));
+                let cond = conds.last_mut().unwrap();
+                cond.active = chosen;
+                cond.taken |= chosen;
+                cond.has_else = name.text == "else";
+            },
+            "endif" => {
+                if conds.pop().is_none() {
+                    self.error(file, span, "`#endif` without `#if`".to_string());
+                }
+            },
+            _ if !active => {},
+            "define" => self.define(file, &name, args),
+            "undef" => match args.first() {
+                Some(tok) if tok.kind == Kind::Ident => {
+                    self.macros.remove(&tok.text);
+                },
+                _ => self.error(file, name.span, "macro name must be an identifier".to_string()),
+            },
+            "include" => {
+                let rest = text[name.span.hi..span.hi].trim();
+                self.include(file, rest, span, depth);
+            },
+            "error" => {
+                let message = text[name.span.hi..span.hi].trim();
+                self.error(file, span, format!("#error {}", message));
+            },
+            "pragma" => {},
+            _ => self.error(file, name.span, format!("unknown preprocessing directive `#{}`", name.text)),
+        }
+    }
+
+    fn define(&mut self, file: usize, directive: &Token, args: &[Token]) {
+        let name = match args.first() {
+            Some(tok) if tok.kind == Kind::Ident => tok,
+            _ => return self.error(file, directive.span, "macro name must be an identifier".to_string()),
+        };
+        if name.text == "defined" {
+            return self.error(file, name.span, "`defined` cannot be used as a macro name".to_string());
+        }
+        // `#define f(x)` has parameters, `#define f (x)` doesn't.
+        let mut rest = &args[1..];
+        let mut params = None;
+        if rest.first().is_some_and(|t| t.text == "(" && t.span.lo == name.span.hi) {
+            let mut names = Vec::new();
+            let mut i = 1;
+            loop {
+                match rest.get(i) {
+                    Some(tok) if tok.text == ")" && names.is_empty() => break,
+                    Some(tok) if tok.text == "..." => {
+                        return self.error(file, tok.span, "variadic macros are not supported".to_string());
+                    },
+                    Some(tok) if tok.kind == Kind::Ident => names.push(tok.text.clone()),
+                    Some(tok) => return self.error(file, tok.span, "expected a parameter name".to_string()),
+                    None => return self.error(file, name.span, "missing `)` in macro parameter list".to_string()),
+                }
+                match rest.get(i + 1) {
+                    Some(tok) if tok.text == "," => i += 2,
+                    Some(tok) if tok.text == ")" => {
+                        i += 1;
+                        break;
+                    },
+                    _ => return self.error(file, name.span, "missing `)` in macro parameter list".to_string()),
+                }
+            }
+            rest = &rest[i + 1..];
+            params = Some(names);
+        }
+        self.macros.insert(name.text.clone(), Macro { params, body: rest.to_vec() });
+    }
+
+    fn include(&mut self, file: usize, rest: &str, span: Span, depth: usize) {
+        let (name, quoted) = match (rest.chars().next(), rest.get(1..)) {
+            (Some('"'), Some(tail)) if tail.contains('"') => (&tail[..tail.find('"').unwrap()], true),
+            (Some('<'), Some(tail)) if tail.contains('>') => (&tail[..tail.find('>').unwrap()], false),
+            _ => return self.error(file, span, "`#include` expects \"FILENAME\" or <FILENAME>".to_string()),
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return self.error(file, span, "`#include` nested too deeply".to_string());
+        }
+
+        // `"..."` looks next to the current file first, then both look in the `-I` directories.
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        if quoted {
+            let current = Path::new(&self.map.files[file].name);
+            dirs.push(current.parent().map_or(PathBuf::new(), |p| p.to_path_buf()));
+        }
+        dirs.extend(self.include_dirs.iter().map(PathBuf::from));
+        let path = dirs.into_iter().map(|dir| dir.join(name)).find(|path| path.is_file());
+
+        let path = match path {
+            Some(path) => path,
+            None if name == "sylib.h" => return,
+            None => return self.error(file, span, format!("cannot find include file `{}`", name)),
+        };
+        match read_to_string(&path) {
+            Ok(text) => {
+                let included = self.map.add_file(SourceFile::new(path.to_string_lossy().to_string(), text));
+                self.run_file(included, depth + 1);
+            },
+            Err(err) => self.error(file, span, format!("cannot read include file `{}`: {}", name, err)),
+        }
+    }
+
+    // the condition of `#if`, `#elif`, `#ifdef` or `#ifndef`.
+    fn condition(&mut self, file: usize, directive: &Token, args: &[Token]) -> bool {
+        if directive.text == "ifdef" || directive.text == "ifndef" {
+            let defined = match args.first() {
+                Some(tok) if tok.kind == Kind::Ident => self.macros.contains_key(&tok.text),
+                _ => {
+                    self.error(file, directive.span, "macro name must be an identifier".to_string());
+                    return false;
+                },
+            };
+            return defined == (directive.text == "ifdef");
+        }
+
+        // `defined X` and `defined(X)` go first, they must not be expanded.
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < args.len() {
+            if args[i].text != "defined" {
+                tokens.push(args[i].clone());
+                i += 1;
+                continue;
+            }
+            let paren = args.get(i + 1).is_some_and(|t| t.text == "(");
+            let name = if paren { args.get(i + 2) } else { args.get(i + 1) };
+            let closed = !paren || args.get(i + 3).is_some_and(|t| t.text == ")");
+            match name {
+                Some(name) if name.kind == Kind::Ident && closed => {
+                    let mut tok = name.clone();
+                    tok.kind = Kind::Number;
+                    tok.text = if self.macros.contains_key(&name.text) { "1" } else { "0" }.to_string();
+                    tokens.push(tok);
+                    i += if paren { 4 } else { 2 };
+                },
+                _ => {
+                    self.error(file, args[i].span, "`defined` expects a macro name".to_string());
+                    return false;
+                },
+            }
+        }
+        let tokens: Vec<Token> = self.expand(file, tokens).into_iter().filter(|t| !t.text.is_empty()).collect();
+        if tokens.is_empty() {
+            self.error(file, directive.span, format!("`#{}` with no expression", directive.text));
+            return false;
+        }
+
+        let mut eval = Eval { tokens: &tokens, pos: 0 };
+        let result = eval.cond(true).and_then(|value| match eval.tokens.get(eval.pos) {
+            Some(tok) => Err((format!("unexpected token `{}` in `#{}`", tok.text, directive.text), tok.span)),
+            None => Ok(value),
+        });
+        match result {
+            Ok(value) => value != 0,
+            Err((message, span)) => {
+                self.error(file, span, message);
+                false
+            },
+        }
+    }
+}
+
+// the argument lists of a call, `input` starts with the `(`. returns the `)`, None if it's missing.
+fn collect_args(input: &mut VecDeque<Token>, args: &mut Vec<Vec<Token>>) -> Option<Token> {
+    input.pop_front();
+    args.push(Vec::new());
+    let mut depth = 0;
+    while let Some(tok) = input.pop_front() {
+        match tok.text.as_str() {
+            ")" if depth == 0 => return Some(tok),
+            "," if depth == 0 => {
+                args.push(Vec::new());
+                continue;
+            },
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            _ => {},
+        }
+        args.last_mut().unwrap().push(tok);
+    }
+    None
+}
+
+
+// integer constant expressions of `#if`, identifiers left after the expansion are 0.
+struct Eval<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+type EvalResult = Result<i64, (String, Span)>;
+
+fn precedence(op: &str) -> Option<u8> {
+    let prec = match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    };
+    Some(prec)
+}
+
+impl<'a> Eval<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn unexpected(&self) -> (String, Span) {
+        match self.peek() {
+            Some(tok) => (format!("unexpected token `{}` in `#if`", tok.text), tok.span),
+            None => ("unexpected end of `#if` expression".to_string(), self.tokens.last().unwrap().span),
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), (String, Span)> {
+        match self.peek() {
+            Some(tok) if tok.text == text => {
+                self.pos += 1;
+                Ok(())
+            },
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    // `live` is false in the branches that aren't evaluated, they can divide by zero.
+    fn cond(&mut self, live: bool) -> EvalResult {
+        let value = self.binary(1, live)?;
+        if self.peek().is_none_or(|t| t.text != "?") {
+            return Ok(value);
+        }
+        self.pos += 1;
+        let then = self.cond(live && value != 0)?;
+        self.expect(":")?;
+        let other = self.cond(live && value == 0)?;
+        Ok(if value != 0 { then } else { other })
+    }
+
+    fn binary(&mut self, min: u8, live: bool) -> EvalResult {
+        let mut lhs = self.unary(live)?;
+        while let Some(tok) = self.peek() {
+            let prec = match precedence(&tok.text) {
+                Some(prec) if prec >= min => prec,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs_live = match tok.text.as_str() {
+                "&&" => live && lhs != 0,
+                "||" => live && lhs == 0,
+                _ => live,
+            };
+            let rhs = self.binary(prec + 1, rhs_live)?;
+            lhs = match tok.text.as_str() {
+                "||" => (lhs != 0 || rhs != 0) as i64,
+                "&&" => (lhs != 0 && rhs != 0) as i64,
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "<<" => lhs.wrapping_shl(rhs as u32),
+                ">>" => lhs.wrapping_shr(rhs as u32),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                _ if rhs == 0 => {
+                    if live {
+                        return Err(("division by zero in `#if`".to_string(), tok.span));
+                    }
+                    0
+                },
+                "/" => lhs.wrapping_div(rhs),
+                _ => lhs.wrapping_rem(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self, live: bool) -> EvalResult {
+        let tok = match self.peek() {
+            Some(tok) => tok,
+            None => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        match (tok.kind, tok.text.as_str()) {
+            (_, "+") => self.unary(live),
+            (_, "-") => Ok(self.unary(live)?.wrapping_neg()),
+            (_, "!") => Ok((self.unary(live)? == 0) as i64),
+            (_, "~") => Ok(!self.unary(live)?),
+            (_, "(") => {
+                let value = self.cond(live)?;
+                self.expect(")")?;
+                Ok(value)
+            },
+            (Kind::Ident, _) => Ok(0),
+            (Kind::Number, text) => parse_integer(text).ok_or_else(|| (format!("invalid integer `{}` in `#if`", text), tok.span)),
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected())
+            },
+        }
+    }
+}
+
+fn parse_integer(text: &str) -> Option<i64> {
+    let digits = text.trim_end_matches(['u', 'U', 'l', 'L']);
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse::<u64>()
+    };
+    value.ok().map(|v| v as i64)
+}
+
+
+// the comments become spaces of the same length, the line breaks in them stay.
+fn blank_comments(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let blank = |result: &mut String, c: char| {
+        if c == '\n' {
+            result.push('\n');
+        } else {
+            result.push_str(&" ".repeat(c.len_utf8()));
+        }
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                result.push(c);
+                while let Some(d) = chars.next() {
+                    result.push(d);
+                    if d == '\\' {
+                        if let Some(e) = chars.next() {
+                            result.push(e);
+                        }
+                    } else if d == c || d == '\n' {
+                        break;
+                    }
+                }
+            },
+            '/' if chars.peek() == Some(&'/') => {
+                result.push(' ');
+                while let Some(&d) = chars.peek() {
+                    if d == '\n' {
+                        break;
+                    }
+                    blank(&mut result, d);
+                    chars.next();
+                }
+            },
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                result.push_str("  ");
+                let mut star = false;
+                for d in chars.by_ref() {
+                    blank(&mut result, d);
+                    if star && d == '/' {
+                        break;
+                    }
+                    star = d == '*';
+                }
+            },
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+const PUNCTS: [&str; 23] = [
+    "<<=", ">>=", "...", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "++",
+    "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "->", "##",
+];
+
+// the tokens of `text[lo..hi]`, the comments are already blanked.
+fn tokenize(text: &str, lo: usize, hi: usize) -> Vec<Token> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = lo;
+    while i < hi {
+        let c = bytes[i];
+        let start = i;
+        let kind = if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while i < hi && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            Kind::Ident
+        } else if c.is_ascii_digit() || (c == b'.' && i + 1 < hi && bytes[i + 1].is_ascii_digit()) {
+            // a pp-number, `1e-5` and `0x1p+3` included.
+            i += 1;
+            while i < hi {
+                let d = bytes[i];
+                let sign = (d == b'+' || d == b'-') && matches!(bytes[i - 1], b'e' | b'E' | b'p' | b'P');
+                if !(sign || d.is_ascii_alphanumeric() || d == b'_' || d == b'.') {
+                    break;
+                }
+                i += 1;
+            }
+            Kind::Number
+        } else if c == b'"' || c == b'\'' {
+            i += 1;
+            while i < hi && bytes[i] != c && bytes[i] != b'\n' {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            i = std::cmp::min(i + 1, hi);
+            Kind::Literal
+        } else {
+            let punct = PUNCTS.iter().find(|p| text[i..hi].starts_with(**p));
+            // a character outside ascii is left to the lexer.
+            i += punct.map_or_else(|| text[i..].chars().next().unwrap().len_utf8(), |p| p.len());
+            Kind::Punct
+        };
+        tokens.push(Token { kind, text: text[start..i].to_string(), span: Span::new(start, i), expanded: false, hide: Vec::new() });
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocess(text: &str) -> (SourceMap, Vec<Diagnostic>) {
+        run(SourceFile::new("t.c".to_string(), text.to_string()), &[])
+    }
+
+    // an empty directory for the files of one test.
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("preprocess-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn eval(expr: &str) -> EvalResult {
+        let tokens = tokenize(expr, 0, expr.len());
+        Eval { tokens: &tokens, pos: 0 }.cond(true)
+    }
+
+    #[test]
+    fn object_like_macro() {
+        let (map, errors) = preprocess("#define N 10\nint a[N];\n");
+        assert_eq!(map.text, "\nint a[ 10 ];\n");
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn rescan_with_the_rest_of_the_input() {
+        let (map, _) = preprocess("#define f(x) x+x\n#define g f\nint a = g(1)(2);\n");
+        assert_eq!(map.text, "\n\nint a =  1 + 1 (2);\n");
+    }
+
+    #[test]
+    fn hide_set_stops_recursion() {
+        let (map, _) = preprocess("#define a b\n#define b a\nint a;\n");
+        assert_eq!(map.text, "\n\nint  a ;\n");
+    }
+
+    #[test]
+    fn empty_macro_and_comments_keep_the_columns() {
+        let (map, _) = preprocess("#define E\nint E x; // c\n/* a\nb */ int y;\n");
+        assert_eq!(map.text, "\nint    x;     \n    \n     int y;\n");
+    }
+
+    #[test]
+    fn line_and_file() {
+        let (map, _) = preprocess("\n__LINE__ __FILE__\n");
+        assert_eq!(map.text, "\n 2   \"t.c\" \n");
+    }
+
+    #[test]
+    fn timers_are_left_to_the_frontend() {
+        // a missing `sylib.h` is skipped, nothing defines the timers.
+        let (map, errors) = preprocess("#include \"sylib.h\"\nstarttime();\n");
+        assert_eq!(map.text, "\nstarttime();\n");
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn wrong_number_of_arguments() {
+        let (map, errors) = preprocess("#define f(x, y) x\nf(1)\n");
+        assert_eq!(map.text, "\nf(1)\n");
+        assert_eq!(errors[0].message, "macro `f` requires 2 arguments, but 1 given");
+        assert_eq!(errors[0].span, Span::new(18, 22));
+    }
+
+    #[test]
+    fn conditional_branches() {
+        let (map, _) = preprocess("#if 0\nx\n#elif 2 > 1\ny\n#else\nz\n#endif\n");
+        assert_eq!(map.text, "\n\n\ny\n\n\n\n");
+        let (map, _) = preprocess("#define A\n#ifdef A\nx\n#endif\n#ifndef A\ny\n#endif\n");
+        assert_eq!(map.text, "\n\nx\n\n\n\n\n");
+    }
+
+    #[test]
+    fn if_expression() {
+        assert_eq!(eval("1 + 2 * 3 == 7"), Ok(1));
+        assert_eq!(eval("(1 << 4) | 0x3 ^ 010"), Ok(27));
+        assert_eq!(eval("-1 < 0 ? 10 : 20"), Ok(10));
+        assert_eq!(eval("!UNDEFINED && ~0 == -1"), Ok(1));
+        assert_eq!(eval("7 % 3 - 8 / 3"), Ok(-1));
+        assert_eq!(eval("100UL"), Ok(100));
+    }
+
+    #[test]
+    fn if_division_by_zero() {
+        assert_eq!(eval("1 / 0"), Err(("division by zero in `#if`".to_string(), Span::new(2, 3))));
+        // the branches that aren't evaluated don't count.
+        assert_eq!(eval("0 && 1 / 0"), Ok(0));
+        assert_eq!(eval("1 || 1 % 0"), Ok(1));
+        assert_eq!(eval("1 ? 2 : 3 / 0"), Ok(2));
+    }
+
+    #[test]
+    fn if_syntax_error() {
+        assert_eq!(eval("1 +"), Err(("unexpected end of `#if` expression".to_string(), Span::new(2, 3))));
+        assert_eq!(eval("(1"), Err(("unexpected end of `#if` expression".to_string(), Span::new(1, 2))));
+        assert_eq!(eval("1 ? 2"), Err(("unexpected end of `#if` expression".to_string(), Span::new(4, 5))));
+        let (_, errors) = preprocess("#if 1 / 0\n#endif\n");
+        assert_eq!(errors[0].span, Span::new(6, 7));
+    }
+
+    #[test]
+    fn locate_through_an_expansion() {
+        let (map, _) = preprocess("#define N 10\nint a[N];\n");
+        // `int` is copied from the file.
+        assert_eq!(map.locate(Span::new(1, 4)).1, Span::new(13, 16));
+        // the expansion maps to the invocation.
+        assert_eq!(map.locate(Span::new(8, 10)).1, Span::new(19, 20));
+        // `a[ 10 ]` is `a[N]`.
+        assert_eq!(map.locate(Span::new(5, 12)).1, Span::new(17, 21));
+    }
+
+    #[test]
+    fn quoted_includes_look_next_to_the_file_first() {
+        let dir = scratch_dir("search-order");
+        let (src, inc) = (dir.join("src"), dir.join("inc"));
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::create_dir_all(&inc).unwrap();
+        std::fs::write(src.join("a.h"), "int here;\n").unwrap();
+        std::fs::write(inc.join("a.h"), "int there;\n").unwrap();
+        std::fs::write(inc.join("b.h"), "int b;\n").unwrap();
+        let main = SourceFile::new(src.join("t.c").to_string_lossy().to_string(), "#include \"a.h\"\n#include <a.h>\n#include \"b.h\"\n".to_string());
+        let (map, errors) = run(main, &[inc.to_string_lossy().to_string()]);
+        // `<...>` skips the directory of the file.
+        assert_eq!(map.text, "int here;\n\nint there;\n\nint b;\n\n");
+        assert!(errors.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_include_with_include_dirs() {
+        let dir = scratch_dir("missing");
+        let main = SourceFile::new(dir.join("t.c").to_string_lossy().to_string(), "int a;\n#include \"none.h\"\n".to_string());
+        let (_, errors) = run(main, &[dir.to_string_lossy().to_string()]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "cannot find include file `none.h`");
+        assert_eq!(errors[0].span, Span::new(7, 24)); // the whole directive.
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,6 +3,7 @@ use core::panic;
 
 use koopa::ir::BasicBlock;
 use koopa::ir::BinaryOp;
+use koopa::ir::Function;
 use koopa::ir::FunctionData;
 // use koopa::front::ast::Return;
 use koopa::ir::Program;
@@ -12,7 +13,9 @@ use koopa::ir::Value;
 use koopa::ir::ValueKind;
 use koopa::ir::dfg::DataFlowGraph;
 use koopa::ir::entities::ValueData;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+use crate::koopa_ir_gen::{intrinsic, FloatAbi, JumpTable, STRING_PREFIX};
 
 const MACHINE_BYTE: i32 = 4; // 32-bit machine.
 const GLOBAL_INTEGER: i32 = 0;
@@ -30,12 +33,22 @@ const SIGN_BITS: i32 = 7;
  * 主要的难度在于理解koopa-IR的表示, 其提供了丰富的信息, 但是需要我们自己去找如何使用.
  *      个人的开发经验: Rust中的`#[derive(Debug)]`太好用了, 直接输出对象, 则我们可以很清晰的找到所需要的信息.
  * 
- * 
+ * float: koopa 里是 i32 的 bit pattern, 和 int 一样存在栈上. 对 `@__sysy_fadd` 这样的 intrinsic 的调用
+ *      不生成 call, 把操作数 fmv 到 ft0/ft1, 做完运算再 fmv 回来. 调用约定是 ilp32f, `float` 参数和返回值走 fa0-fa7,
+ *      哪些参数是 `float` 由前端的 FloatAbi 告诉我们.
+ *      `putf` 的 `float` 参数要提升成 `double`, 前端已经拆成了两个 i32, 高低两半由 `@__sysy_ftod_hi/lo` 算出来,
+ *      RV32F 没有 double, 只能用整数指令拼 bit.
+ *
+ * 字符串: koopa 里是 `@__sysy_str_` 开头的 i32 数组, 一个字节一个 i32. 这里放进 .rodata, 还原成真正的字节,
+ *      `putf` 是 C 写的, 要的是 `char *`.
+ *
+ * switch: 前端把密集的 `switch` 记成 JumpTable. 比较树的第一个 block 换成查表 + `jr`, 表放在 .rodata,
+ *      比较树剩下的 block 只有它自己跳得到, 直接不生成.
  */
 
 fn block2str(bb: &BasicBlock) -> String {
     let name = format!("{:?}", bb);
-    return format!("{}{}", name[0..10].to_string(), name[11..name.len()-1].to_string());
+    format!("{}{}", &name[0..10], &name[11..name.len()-1])
 }
 
 fn calc_funcinstr(func: &FunctionData) -> i32 {
@@ -64,40 +77,41 @@ fn calc_funcinstr(func: &FunctionData) -> i32 {
 fn riscv_addi(dst: &str, src: &str, imme: i32) -> String {
     assert!(src != "t1");
     let mut program = "".to_string();
-    if imme < -2048 || imme > 2047 {
+    if !(-2048..=2047).contains(&imme) {
         program.push_str(&format!("    li t3, {}\n", imme));
         program.push_str(&format!("    add {}, {}, t3\n", dst, src));
     } else {
         program.push_str(&format!("    addi {}, {}, {}\n", dst, src, &imme));
     }
-    return program;
+    program
 }
 fn riscv_lw(dst: &str, src: &str, imme: i32) -> String {
     assert!(src != "t3");
     let mut program = "".to_string();
-    if imme < -2048 || imme > 2047 {
+    if !(-2048..=2047).contains(&imme) {
         program.push_str(&format!("    li t3, {}\n", imme));
         program.push_str(&format!("    add t3, {}, t3\n", src));
         program.push_str(&format!("    lw {}, 0(t3)\n", dst));
     } else {
         program.push_str(&format!("    lw {}, {}({})\n", dst, imme, src));
     }
-    return program;
+    program
 }
 fn riscv_sw(dst: &str, src: &str, imme: i32) -> String {
     assert!(src != "t3");
     let mut program = "".to_string();
-    if imme < -2048 || imme > 2047 {
+    if !(-2048..=2047).contains(&imme) {
         program.push_str(&format!("    li t3, {}\n", imme));
         program.push_str(&format!("    add t3, {}, t3\n", src)); // 你tm写sp？
         program.push_str(&format!("    sw {}, 0(t3)\n", dst));
     } else {
         program.push_str(&format!("    sw {}, {}({})\n", dst, imme, src));
     }
-    return program;
+    program
 }
 
 // idx must be store in `t1`.
+// `type_size` is the size of what the result points to, an `i32`, a row, or a struct (`[i32, n]`), so it's the stride.
 fn loadpointer2register(scope: &HashMap<Value, (i32, i32)>, pt: &Value, idx: &str, dst: &str, type_size: i32) -> String {
     let mut program = "".to_string();
     let var = scope.get(pt).unwrap();
@@ -107,7 +121,7 @@ fn loadpointer2register(scope: &HashMap<Value, (i32, i32)>, pt: &Value, idx: &st
         // program.push_str(&format!("    addi t0, sp, {}\n", var.1)); 
         // program.push_str(&format!("    li t2, {}\n    mul t1, t1, t2\n", type_size));
         // program.push_str(&format!("    add {}, t0, t1\n", dst));
-    } else if (var.0 & SIGN_BITS) == GLOBAL_ARRAY {
+    } else if (var.0 & SIGN_BITS) == GLOBAL_ARRAY || var.0 == GLOBAL_INTEGER { // `&g + 1` is a `getptr` of a global `int`.
         program.push_str(&format!("    la t0, glb_var{}\n", var.1)); // load base.
     } else {
         // println!("type: {}\n", var.0);
@@ -119,49 +133,57 @@ fn loadpointer2register(scope: &HashMap<Value, (i32, i32)>, pt: &Value, idx: &st
     // idx * size
     program.push_str(&format!("    li t2, {}\n    mul t1, t1, t2\n", type_size));
     program.push_str(&format!("    add {}, t0, t1\n", dst));
-    return program;
+    program
 }
 
-// global variable and local var is different, you should treat them differently.
-fn load2register(scope: &HashMap<Value, (i32, i32)>, pt: &Value, data_graph: &DataFlowGraph, dst: &str, not_pointer: bool) -> String {
+// the value itself to `dst`. an alloc or a global is a variable, its value is its address (`&x`).
+// the others are constants or kept in their stack slot, a pointer there is the address it holds.
+fn value2register(scope: &HashMap<Value, (i32, i32)>, value: &Value, data_graph: &DataFlowGraph, dst: &str) -> String {
     let mut program = "".to_string();
-    let is_local = scope.get(pt);
+    let Some(val) = data_graph.values().get(value) else { // a global.
+        let pos = scope.get(value).unwrap();
+        program.push_str(&format!("    la {}, glb_var{}\n", dst, pos.1));
+        return program;
+    };
+    match val.kind() {
+        ValueKind::Integer(var) => {
+            program.push_str(&format!("    li {}, {}\n", dst, var.value()));
+        },
+        ValueKind::ZeroInit(_) => { // the null pointer.
+            program.push_str(&format!("    li {}, 0\n", dst));
+        },
+        ValueKind::Alloc(_) => {
+            let pos = scope.get(value).unwrap();
+            program.push_str(&riscv_addi(dst, "sp", pos.1));
+        },
+        _ => {
+            let pos = scope.get(value).unwrap();
+            assert!(pos.0 == INTEGER_POINTER || pos.0 == REAL_POINTER);
+            program.push_str(&riscv_lw(dst, "sp", pos.1));
+        },
+    }
+    program
+}
 
-    if is_local.is_none() || is_local.unwrap().0 == INTEGER_POINTER {
-        let val = data_graph.value(pt.clone());
-        match val.kind() {
-            ValueKind::Integer(var) => {
-                program.push_str(&format!("    li {}, {}\n", dst, var.value()));
-            },
-            _ => {
-                let pos = is_local.unwrap();
-                assert!(pos.0 == INTEGER_POINTER);
-                program.push_str(&riscv_lw(dst, "sp", pos.1));
-                // program.push_str(&format!("    lw {}, {}(sp)\n", dst, pos.1))
-            },
-        }
-    } else  {
-        // there must be a global definition. else crash.
-        let pos = is_local.unwrap();
-        // println!("    Query: {:?}:\n\n\n", pos);
-        if pos.0 == GLOBAL_INTEGER {
-            // la t0, var
-            // lw t0, 0(t0)
-            program.push_str(&format!("    la {}, glb_var{}\n", dst, pos.1));
-            program.push_str(&riscv_lw(dst, dst, 0));// integer.
-        } else { 
-            assert!(pos.0 == REAL_POINTER);
-
-            program.push_str(&riscv_addi(dst, "sp", pos.1)); // load base.
-            program.push_str(&riscv_lw(dst, dst, 0)); // get the pointer value.
-
-            // Fixed bug: in function call, we may want a pointer.
-            if not_pointer {
-                program.push_str(&riscv_lw(dst, dst, 0)); // get the real value.
-            }
-        }
+// global variable and local var is different, you should treat them differently.
+// the data `pt` points to, for `load`. `pt` is a variable, or a pointer computed before.
+fn load2register(scope: &HashMap<Value, (i32, i32)>, pt: &Value, data_graph: &DataFlowGraph, dst: &str) -> String {
+    let mut program = "".to_string();
+    let pos = scope.get(pt).unwrap();
+    if pos.0 == GLOBAL_INTEGER {
+        // la t0, var
+        // lw t0, 0(t0)
+        program.push_str(&format!("    la {}, glb_var{}\n", dst, pos.1));
+        program.push_str(&riscv_lw(dst, dst, 0));// integer.
+    } else if matches!(data_graph.value(*pt).kind(), ValueKind::Alloc(_)) {
+        assert!(pos.0 == INTEGER_POINTER);
+        program.push_str(&riscv_lw(dst, "sp", pos.1));
+    } else {
+        assert!(pos.0 == REAL_POINTER);
+        program.push_str(&riscv_lw(dst, "sp", pos.1)); // get the pointer value.
+        program.push_str(&riscv_lw(dst, dst, 0)); // get the real value.
     }
-    return program;
+    program
 }
 
 // multi-layer structure, dfs put to flat one.
@@ -173,13 +195,13 @@ fn aggre_flatmap_datagraph(aggre: &ValueData, data_graph: &DataFlowGraph) -> Vec
         },
         ValueKind::Aggregate(var) => {
             for x in var.elems() {
-                let mut ret_val = aggre_flatmap_datagraph(data_graph.value(x.clone()), data_graph);
+                let mut ret_val = aggre_flatmap_datagraph(data_graph.value(*x), data_graph);
                 res.append(&mut ret_val);
             }
         },
         _ => panic!("Fuck off"),
     }
-    return res;
+    res
 }
 // multi-layer structure, dfs put to flat one.
 fn aggre_flatmap_hashmap(aggre: &ValueData, map: &HashMap<Value, ValueData>) -> Vec<i32> {
@@ -190,13 +212,134 @@ fn aggre_flatmap_hashmap(aggre: &ValueData, map: &HashMap<Value, ValueData>) ->
         },
         ValueKind::Aggregate(var) => {
             for x in var.elems() {
-                let mut ret_val = aggre_flatmap_hashmap(map.get(&x).unwrap(), map);
+                let mut ret_val = aggre_flatmap_hashmap(map.get(x).unwrap(), map);
                 res.append(&mut ret_val);
             }
         },
         _ => panic!("Fuck off"),
     }
-    return res;
+    res
+}
+
+
+// where an argument is passed, ilp32f: a `float` goes to fa0-fa7, the others and the `float`s after fa7 to a0-a7,
+// the rest to the stack, in order.
+enum ArgSlot {
+    Int(usize),
+    Float(usize),
+    Stack(usize),
+}
+
+fn arg_slots(floats: &[bool]) -> Vec<ArgSlot> {
+    let (mut ints, mut fs, mut stack) = (0, 0, 0);
+    let mut slots = Vec::new();
+    for &is_float in floats {
+        if is_float && fs < 8 {
+            slots.push(ArgSlot::Float(fs));
+            fs += 1;
+        } else if ints < 8 {
+            slots.push(ArgSlot::Int(ints));
+            ints += 1;
+        } else {
+            slots.push(ArgSlot::Stack(stack));
+            stack += 1;
+        }
+    }
+    slots
+}
+
+// the bytes of a string for `.asciz`, escaped as octal unless printable.
+fn asciz(bytes: &[i32]) -> String {
+    let mut text = "".to_string();
+    for &byte in &bytes[..bytes.len() - 1] { // `.asciz` adds the `\0`.
+        let c = byte as u8 as char;
+        if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+            text.push(c);
+        } else {
+            text.push_str(&format!("\\{:03o}", byte as u8));
+        }
+    }
+    text
+}
+
+fn value2str(value: &Value) -> String {
+    let name = format!("{:?}", value);
+    format!("{}{}", &name[0..5], &name[6..name.len()-1])
+}
+
+// the `float` bits in `t1` to the `double` bits, the high half in `t1` and the low half in `t2`.
+// the exponent is rebiased from 127 to 1023, the mantissa gets 29 more bits. a subnormal `float` is normal
+// as a `double`, it's shifted until the hidden bit appears. `label` makes the labels unique.
+fn float_to_double(label: &str) -> String {
+    let mut program = "".to_string();
+    program.push_str("    srli t2, t1, 23\n"); // exponent.
+    program.push_str("    andi t2, t2, 255\n");
+    program.push_str("    slli t3, t1, 9\n"); // mantissa.
+    program.push_str("    srli t3, t3, 9\n");
+    program.push_str("    srli t4, t1, 31\n"); // sign.
+    program.push_str("    slli t4, t4, 31\n");
+    program.push_str("    li t5, 255\n");
+    program.push_str(&format!("    beq t2, t5, {}_inf\n", label));
+    program.push_str(&format!("    bnez t2, {}_normal\n", label));
+    program.push_str(&format!("    beqz t3, {}_pack\n", label)); // +-0.
+    program.push_str(&format!("{}_subnormal:\n", label));
+    program.push_str("    slli t3, t3, 1\n");
+    program.push_str("    addi t2, t2, -1\n");
+    program.push_str("    srli t5, t3, 23\n");
+    program.push_str(&format!("    beqz t5, {}_subnormal\n", label));
+    program.push_str("    addi t2, t2, 1\n");
+    program.push_str("    slli t3, t3, 9\n");
+    program.push_str("    srli t3, t3, 9\n");
+    program.push_str(&format!("{}_normal:\n", label));
+    program.push_str("    addi t2, t2, 896\n");
+    program.push_str(&format!("    j {}_pack\n", label));
+    program.push_str(&format!("{}_inf:\n", label)); // and nan.
+    program.push_str("    li t2, 2047\n");
+    program.push_str(&format!("{}_pack:\n", label));
+    program.push_str("    slli t2, t2, 20\n");
+    program.push_str("    or t2, t2, t4\n");
+    program.push_str("    srli t5, t3, 3\n");
+    program.push_str("    or t1, t2, t5\n");
+    program.push_str("    slli t2, t3, 29\n");
+    program
+}
+
+// operands are the bits in `t1` and `t2`, the result goes to `t1`.
+// `label` is unique to the call, for the intrinsics that branch.
+fn float_intrinsic(name: &str, label: &str) -> String {
+    let binary = |instr: &str| format!("    fmv.w.x ft0, t1\n    fmv.w.x ft1, t2\n{}", instr);
+    match name {
+        "fadd" | "fsub" | "fmul" | "fdiv" => binary(&format!("    {}.s ft0, ft0, ft1\n    fmv.x.w t1, ft0\n", name)),
+        "feq" => binary("    feq.s t1, ft0, ft1\n"),
+        "fne" => binary("    feq.s t1, ft0, ft1\n    seqz t1, t1\n"),
+        "flt" => binary("    flt.s t1, ft0, ft1\n"),
+        "fle" => binary("    fle.s t1, ft0, ft1\n"),
+        "fgt" => binary("    flt.s t1, ft1, ft0\n"),
+        "fge" => binary("    fle.s t1, ft1, ft0\n"),
+        "itof" => "    fcvt.s.w ft0, t1\n    fmv.x.w t1, ft0\n".to_string(),
+        "ftoi" => "    fmv.w.x ft0, t1\n    fcvt.w.s t1, ft0, rtz\n".to_string(), // truncate, as C does.
+        "ftod_hi" => float_to_double(label),
+        "ftod_lo" => float_to_double(label) + "    mv t1, t2\n",
+        "ptoi" => "".to_string(), // an address is already an `int`.
+        _ => panic!("No intrinsic named this."),
+    }
+}
+
+
+// the value is in `t1`, out of range (unsigned, below `low` too) goes to `default`.
+// returns the code and the table for `.rodata`.
+fn jump_table(scope: &HashMap<Value, (i32, i32)>, table: &JumpTable, data_graph: &DataFlowGraph, label: &str) -> (String, String) {
+    let mut program = value2register(scope, &table.value, data_graph, "t1");
+    program.push_str(&format!("    li t2, {}\n    sub t1, t1, t2\n", table.low));
+    program.push_str(&format!("    li t2, {}\n    bgeu t1, t2, {}\n", table.targets.len(), block2str(&table.default)));
+    program.push_str(&format!("    la t2, {}_table\n    slli t1, t1, 2\n    add t2, t2, t1\n", label));
+    program.push_str("    lw t2, 0(t2)\n    jr t2\n");
+
+    let mut rodata = format!("    .section .rodata\n    .p2align 2\n{}_table:\n", label);
+    for bb in &table.targets {
+        rodata.push_str(&format!("    .word {}\n", block2str(bb)));
+    }
+    (program, rodata)
 }
 
 
@@ -204,18 +347,27 @@ struct RetValue {
     program: String,
     stack_size: i32,
 }
+// what the function being generated needs to know besides its own data.
+// `abi` has every function, `func` is the function being generated, `tables` are the jump tables of the `switch`es.
+struct GenContext<'a> {
+    koopa: &'a Program,
+    abi: &'a HashMap<Function, FloatAbi>,
+    tables: &'a HashMap<BasicBlock, JumpTable>,
+    func: Function,
+}
+
 trait GenerateAsm {
-    fn gen(&self, koopa: &Program, func_data: &koopa::ir::FunctionData, scope: &mut HashMap<Value, (i32, i32)>, stack_size: i32, mx_size: i32) -> RetValue;
+    fn gen(&self, ctx: &GenContext, func_data: &koopa::ir::FunctionData, scope: &mut HashMap<Value, (i32, i32)>, stack_size: i32, mx_size: i32) -> RetValue;
 }
 
 trait GenerateAsmFunc {
-    fn gen(&self, koopa: &Program, scope: &mut HashMap<Value, (i32, i32)>, param_len: i32) -> String;
+    fn gen(&self, ctx: &GenContext, scope: &mut HashMap<Value, (i32, i32)>, param_len: i32) -> String;
 }
 
 
 impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
 
-    fn gen(&self, koopa: &Program, func_data: &koopa::ir::FunctionData, scope: &mut HashMap<Value, (i32, i32)>, stack_size: i32, mx_size: i32) -> RetValue {
+    fn gen(&self, ctx: &GenContext, func_data: &koopa::ir::FunctionData, scope: &mut HashMap<Value, (i32, i32)>, stack_size: i32, mx_size: i32) -> RetValue {
         let mut stack_size = stack_size;
         let mut program = "".to_string();
 
@@ -263,7 +415,7 @@ impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
                 },
                 ValueKind::Load(load) => {
                     // let src = data_graph.value(load.src());
-                    let fragment = load2register(&scope, &load.src(), data_graph, "t1", value_data.ty().is_i32());
+                    let fragment = load2register(scope, &load.src(), data_graph, "t1");
                     program.push_str(&fragment);
 
                     stack_size -= MACHINE_BYTE; // only  wrong!!!
@@ -283,22 +435,21 @@ impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
                     // let dst = ;
                     let pos = scope.get(&store.dest()).unwrap();
 
-                    let not_pointer = data_graph.value(store.value()).ty().is_i32();
                     // let x = data_graph.value(store.dest()).ty();
 
                     if pos.0 == INTEGER_POINTER  {
-                        let fragment = load2register(&scope, &store.value(), data_graph, "t1", not_pointer);
+                        let fragment = value2register(scope, &store.value(), data_graph, "t1");
                         program.push_str(&fragment);
                         program.push_str(&riscv_sw("t1", "sp", pos.1));
                         // program.push_str(&format!("    sw t1, {}(sp)\n", pos.1));
                     } else if pos.0 == GLOBAL_INTEGER {
-                        let fragment = load2register(&scope, &store.value(), data_graph, "t1", not_pointer);
+                        let fragment = value2register(scope, &store.value(), data_graph, "t1");
                         program.push_str(&fragment);
                         program.push_str(&format!("    la t2, glb_var{}\n", pos.1));
                         program.push_str(&riscv_sw("t1", "t2", 0));
                         // program.push_str(&format!("    sw t1, 0(t2)\n"));
                     } else if pos.0 == REAL_POINTER {
-                        let fragment = load2register(&scope, &store.value(), data_graph, "t1", not_pointer);
+                        let fragment = value2register(scope, &store.value(), data_graph, "t1");
                         program.push_str(&fragment);
                         program.push_str(&riscv_lw("t2", "sp", pos.1)); // get pointer value.
                         // program.push_str(&format!("    lw t2, {}(sp)\n", pos.1));
@@ -351,7 +502,7 @@ impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
                     let src = getptr.src();
                     let idx = getptr.index();
 
-                    let idx_str = load2register(scope, &idx, data_graph, "t1", true);
+                    let idx_str = value2register(scope, &idx, data_graph, "t1");
                     program.push_str(&idx_str);
                     let pt_str = loadpointer2register(scope, &src, "t1", "t0", type_size);
                     program.push_str(&pt_str);
@@ -367,7 +518,7 @@ impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
                     let src = getelemptr.src();
                     let idx = getelemptr.index();
 
-                    let idx_str = load2register(scope, &idx, data_graph, "t1", true);
+                    let idx_str = value2register(scope, &idx, data_graph, "t1");
                     program.push_str(&idx_str);
                     let pt_str = loadpointer2register(scope, &src, "t1", "t0", type_size);
                     program.push_str(&pt_str);
@@ -380,8 +531,8 @@ impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
                 ValueKind::Binary(binary) => {
                     let lhs = binary.lhs();
                     let rhs = binary.rhs();
-                    let fragl = load2register(&scope, &lhs, data_graph, "t1", true);
-                    let fragr = load2register(&scope, &rhs, data_graph, "t2", true);
+                    let fragl = value2register(scope, &lhs, data_graph, "t1");
+                    let fragr = value2register(scope, &rhs, data_graph, "t2");
                     program.push_str(&fragl);
                     program.push_str(&fragr);
 
@@ -416,7 +567,7 @@ impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
                     let br_true = block2str(&br.true_bb());
                     let br_false = block2str(&br.false_bb());
 
-                    let frag = load2register(scope, &br.cond(), data_graph, "t1", true);
+                    let frag = value2register(scope, &br.cond(), data_graph, "t1");
                     program.push_str(&frag);
                     // bnez t0, then
                     // j else
@@ -429,29 +580,46 @@ impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
                 },
                 ValueKind::Call(func_call) => {
                     let args = func_call.args();
-                    let name = koopa.func(func_call.callee()).name();
+                    let name = ctx.koopa.func(func_call.callee()).name();
+
+                    // a `float` operation, no call at all.
+                    if let Some(intrinsic) = intrinsic(name) {
+                        for (arg, dst) in args.iter().zip(["t1", "t2"]) {
+                            program.push_str(&value2register(scope, arg, data_graph, dst));
+                        }
+                        program.push_str(&float_intrinsic(intrinsic, &value2str(&inst)));
+
+                        stack_size -= MACHINE_BYTE;
+                        scope.insert(inst, (INTEGER_POINTER, stack_size));
+                        program.push_str(&riscv_sw("t1", "sp", stack_size));
+                        continue;
+                    }
 
                     // pass parameter.
-                    for (i, arg) in args.iter().enumerate() {
-                        let dst = {
-                            if i <= 7 { // a0-a7.
-                                format!("a{}", i.to_string())
-                            } else {
-                                format!("t1")
-                            }
+                    let callee = &ctx.abi[&func_call.callee()];
+                    for (arg, slot) in args.iter().zip(arg_slots(&callee.params)) {
+                        let dst = match slot {
+                            ArgSlot::Int(i) => format!("a{}", i), // a0-a7.
+                            _ => "t1".to_string(),
                         };
-                        // if  { // we want to pass a `i32`.
-                        let ret_val = load2register(scope, arg, data_graph, &dst, data_graph.value(*arg).ty().is_i32());
+                        let ret_val = value2register(scope, arg, data_graph, &dst);
                         program.push_str(&ret_val);
-                        if i > 7 { // spilled
-                            program.push_str(&riscv_sw("t1", "sp", ((i - 8) * 4) as i32));
-                            // program.push_str(&format!("    sw t1, {}(sp)\n", (i - 8) * 4));
+                        match slot {
+                            ArgSlot::Float(i) => program.push_str(&format!("    fmv.w.x fa{}, t1\n", i)),
+                            ArgSlot::Stack(i) => { // spilled
+                                program.push_str(&riscv_sw("t1", "sp", (i * 4) as i32));
+                                // program.push_str(&format!("    sw t1, {}(sp)\n", (i - 8) * 4));
+                            },
+                            ArgSlot::Int(_) => {},
                         }
                     }
                     // call function.
                     program.push_str(&format!("    call {}\n", &name[1..name.len()]));
                     if !value_data.ty().is_unit() {
-                        // we only have `integer` return value.
+                        // `int` in a0, `float` in fa0, both are kept as bits.
+                        if callee.ret {
+                            program.push_str("    fmv.x.w a0, fa0\n");
+                        }
                         stack_size -= 4;
                         scope.insert(inst, (INTEGER_POINTER, stack_size));
 
@@ -459,12 +627,12 @@ impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
                     }
                 },
                 ValueKind::Return(val) => { // ret
-                    match val.value() {
-                        Some(x) => {
-                            let loader = load2register(&scope, &x, data_graph, "a0", true);
-                            program.push_str(&loader);
-                        },
-                        None => {},
+                    if let Some(x) = val.value() {
+                        let loader = value2register(scope, &x, data_graph, "a0");
+                        program.push_str(&loader);
+                        if ctx.abi[&ctx.func].ret {
+                            program.push_str("    fmv.w.x fa0, a0\n");
+                        }
                     }
                     
                     program.push_str(&riscv_lw("ra", "sp", mx_size - 4));
@@ -477,7 +645,7 @@ impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
             }
         }
 
-        return RetValue {program, stack_size};
+        RetValue {program, stack_size}
     }
 }
 
@@ -488,8 +656,8 @@ impl GenerateAsm for koopa::ir::layout::BasicBlockNode {
 
 // 为什么impl不行, impl trait就行呢.
 impl GenerateAsmFunc for koopa::ir::FunctionData {
-    fn gen(&self, koopa: &Program, scope: &mut HashMap<Value, (i32, i32)>, param_len: i32) -> String {
-        if self.layout().bbs().len() == 0 { // `std` function, we don't cope with.
+    fn gen(&self, ctx: &GenContext, scope: &mut HashMap<Value, (i32, i32)>, param_len: i32) -> String {
+        if self.layout().bbs().is_empty() { // `std` function, we don't cope with.
             return "".to_string();
         }
 
@@ -516,7 +684,7 @@ impl GenerateAsmFunc for koopa::ir::FunctionData {
 
         // load parameter.
         // must discriminate `pointer` and `i32`.
-        for (i, param) in self.params().iter().enumerate() {
+        for (param, slot) in self.params().iter().zip(arg_slots(&ctx.abi[&ctx.func].params)) {
             let param_type = {
                 if self.dfg().value(*param).ty().is_i32() {
                     INTEGER_POINTER
@@ -524,33 +692,56 @@ impl GenerateAsmFunc for koopa::ir::FunctionData {
                     REAL_POINTER
                 }
             };
-            if i > 7 {
-                scope.insert(param.clone(), (param_type, origin_stack_size + (4 * (i - 8) as i32)));
-            } else { // pass through a0-a7
-                stack_size -= 4;
-                scope.insert(param.clone(), (param_type, stack_size));
-                program.push_str(&riscv_sw(&format!("a{}", i), "sp", stack_size));
-                // program.push_str(&format!("    sw a{}, {}(sp)\n", i, stack_size));
+            match slot {
+                ArgSlot::Stack(i) => {
+                    scope.insert(*param, (param_type, origin_stack_size + (4 * i as i32)));
+                },
+                ArgSlot::Int(i) => { // pass through a0-a7
+                    stack_size -= 4;
+                    scope.insert(*param, (param_type, stack_size));
+                    program.push_str(&riscv_sw(&format!("a{}", i), "sp", stack_size));
+                    // program.push_str(&format!("    sw a{}, {}(sp)\n", i, stack_size));
+                },
+                ArgSlot::Float(i) => { // pass through fa0-fa7, kept as bits.
+                    stack_size -= 4;
+                    scope.insert(*param, (param_type, stack_size));
+                    program.push_str(&format!("    fmv.x.w t1, fa{}\n", i));
+                    program.push_str(&riscv_sw("t1", "sp", stack_size));
+                },
             }
         }
 
+        // the comparisons replaced by a jump table.
+        let skipped: HashSet<BasicBlock> = ctx.tables.values().flat_map(|table| table.blocks.iter().copied()).collect();
+        let mut rodata = "".to_string();
         for (&bb, node) in self.layout().bbs() {
-            program.push_str(&format!("\n{}:\n", block2str(&bb)));
+            if skipped.contains(&bb) {
+                continue;
+            }
+            let label = block2str(&bb);
+            program.push_str(&format!("\n{}:\n", label));
+            if let Some(table) = ctx.tables.get(&bb) {
+                let (code, table) = jump_table(scope, table, self.dfg(), &label);
+                program.push_str(&code);
+                rodata.push_str(&table);
+                continue;
+            }
             // remember inherit the stack_size!
-            let ret_val = node.gen(&koopa, self, scope, stack_size, origin_stack_size);
+            let ret_val = node.gen(ctx, self, scope, stack_size, origin_stack_size);
             program.push_str(&ret_val.program);
             stack_size = ret_val.stack_size;
         }
+        program.push_str(&rodata);
         program.push_str("\n\n\n");
 
         // we have to replace stack_size.
-        return program;
+        program
     }
 }
 
 
 
-pub fn generate (koopa_program: Program) -> String {
+pub fn generate (koopa_program: Program, abi: &HashMap<Function, FloatAbi>, tables: &HashMap<BasicBlock, JumpTable>) -> String {
 
     koopa::ir::Type::set_ptr_size(4); // set 32-bit machine.
     
@@ -563,10 +754,20 @@ pub fn generate (koopa_program: Program) -> String {
 
 
     // global variable initialize.
-    let mut glb_count = 0;
-    for glb_var in koopa_program.inst_layout() {
+    for (glb_count, glb_var) in koopa_program.inst_layout().iter().enumerate() {
+        let glb_count = glb_count as i32;
         let data = f.get(glb_var).unwrap();
         let name = format!("glb_var{}", glb_count); 
+
+        // a string literal, only read by `putf`.
+        if data.name().as_ref().is_some_and(|name| name.starts_with(STRING_PREFIX)) {
+            let ValueKind::GlobalAlloc(val) = data.kind() else { panic!("Global variable initialize error.") };
+            let bytes = aggre_flatmap_hashmap(f.get(&val.init()).unwrap(), &f);
+            program.push_str(&format!("    .section .rodata\n{}:\n    .asciz \"{}\"\n", &name, asciz(&bytes)));
+            scope.insert(*glb_var, (GLOBAL_ARRAY, glb_count));
+            program.push_str("\n\n\n\n");
+            continue;
+        }
         program.push_str(&format!("    .data\n    .globl {}\n{}:\n", &name, &name));
 
         match data.kind() {
@@ -601,19 +802,71 @@ pub fn generate (koopa_program: Program) -> String {
             _ => panic!("Global variable initialize error.")
         }
 
-        glb_count += 1;
         program.push_str("\n\n\n\n");
     }
 
     // println!("asd123www: {:?}\n", koopa_program.inst_layout());
 
+    // the outgoing arguments on the stack, enough for any callee. a call may come before the callee,
+    // and `putf` is only a declaration.
     let mut param_mxlen: i32 = 0;
+    for (func, callee) in abi {
+        if intrinsic(koopa_program.func(*func).name()).is_some() {
+            continue;
+        }
+        let spilled = arg_slots(&callee.params).iter().filter(|slot| matches!(slot, ArgSlot::Stack(_))).count();
+        param_mxlen = std::cmp::max(param_mxlen, spilled as i32);
+    }
     for &func in koopa_program.func_layout() {
         let func_data = koopa_program.func(func);
-        param_mxlen = std::cmp::max(param_mxlen, (func_data.params().len() as i32) - 8);
-        let ret_val = func_data.gen(&koopa_program, &mut scope, param_mxlen);
+        if func_data.layout().bbs().is_empty() { // library functions and intrinsics.
+            continue;
+        }
+        let ctx = GenContext { koopa: &koopa_program, abi, tables, func };
+        let ret_val = func_data.gen(&ctx, &mut scope, param_mxlen);
         program.push_str(&ret_val);
     }
 
     program.to_string()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::semantic::Extensions;
+    use crate::testing::riscv;
+
+    // how the arguments of the `putf` of `label` are passed: `li a1, 3`, `lw a2, `, `sw t1, 0(sp)`.
+    fn putf_call(asm: &str, label: &str) -> Vec<String> {
+        let start = asm.find(&format!("la t0, {}\n", label)).unwrap();
+        let end = start + asm[start..].find("call putf\n").unwrap();
+        let args = asm[start..end].lines().map(str::trim).filter(|line| line.starts_with("li a") || line.starts_with("lw a")
+            || line.starts_with("sw t1, 0(sp)") || line.starts_with("sw t1, 4(sp)"));
+        // the frame offsets of the loaded values don't matter.
+        args.map(|line| match line.find('(') {
+            Some(paren) if line.starts_with("lw") => line[..paren].trim_end_matches(char::is_numeric).to_string(),
+            _ => line.to_string(),
+        }).collect()
+    }
+
+    #[test]
+    fn strings_are_asciz_in_rodata() {
+        let asm = riscv("int main() {\n  putf(\"a\\tb\\\"%d\\\\\\n\", 1);\n  putf(\"a\\tb\\\"%d\\\\\\n\", 2);\n  putf(\"\\x41 \\101\");\n\
+            return 0;\n}\n", Extensions::default());
+        assert!(asm.contains("    .section .rodata\nglb_var0:\n    .asciz \"a\\011b\\042%d\\134\\012\"\n"));
+        assert!(asm.contains("glb_var1:\n    .asciz \"A A\"\n"));
+        assert_eq!(asm.matches(".asciz").count(), 2); // the same literal is one global.
+        assert_eq!(asm.matches("la t0, glb_var0\n").count(), 2);
+    }
+
+    #[test]
+    fn floats_in_putf_take_an_aligned_pair() {
+        let asm = riscv("int main() {\n  float f = 1.5;\n  putf(\"%d %f %d\", 3, f, 4);\n  putf(\"%d%d%d%d%d%d %f\", 1, 2, 3, 4, 5, 6, f);\n\
+            return 0;\n}\n", Extensions::default());
+        // after an `int` in `a1`, the `double` takes the even pair `a2`/`a3`.
+        let first = putf_call(&asm, "glb_var0");
+        assert_eq!(&first[..5], ["lw a0, ", "li a1, 3", "lw a2, ", "lw a3, ", "li a4, 4"]);
+        // `a7` is odd, the `double` goes to the stack, 8-byte aligned at `0(sp)`.
+        let second = putf_call(&asm, "glb_var1");
+        assert_eq!(&second[6..], ["li a6, 6", "li a7, 0", "sw t1, 0(sp)", "sw t1, 4(sp)"]);
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostic::{Diagnostic, Span};
+
+/*
+ * 函数之间的调用关系, semantic 检查的时候顺便记下来.
+ * koopa 的文本格式只认识已经出现过的函数, 所以输出的时候被调用的函数要排在调用它的函数前面,
+ * 递归调用自己没关系, 两个以上的函数互相递归就排不出来了, 只有 `-riscv` 能处理.
+ */
+
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    calls: HashMap<String, Vec<(String, Span)>>, // the callees of each function, with the first call to each.
+}
+
+// a call that closes a cycle of mutually recursive functions.
+#[derive(Debug, PartialEq)]
+pub struct Cycle {
+    pub caller: String,
+    pub callee: String,
+    pub span: Span,
+}
+
+impl From<Cycle> for Diagnostic {
+    fn from(cycle: Cycle) -> Diagnostic {
+        Diagnostic::error(cycle.span, "mutual recursion is not representable in Koopa text; use -riscv".to_string())
+            .with_label(format!("`{}` calls back into `{}`", cycle.caller, cycle.callee))
+    }
+}
+
+impl CallGraph {
+    pub fn record(&mut self, caller: &str, callee: &str, span: Span) {
+        let callees = self.calls.entry(caller.to_string()).or_default();
+        if !callees.iter().any(|(name, _)| name == callee) {
+            callees.push((callee.to_string(), span));
+        }
+    }
+
+    // `functions` ordered so that a callee comes before its callers, otherwise in the given order.
+    // the first call found that can't be ordered is returned too.
+    pub fn callees_first(&self, functions: &[String]) -> (Vec<String>, Option<Cycle>) {
+        let mut walk = Walk { on_stack: HashSet::new(), done: HashSet::new(), order: Vec::new(), cycle: None };
+        for func in functions {
+            self.visit(func, &mut walk);
+        }
+        walk.order.retain(|func| functions.contains(func)); // the runtime library is declared anyway.
+        (walk.order, walk.cycle)
+    }
+
+    // depth first, a function is done after all its callees.
+    fn visit(&self, func: &str, walk: &mut Walk) {
+        if walk.done.contains(func) {
+            return;
+        }
+        walk.on_stack.insert(func.to_string());
+        for (callee, span) in self.calls.get(func).into_iter().flatten() {
+            if walk.on_stack.contains(callee) {
+                if callee != func { // calling itself is fine.
+                    walk.cycle.get_or_insert(Cycle { caller: func.to_string(), callee: callee.clone(), span: *span });
+                }
+                continue;
+            }
+            self.visit(callee, walk);
+        }
+        walk.on_stack.remove(func);
+        walk.done.insert(func.to_string());
+        walk.order.push(func.to_string());
+    }
+}
+
+struct Walk {
+    on_stack: HashSet<String>,
+    done: HashSet<String>,
+    order: Vec<String>,
+    cycle: Option<Cycle>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(calls: &[(&str, &str)]) -> CallGraph {
+        let mut graph = CallGraph::default();
+        for (i, (caller, callee)) in calls.iter().enumerate() {
+            graph.record(caller, callee, Span::new(i, i + 1));
+        }
+        graph
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn callees_come_first() {
+        let graph = graph(&[("main", "f"), ("f", "h"), ("main", "putint"), ("f", "f")]);
+        let (order, cycle) = graph.callees_first(&names(&["main", "h", "f"]));
+        assert_eq!(order, names(&["h", "f", "main"]));
+        assert_eq!(cycle, None); // `f` calling itself is fine.
+    }
+
+    #[test]
+    fn mutual_recursion_is_a_cycle() {
+        let graph = graph(&[("main", "f"), ("f", "g"), ("g", "f")]);
+        let (order, cycle) = graph.callees_first(&names(&["f", "g", "main"]));
+        assert_eq!(order, names(&["g", "f", "main"]));
+        assert_eq!(cycle, Some(Cycle { caller: "g".to_string(), callee: "f".to_string(), span: Span::new(2, 3) }));
+        let diag = Diagnostic::from(cycle.unwrap());
+        assert_eq!(diag.message, "mutual recursion is not representable in Koopa text; use -riscv");
+    }
+}
@@ -0,0 +1,125 @@
+use super::types::Type;
+
+/*
+ * 常量求值, 语义检查和 generator 共用, 两边折叠出来的值一定一样.
+ * 结果和运行时 risc-v 上算出来的一致, 不会因为溢出或者除以 0 panic.
+ * `float` 常量是 i32 里的 bit pattern.
+ */
+
+// `int` wraps around in two's complement, the same as the run-time result of risc-v:
+// `INT_MIN / -1` is `INT_MIN` and `INT_MIN % -1` is 0, `div` by zero gives -1 and `rem` by zero the dividend.
+// the check reports a constant division by zero, the value here only keeps the folding total.
+pub fn fold(op: &str, a: i32, b: i32) -> i32 {
+    match op {
+        "mul" => a.wrapping_mul(b),
+        "div" => if b == 0 { -1 } else { a.wrapping_div(b) },
+        "mod" => if b == 0 { a } else { a.wrapping_rem(b) },
+        "add" => a.wrapping_add(b),
+        "sub" => a.wrapping_sub(b),
+        "eq" => (a == b) as i32,
+        "ne" => (a != b) as i32,
+        "lt" => (a < b) as i32,
+        "gt" => (a > b) as i32,
+        "le" => (a <= b) as i32,
+        "ge" => (a >= b) as i32,
+        "and" => a & b,
+        "or" => a | b,
+        "xor" => a ^ b,
+        "shl" => a.wrapping_shl(b as u32), // only the low 5 bits of the amount count, the same as `sll`.
+        "sar" => a.wrapping_shr(b as u32),
+        "land" => (a != 0 && b != 0) as i32,
+        "lor" => (a != 0 || b != 0) as i32,
+        _ => panic!("No operator labeled this."),
+    }
+}
+
+// single precision, the same as `fadd.s` and friends. Comparisons give an `int`.
+pub fn fold_float(op: &str, a: f32, b: f32) -> (Type, i32) {
+    let float = |x: f32| (Type::Float, x.to_bits() as i32);
+    let int = |x: bool| (Type::Int, x as i32);
+    match op {
+        "mul" => float(a * b),
+        "div" => float(a / b),
+        "add" => float(a + b),
+        "sub" => float(a - b),
+        "eq" => int(a == b),
+        "ne" => int(a != b),
+        "lt" => int(a < b),
+        "gt" => int(a > b),
+        "le" => int(a <= b),
+        "ge" => int(a >= b),
+        "land" => int(a != 0.0 && b != 0.0),
+        "lor" => int(a != 0.0 || b != 0.0),
+        _ => panic!("No operator labeled this."),
+    }
+}
+
+// the implicit conversion of a constant, `float` to `int` truncates.
+pub fn convert_constant(value: i32, from: &Type, to: &Type) -> i32 {
+    match (from, to) {
+        (Type::Int, Type::Float) => (value as f32).to_bits() as i32,
+        (Type::Float, Type::Int) => f32::from_bits(value as u32) as i32,
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::Extensions;
+    use crate::testing::{errors, koopa};
+
+    #[test]
+    fn division_never_panics() {
+        assert_eq!(fold("div", i32::MIN, -1), i32::MIN);
+        assert_eq!(fold("mod", i32::MIN, -1), 0);
+        assert_eq!(fold("div", 7, 0), -1);
+        assert_eq!(fold("mod", 7, 0), 7);
+        assert_eq!(fold("div", -7, 2), -3); // truncates toward zero.
+        assert_eq!(fold("mod", -7, 2), -1);
+    }
+
+    #[test]
+    fn arithmetic_wraps() {
+        assert_eq!(fold("add", i32::MAX, 1), i32::MIN);
+        assert_eq!(fold("sub", i32::MIN, 1), i32::MAX);
+        assert_eq!(fold("mul", 65536, 65536), 0);
+    }
+
+    #[test]
+    fn shift_amount_is_masked() {
+        assert_eq!(fold("shl", 1, 31), i32::MIN);
+        assert_eq!(fold("shl", 1, 32), 1);
+        assert_eq!(fold("shl", 1, 33), 2);
+        assert_eq!(fold("sar", -8, 1), -4); // arithmetic shift.
+        assert_eq!(fold("sar", -8, 35), -1);
+        assert_eq!(fold("shl", 1, -1), i32::MIN);
+    }
+
+    #[test]
+    fn logic_gives_0_or_1() {
+        assert_eq!(fold("land", 2, -3), 1);
+        assert_eq!(fold("lor", 0, 0), 0);
+        assert_eq!(fold("lt", -1, 0), 1);
+    }
+
+    #[test]
+    fn float_constants() {
+        let bits = |x: f32| x.to_bits() as i32;
+        assert_eq!(fold_float("div", 1.0, 0.0), (Type::Float, bits(f32::INFINITY)));
+        assert_eq!(fold_float("lt", 1.0, 2.0), (Type::Int, 1));
+        assert_eq!(fold_float("land", 0.5, -0.0), (Type::Int, 0));
+        assert_eq!(convert_constant(bits(-2.9), &Type::Float, &Type::Int), -2);
+        assert_eq!(convert_constant(3, &Type::Int, &Type::Float), bits(3.0));
+    }
+
+    #[test]
+    fn negated_int_min_literal() {
+        let ir = koopa("const int M = -2147483648;\nint a[-2147483648 + 2147483647 + 3];\nint main() {\n  return M;\n}\n",
+            Extensions::default());
+        assert!(ir.contains("global @a = alloc [i32, 2], zeroinit\n"), "{}", ir);
+        assert!(ir.contains("ret -2147483648\n"), "{}", ir);
+        let errors = errors("int main() {\n  return 2147483648;\n}\n", Extensions::default());
+        assert!(errors.starts_with("error: integer literal is too large for `int`\n --> t.c:2:10\n"), "{}", errors);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::diagnostic::{Diagnostic, Span};
+use super::types::{FunctionSignature, Type};
+
+/*
+ * 语义检查发现的错误, 每种错误一个 variant, 带上出错位置的 span.
+ * 只在最后打印的时候才转成 Diagnostic.
+ */
+
+#[derive(Debug)]
+pub enum SemanticError {
+    UndeclaredVariable { name: String, span: Span },
+    UndeclaredFunction { name: String, span: Span },
+    Redeclared { name: String, span: Span },
+    ReservedName { name: String, span: Span },
+    ConflictingSignature { name: String, previous: FunctionSignature, found: FunctionSignature, span: Span },
+    AssignToConstant { name: String, span: Span },
+    NonConstantDimension { span: Span },
+    NonPositiveDimension { value: i32, span: Span },
+    NonConstantInitializer { name: String, span: Span },
+    ArrayNeedsList { name: String, span: Span },
+    ExcessElements { name: String, span: Span },
+    BracedScalar { name: String, span: Span },
+    InitializerTooDeep { name: String, dims: usize, span: Span },
+    BreakOutsideLoop { span: Span },
+    ContinueOutsideLoop { span: Span },
+    NonConstantCase { span: Span },
+    DuplicateCase { value: i32, span: Span },
+    DuplicateDefault { span: Span },
+    ArgumentCount { name: String, expected: usize, variadic: bool, found: usize, span: Span },
+    ArgumentType { name: String, position: usize, expected: Type, found: Type, span: Span },
+    MismatchedTypes { expected: Type, found: Type, span: Span },
+    TooManySubscripts { name: String, span: Span },
+    ReturnValueInVoid { name: String, span: Span },
+    MissingReturnValue { name: String, span: Span },
+    RuntimeDefinition { name: String, span: Span },
+    Extension { feature: String, flag: String, span: Span },
+    NotAPointer { found: Type, span: Span },
+    AddressOfConstant { name: String, span: Span },
+    AddressOfPointer { name: String, span: Span },
+    IntegerTooLarge { span: Span },
+    DivisionByZero { span: Span },
+    IndexOutOfBounds { name: String, index: i32, len: i32, span: Span },
+    UndeclaredStruct { name: String, span: Span },
+    NoSuchField { name: String, field: String, span: Span },
+    NotAStruct { found: Type, span: Span },
+    StructInitializer { name: String, span: Span },
+    StructPointer { span: Span },
+    FieldArray { name: String, span: Span },
+}
+
+impl From<SemanticError> for Diagnostic {
+    fn from(err: SemanticError) -> Diagnostic {
+        match err {
+            SemanticError::UndeclaredVariable { name, span } => {
+                Diagnostic::error(span, format!("use of undeclared identifier `{}`", name))
+            },
+            SemanticError::UndeclaredFunction { name, span } => {
+                Diagnostic::error(span, format!("call to undeclared function `{}`", name))
+            },
+            SemanticError::Redeclared { name, span } => {
+                Diagnostic::error(span, format!("redefinition of `{}`", name))
+                    .with_label("already defined in this scope".to_string())
+            },
+            SemanticError::ReservedName { name, span } => {
+                Diagnostic::error(span, format!("`{}` is a reserved name", name))
+                    .with_label("global names starting with `__sysy_` are used by the generated code".to_string())
+            },
+            SemanticError::ConflictingSignature { name, previous, found, span } => {
+                Diagnostic::error(span, format!("conflicting types for `{}`", name))
+                    .with_label(format!("declared before as `{}`, found `{}`", previous, found))
+            },
+            SemanticError::AssignToConstant { name, span } => {
+                Diagnostic::error(span, format!("cannot assign to constant `{}`", name))
+            },
+            SemanticError::NonConstantDimension { span } => {
+                Diagnostic::error(span, "array dimension is not a constant expression".to_string())
+            },
+            SemanticError::NonPositiveDimension { value, span } => {
+                Diagnostic::error(span, format!("array dimension must be positive, found {}", value))
+            },
+            SemanticError::NonConstantInitializer { name, span } => {
+                Diagnostic::error(span, format!("initializer of `{}` is not a constant expression", name))
+            },
+            SemanticError::ArrayNeedsList { name, span } => {
+                Diagnostic::error(span, format!("array `{}` must be initialized with a braced list", name))
+            },
+            SemanticError::ExcessElements { name, span } => {
+                Diagnostic::error(span, format!("excess elements in initializer of `{}`", name))
+            },
+            SemanticError::BracedScalar { name, span } => {
+                Diagnostic::error(span, format!("braces around scalar initializer of `{}`", name))
+                    .with_label("this element is an `int`, not the start of a sub-array".to_string())
+            },
+            SemanticError::InitializerTooDeep { name, dims, span } => {
+                Diagnostic::error(span, format!("initializer of `{}` is nested too deeply", name))
+                    .with_label(format!("`{}` has {} dimension{}", name, dims, if dims == 1 { "" } else { "s" }))
+            },
+            SemanticError::BreakOutsideLoop { span } => {
+                Diagnostic::error(span, "`break` statement not within a loop or switch".to_string())
+            },
+            SemanticError::ContinueOutsideLoop { span } => {
+                Diagnostic::error(span, "`continue` statement not within a loop".to_string())
+            },
+            SemanticError::NonConstantCase { span } => {
+                Diagnostic::error(span, "case label is not a constant expression".to_string())
+            },
+            SemanticError::DuplicateCase { value, span } => {
+                Diagnostic::error(span, format!("duplicate case value `{}`", value))
+            },
+            SemanticError::DuplicateDefault { span } => {
+                Diagnostic::error(span, "multiple default labels in one switch".to_string())
+            },
+            SemanticError::ArgumentCount { name, expected, variadic, found, span } => {
+                Diagnostic::error(span, format!("function `{}` takes {}{} argument{}, but {} {} given",
+                    name, if variadic { "at least " } else { "" }, expected, if expected == 1 { "" } else { "s" },
+                    found, if found == 1 { "was" } else { "were" }))
+            },
+            SemanticError::ArgumentType { name, position, expected, found, span } => {
+                Diagnostic::error(span, format!("mismatched types in argument {} of `{}`", position, name))
+                    .with_label(format!("expected `{}`, found `{}`", expected, found))
+            },
+            SemanticError::MismatchedTypes { expected, found, span } => {
+                Diagnostic::error(span, "mismatched types".to_string())
+                    .with_label(format!("expected `{}`, found `{}`", expected, found))
+            },
+            SemanticError::TooManySubscripts { name, span } => {
+                Diagnostic::error(span, format!("`{}` is subscripted more times than it has dimensions", name))
+            },
+            SemanticError::ReturnValueInVoid { name, span } => {
+                Diagnostic::error(span, format!("void function `{}` should not return a value", name))
+            },
+            SemanticError::MissingReturnValue { name, span } => {
+                Diagnostic::error(span, format!("non-void function `{}` should return a value", name))
+            },
+            SemanticError::RuntimeDefinition { name, span } => {
+                Diagnostic::error(span, format!("`-runtime` takes prototypes only, `{}` has a body", name))
+            },
+            SemanticError::Extension { feature, flag, span } => {
+                Diagnostic::error(span, format!("{} are a language extension", feature))
+                    .with_label(format!("enable them with `{}`", flag))
+            },
+            SemanticError::NotAPointer { found, span } => {
+                Diagnostic::error(span, "cannot dereference a non-pointer".to_string())
+                    .with_label(format!("expected a pointer, found `{}`", found))
+            },
+            SemanticError::AddressOfConstant { name, span } => {
+                Diagnostic::error(span, format!("cannot take the address of constant `{}`", name))
+            },
+            SemanticError::AddressOfPointer { name, span } => {
+                Diagnostic::error(span, format!("cannot take the address of pointer `{}`", name))
+                    .with_label("pointers to pointers are not supported".to_string())
+            },
+            SemanticError::IntegerTooLarge { span } => {
+                Diagnostic::error(span, "integer literal is too large for `int`".to_string())
+            },
+            SemanticError::DivisionByZero { span } => {
+                Diagnostic::error(span, "division by zero in constant expression".to_string())
+            },
+            SemanticError::IndexOutOfBounds { name, index, len, span } => {
+                Diagnostic::error(span, format!("index {} is out of bounds of constant array `{}`", index, name))
+                    .with_label(format!("the length is {}", len))
+            },
+            SemanticError::UndeclaredStruct { name, span } => {
+                Diagnostic::error(span, format!("use of undeclared struct `{}`", name))
+            },
+            SemanticError::NoSuchField { name, field, span } => {
+                Diagnostic::error(span, format!("struct `{}` has no field named `{}`", name, field))
+            },
+            SemanticError::NotAStruct { found, span } => {
+                Diagnostic::error(span, "member access on a non-struct".to_string())
+                    .with_label(format!("expected a struct, found `{}`", found))
+            },
+            SemanticError::StructInitializer { name, span } => {
+                Diagnostic::error(span, format!("struct variable `{}` cannot have an initializer", name))
+                    .with_label("assign its fields instead".to_string())
+            },
+            SemanticError::StructPointer { span } => {
+                Diagnostic::error(span, "pointers to structs are not supported".to_string())
+            },
+            SemanticError::FieldArray { name, span } => {
+                Diagnostic::error(span, format!("array field `{}` cannot be used as a whole here", name))
+                    .with_label("only its last dimension decays to a pointer, subscript it down to that".to_string())
+            },
+        }
+    }
+}
@@ -0,0 +1,585 @@
+use crate::ast::*;
+use crate::diagnostic::Span;
+use super::constant::{convert_constant, fold, fold_float};
+use super::structs::Element;
+use super::types::{index_type, struct_index_type, variadic_slot, Type};
+use super::{Checker, SemanticError, Symbol};
+
+/*
+ * 和 `koopa_ir_gen/expression.rs` 一样逐层往下走.
+ * 每一层返回表达式的类型, `constant` 为 Some(value) 代表表达式是常量, `float` 的常量是它的 bit pattern.
+ * span 是 LVal, 函数调用和 Exp 的位置, 类型不对的时候用来报错.
+ * 运算符的结果总是 `int` 或者 `float`, 不会被报告, span 留空.
+ * `int` 和 `float` 一起运算的时候 `int` 先转换成 `float`, 比较和逻辑运算的结果是 `int`.
+ * 出错的表达式按 `int` 继续检查, 一个错误不会引出一串错误.
+ * 打开指针扩展之后, 指针可以加减 `int`, 同类型的指针可以相减和比较, 结果都不是常量.
+ * 指针和 `int` 的常量 0 (空指针) 可以用 `==` 和 `!=` 比较, 指针也可以当作条件, 用在 `!`, `&&` 和 `||` 里.
+ * struct 和 struct 的数组只能用来访问字段, 在别的地方都是类型不对.
+ */
+pub struct ExpValue {
+    pub ty: Type,
+    pub constant: Option<i32>,
+    pub span: Span,
+}
+
+impl ExpValue {
+    // `0`, the null pointer as well.
+    pub fn is_null(&self) -> bool {
+        self.ty == Type::Int && self.constant == Some(0)
+    }
+
+    // the value can be stored to a `target`, converted if needed. a pointer takes the null pointer too.
+    pub fn converts_to(&self, target: &Type) -> bool {
+        self.ty.converts_to(target) || matches!(target, Type::Pointer(_, _)) && self.is_null()
+    }
+}
+
+pub trait CheckExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue;
+}
+
+// `%`, the bitwise operators and the shifts.
+pub fn int_only(op: &str) -> bool {
+    matches!(op, "mod" | "and" | "or" | "xor" | "shl" | "sar")
+}
+
+// `p + i`, `i + p` and `p - i` are pointers, `p - q` and the comparisons of the same pointers are `int`.
+// `p == 0`, `p && q` and the like are `int` too. None if the operands don't fit any of them.
+fn pointer_binary(op: &str, val1: &ExpValue, val2: &ExpValue) -> Option<Type> {
+    let (ty1, ty2) = (&val1.ty, &val2.ty);
+    match (op, ty1, ty2) {
+        ("add" | "sub", Type::Pointer(_, _), Type::Int) => Some(ty1.clone()),
+        ("add", Type::Int, Type::Pointer(_, _)) => Some(ty2.clone()),
+        ("sub" | "eq" | "ne" | "lt" | "gt" | "le" | "ge", Type::Pointer(_, _), _) if ty1 == ty2 => Some(Type::Int),
+        ("eq" | "ne", Type::Pointer(_, _), _) if val2.is_null() => Some(Type::Int),
+        ("eq" | "ne", _, Type::Pointer(_, _)) if val1.is_null() => Some(Type::Int),
+        ("land" | "lor", Type::Pointer(_, _), _) | ("land" | "lor", _, Type::Pointer(_, _)) => {
+            for ty in [ty1, ty2] {
+                if !ty.is_number() && !matches!(ty, Type::Pointer(_, _)) {
+                    return None;
+                }
+            }
+            Some(Type::Int)
+        },
+        _ => None,
+    }
+}
+
+// `int` op `int` is `int`, a `float` operand makes the other one `float` too.
+// the `int_only` operators only take `int`, comparisons and logical operators always give an `int`.
+fn check_binary(lhs: &dyn CheckExp, rhs: &dyn CheckExp, op: &str, checker: &mut Checker) -> ExpValue {
+    let val1 = lhs.check(checker);
+    let val2 = rhs.check(checker);
+    binary_value(val1, val2, op, checker)
+}
+
+fn binary_value(val1: ExpValue, val2: ExpValue, op: &str, checker: &mut Checker) -> ExpValue {
+    if checker.extensions.pointers {
+        if let Some(ty) = pointer_binary(op, &val1, &val2) {
+            return ExpValue { ty, constant: None, span: Span::default() };
+        }
+    }
+    let int_only = int_only(op);
+    for val in [&val1, &val2] {
+        if int_only {
+            checker.expect_int(&val.ty, val.span);
+        } else {
+            checker.expect_number(&val.ty, val.span);
+        }
+    }
+    if val1.ty != Type::Float && val2.ty != Type::Float || int_only {
+        let constant = match (val1.constant, val2.constant) {
+            (Some(a), Some(b)) => Some(fold(op, a, b)),
+            _ => None,
+        };
+        return ExpValue { ty: Type::Int, constant, span: Span::default() };
+    }
+
+    let ty = if matches!(op, "mul" | "div" | "add" | "sub") { Type::Float } else { Type::Int };
+    let constant = match (val1.constant, val2.constant) {
+        (Some(a), Some(b)) => {
+            let a = f32::from_bits(convert_constant(a, &val1.ty, &Type::Float) as u32);
+            let b = f32::from_bits(convert_constant(b, &val2.ty, &Type::Float) as u32);
+            Some(fold_float(op, a, b).1)
+        },
+        _ => None,
+    };
+    ExpValue { ty, constant, span: Span::default() }
+}
+
+// the type of `*exp`, the element a pointer points to.
+fn deref_type(exp: &UnaryExp, checker: &mut Checker) -> Type {
+    let value = exp.check(checker);
+    match value.ty {
+        Type::Pointer(btype, dims) => {
+            let mut dims = dims;
+            dims.insert(0, 0);
+            index_type(btype, &dims, 1).unwrap()
+        },
+        found => {
+            checker.errors.push(SemanticError::NotAPointer { found, span: value.span });
+            Type::Int
+        },
+    }
+}
+
+// LVal "." IDENT {"[" Exp "]"}, a field of a struct, subscripted like an array.
+// a field decays to a pointer only at its last dimension, koopa sees nothing but the `i32`s of the struct.
+fn check_member(base: &LVal, name: &str, exps: &[Exp], span: Span, checker: &mut Checker) -> ExpValue {
+    let value = base.check(checker);
+    for exp in exps {
+        let index = exp.check(checker);
+        checker.expect_int(&index.ty, index.span);
+    }
+    let mut ret = ExpValue { ty: Type::Int, constant: None, span };
+    let struct_name = match &value.ty {
+        Type::Struct(struct_name) => struct_name.clone(),
+        found => {
+            checker.errors.push(SemanticError::NotAStruct { found: found.clone(), span: value.span });
+            return ret;
+        },
+    };
+    let field = match checker.structs.get(&struct_name) {
+        None => return ret, // an undeclared struct, already reported.
+        Some(layout) => layout.field(name).cloned(),
+    };
+    let Some(field) = field else {
+        checker.errors.push(SemanticError::NoSuchField { name: struct_name, field: name.to_string(), span });
+        return ret;
+    };
+    let ty = match &field.element {
+        Element::Basic(btype) => index_type(*btype, &field.dims, exps.len()),
+        Element::Struct(element) => struct_index_type(element, &field.dims, exps.len()),
+    };
+    match ty {
+        None => checker.errors.push(SemanticError::TooManySubscripts { name: name.to_string(), span }),
+        Some(Type::Pointer(_, dims)) if !dims.is_empty() => {
+            checker.errors.push(SemanticError::FieldArray { name: name.to_string(), span });
+        },
+        Some(ty) => ret.ty = ty,
+    }
+    ret
+}
+
+// LVal ::= IDENT {"[" Exp "]"} | "*" UnaryExp | LVal "." IDENT {"[" Exp "]"};
+impl CheckExp for LVal {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        let (ident, exps, span) = match self {
+            LVal::Ident(ident, exps, span) => (ident, exps, *span),
+            LVal::Deref(exp, span) => {
+                checker.expect_pointers(*span);
+                let ty = deref_type(exp, checker);
+                return ExpValue { ty, constant: None, span: *span };
+            },
+            LVal::Member(base, name, exps, span) => return check_member(base, name, exps, *span, checker),
+        };
+        let mut indices = Vec::new();
+        for exp in exps {
+            let value = exp.check(checker);
+            checker.expect_int(&value.ty, value.span);
+            indices.push(value.constant.filter(|_| value.ty == Type::Int));
+        }
+        let mut ret = ExpValue { ty: Type::Int, constant: None, span };
+        match checker.lookup(ident) {
+            None => {
+                checker.errors.push(SemanticError::UndeclaredVariable { name: ident.clone(), span });
+            },
+            // still a struct when subscripted too many times, so that its fields are checked.
+            Some(Symbol::Struct(name, dims)) => {
+                ret.ty = struct_index_type(name, dims, exps.len()).unwrap_or(Type::Struct(name.clone()));
+                if exps.len() > dims.len() {
+                    checker.errors.push(SemanticError::TooManySubscripts { name: ident.clone(), span });
+                }
+            },
+            Some(symbol) => {
+                if let Symbol::Constant(_, value) = symbol {
+                    if exps.is_empty() {
+                        ret.constant = Some(*value);
+                    }
+                }
+                let element = match symbol {
+                    Symbol::ConstArray(_, dims, values) => const_element(dims, values, &indices),
+                    _ => Ok(None),
+                };
+                match index_type(symbol.btype(), symbol.dims(), exps.len()) {
+                    Some(ty) => ret.ty = ty,
+                    None => {
+                        checker.errors.push(SemanticError::TooManySubscripts { name: ident.clone(), span });
+                    },
+                }
+                match element {
+                    Ok(value) => ret.constant = ret.constant.or(value),
+                    Err((k, len)) => {
+                        let index = indices[k].unwrap();
+                        checker.errors.push(SemanticError::IndexOutOfBounds { name: ident.clone(), index, len, span: exps[k].span });
+                    },
+                }
+            },
+        }
+        ret
+    }
+}
+
+// an element of a constant array read with constant indices, its value is known when compiling.
+// every constant index must be in bounds, even when the others are not constant: Err with its position and the length.
+fn const_element(dims: &[i32], values: &[i32], indices: &[Option<i32>]) -> Result<Option<i32>, (usize, i32)> {
+    let mut flat = Some(0);
+    for (k, (index, len)) in indices.iter().zip(dims).enumerate() {
+        match index {
+            Some(index) if *index < 0 || *index >= *len => return Err((k, *len)),
+            Some(index) => flat = flat.map(|flat| flat * len + index),
+            None => flat = None,
+        }
+    }
+    if indices.len() != dims.len() || values.is_empty() {
+        return Ok(None);
+    }
+    Ok(flat.map(|flat| values[flat as usize]))
+}
+
+// the type of `&lval`. a variable or an element gives a pointer to it, an array or a row gives a pointer to
+// the whole array, `&*p` is `p` itself. a pointer and an array parameter are not in memory, so no address.
+fn address_type(lval: &LVal, checker: &mut Checker) -> Type {
+    let (ident, exps, span) = match lval {
+        LVal::Ident(ident, exps, span) => (ident, exps, *span),
+        LVal::Deref(exp, span) => {
+            let value = exp.check(checker);
+            if let Type::Pointer(_, _) = value.ty {
+                return value.ty;
+            }
+            checker.errors.push(SemanticError::NotAPointer { found: value.ty, span: *span });
+            return Type::Pointer(BType::Int, vec![]);
+        },
+        // an element of a field, not the field as a whole array.
+        LVal::Member(_, name, _, span) => {
+            let value = lval.check(checker);
+            match value.ty {
+                Type::Float => return Type::Pointer(BType::Float, vec![]),
+                Type::Struct(_) | Type::StructArray(_, _) => {
+                    checker.errors.push(SemanticError::StructPointer { span: *span });
+                },
+                Type::Pointer(_, _) => {
+                    checker.errors.push(SemanticError::FieldArray { name: name.clone(), span: *span });
+                },
+                _ => {},
+            }
+            return Type::Pointer(BType::Int, vec![]);
+        },
+    };
+    let value = lval.check(checker);
+    let count = exps.len();
+    match checker.lookup(ident) {
+        Some(Symbol::Constant(_, _)) | Some(Symbol::ConstArray(_, _, _)) => {
+            checker.errors.push(SemanticError::AddressOfConstant { name: ident.clone(), span });
+        },
+        Some(Symbol::Struct(_, _)) => {
+            checker.errors.push(SemanticError::StructPointer { span });
+        },
+        Some(Symbol::Pointer(_, _)) if count == 0 => {
+            checker.errors.push(SemanticError::AddressOfPointer { name: ident.clone(), span });
+        },
+        Some(Symbol::Array(_, dims)) if count == 0 && dims[0] == 0 => {
+            checker.errors.push(SemanticError::AddressOfPointer { name: ident.clone(), span });
+        },
+        Some(symbol) if count <= symbol.dims().len() => {
+            return Type::Pointer(symbol.btype(), symbol.dims()[count..].to_vec());
+        },
+        _ => {}, // already reported.
+    }
+    match value.ty {
+        Type::Float => Type::Pointer(BType::Float, vec![]),
+        _ => Type::Pointer(BType::Int, vec![]),
+    }
+}
+
+// Exp ::= CondExp;
+impl CheckExp for Exp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        let value = self.condexp.check(checker);
+        ExpValue { span: self.span, ..value }
+    }
+}
+
+// ConstExp ::= Exp;
+impl CheckExp for ConstExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        self.exp.check(checker)
+    }
+}
+
+// PrimaryExp ::= "(" Exp ")" | LVal | Number;
+impl CheckExp for PrimaryExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            PrimaryExp::Exp(exp) => exp.check(checker),
+            PrimaryExp::Lval(lval) => lval.check(checker),
+            PrimaryExp::Num(num) => ExpValue { ty: Type::Int, constant: Some(*num), span: Span::default() },
+            PrimaryExp::BigNum(num, span) => {
+                checker.errors.push(SemanticError::IntegerTooLarge { span: *span });
+                ExpValue { ty: Type::Int, constant: Some(*num as i32), span: *span }
+            },
+            PrimaryExp::Str(_, span) => ExpValue { ty: Type::Str, constant: None, span: *span },
+            PrimaryExp::FloatNum(num, span) => {
+                ExpValue { ty: Type::Float, constant: Some(num.to_bits() as i32), span: *span }
+            },
+        }
+    }
+}
+
+// UnaryExp ::= PrimaryExp | UnaryOp UnaryExp | IDENT "(" [FuncRParams] ")" | "&" LVal
+impl CheckExp for UnaryExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            UnaryExp::Primaryexp(primaryexp) => primaryexp.check(checker),
+            UnaryExp::AddrOf(lval, span) => {
+                checker.expect_pointers(*span);
+                let ty = address_type(lval, checker);
+                ExpValue { ty, constant: None, span: *span }
+            },
+            // `starttime()` without `sylib.h`, the generator passes the line.
+            UnaryExp::Funcall(ident, None, span) if checker.timer(ident).is_some() => {
+                let entry = checker.timer(ident).unwrap();
+                checker.calls.record(&checker.current_function.0, entry, *span);
+                checker.timers.push(*span);
+                ExpValue { ty: Type::Void, constant: None, span: *span }
+            },
+            UnaryExp::Funcall(ident, params, span) => {
+                let args: &[Exp] = match params {
+                    None => &[],
+                    Some(v) => &v.params,
+                };
+                let values: Vec<ExpValue> = args.iter().map(|exp| exp.check(checker)).collect();
+
+                // a call is never constant.
+                let signature = match checker.lookup_function(ident) {
+                    None => {
+                        checker.errors.push(SemanticError::UndeclaredFunction { name: ident.clone(), span: *span });
+                        return ExpValue { ty: Type::Int, constant: None, span: *span };
+                    },
+                    Some(signature) => signature.clone(),
+                };
+                checker.calls.record(&checker.current_function.0, ident, *span);
+                let expected = signature.params.len();
+                if values.len() < expected || (values.len() > expected && !signature.variadic) {
+                    checker.errors.push(SemanticError::ArgumentCount {
+                        name: ident.clone(), expected, variadic: signature.variadic, found: values.len(), span: *span,
+                    });
+                }
+                let mut slots = expected;
+                for (i, value) in values.into_iter().enumerate() {
+                    let target = match signature.params.get(i) {
+                        Some(ty) => ty.clone(),
+                        None if !signature.variadic => break, // already reported.
+                        None if value.ty.is_number() => { // a variadic argument.
+                            let (first, len) = variadic_slot(slots, &value.ty);
+                            slots = first + len;
+                            continue;
+                        },
+                        None => Type::Int,
+                    };
+                    if !value.converts_to(&target) {
+                        checker.errors.push(SemanticError::ArgumentType {
+                            name: ident.clone(), position: i + 1, expected: target, found: value.ty, span: value.span,
+                        });
+                    }
+                }
+                if signature.variadic {
+                    let widest = checker.variadic_slots.entry(ident.clone()).or_insert(expected);
+                    *widest = std::cmp::max(*widest, slots);
+                }
+                ExpValue { ty: signature.ret, constant: None, span: *span }
+            },
+            UnaryExp::Unaryexp(unaryop, unaryexp) => {
+                // `-2147483648`, the literal alone doesn't fit in `int`.
+                if let (UnaryOp::Sub, UnaryExp::Primaryexp(PrimaryExp::BigNum(num, _))) = (unaryop, unaryexp.as_ref()) {
+                    if *num == 1 << 31 {
+                        return ExpValue { ty: Type::Int, constant: Some(i32::MIN), span: Span::default() };
+                    }
+                }
+                let value = unaryexp.check(checker);
+                if let (UnaryOp::Not, Type::Pointer(_, _)) = (unaryop, &value.ty) { // `!p`, is it null.
+                    return ExpValue { ty: Type::Int, constant: None, span: Span::default() };
+                }
+                let bit_not = matches!(unaryop, UnaryOp::BitNot);
+                if bit_not {
+                    checker.expect_int(&value.ty, value.span);
+                } else {
+                    checker.expect_number(&value.ty, value.span);
+                }
+                if value.ty == Type::Float && !bit_not {
+                    let constant = value.constant.map(|val| match unaryop {
+                        UnaryOp::Add => val,
+                        UnaryOp::Sub => val ^ i32::MIN, // flip the sign bit.
+                        UnaryOp::Not => (f32::from_bits(val as u32) == 0.0) as i32,
+                        UnaryOp::BitNot => unreachable!(),
+                    });
+                    let ty = if let UnaryOp::Not = unaryop { Type::Int } else { Type::Float };
+                    return ExpValue { ty, constant, span: Span::default() };
+                }
+                let constant = value.constant.map(|val| match unaryop {
+                    UnaryOp::Add => val,
+                    UnaryOp::Sub => val.wrapping_neg(),
+                    UnaryOp::Not => (val == 0) as i32,
+                    UnaryOp::BitNot => !val,
+                });
+                ExpValue { ty: Type::Int, constant, span: Span::default() }
+            },
+        }
+    }
+}
+
+// MulExp ::= UnaryExp | MulExp ("*" | "/" | "%") UnaryExp;
+impl CheckExp for MulExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            MulExp::Unaryexp(unaryexp) => unaryexp.check(checker),
+            MulExp::Mulexp(mulexp, unaryexp, op) => check_binary(mulexp.as_ref(), unaryexp, op, checker),
+            MulExp::Divexp(mulexp, unaryexp, op, span) |
+            MulExp::Modexp(mulexp, unaryexp, op, span) => {
+                let val1 = mulexp.check(checker);
+                let val2 = unaryexp.check(checker);
+                // `float` division by zero is an infinity, `int` has nothing to give. like C it isn't a constant,
+                // an error only where a constant is required. elsewhere it's a `div` at run time, which may never run.
+                let by_zero = val1.ty == Type::Int && val2.ty == Type::Int && val1.constant.is_some() && val2.constant == Some(0);
+                if by_zero && checker.constant_required {
+                    checker.errors.push(SemanticError::DivisionByZero { span: *span });
+                }
+                let value = binary_value(val1, val2, op, checker);
+                if by_zero {
+                    return ExpValue { constant: None, ..value };
+                }
+                value
+            },
+        }
+    }
+}
+
+// AddExp ::= MulExp | AddExp ("+" | "-") MulExp;
+impl CheckExp for AddExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            AddExp::Mulexp(mulexp) => mulexp.check(checker),
+            AddExp::Addexp(addexp, mulexp, op) |
+            AddExp::Subexp(addexp, mulexp, op) => check_binary(addexp.as_ref(), mulexp, op, checker),
+        }
+    }
+}
+
+// ShiftExp ::= AddExp | ShiftExp ("<<" | ">>") AddExp;
+impl CheckExp for ShiftExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            ShiftExp::Addexp(addexp) => addexp.check(checker),
+            ShiftExp::Shlexp(shiftexp, addexp, op) |
+            ShiftExp::Sarexp(shiftexp, addexp, op) => check_binary(shiftexp.as_ref(), addexp, op, checker),
+        }
+    }
+}
+
+// RelExp ::= ShiftExp | RelExp ("<" | ">" | "<=" | ">=") ShiftExp;
+impl CheckExp for RelExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            RelExp::Shiftexp(shiftexp) => shiftexp.check(checker),
+            RelExp::Ltexp(relexp, shiftexp, op) |
+            RelExp::Gtexp(relexp, shiftexp, op) |
+            RelExp::Geexp(relexp, shiftexp, op) |
+            RelExp::Leexp(relexp, shiftexp, op) => check_binary(relexp.as_ref(), shiftexp, op, checker),
+        }
+    }
+}
+
+// EqExp ::= RelExp | EqExp ("==" | "!=") RelExp;
+impl CheckExp for EqExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            EqExp::Relexp(relexp) => relexp.check(checker),
+            EqExp::Eqexp(eqexp, relexp, op) |
+            EqExp::Neqexp(eqexp, relexp, op) => check_binary(eqexp.as_ref(), relexp, op, checker),
+        }
+    }
+}
+
+// BitAndExp ::= EqExp | BitAndExp "&" EqExp;
+impl CheckExp for BitAndExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            BitAndExp::Eqexp(eqexp) => eqexp.check(checker),
+            BitAndExp::Andexp(bitandexp, eqexp, op) => check_binary(bitandexp.as_ref(), eqexp, op, checker),
+        }
+    }
+}
+
+// BitXorExp ::= BitAndExp | BitXorExp "^" BitAndExp;
+impl CheckExp for BitXorExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            BitXorExp::Bitandexp(bitandexp) => bitandexp.check(checker),
+            BitXorExp::Xorexp(bitxorexp, bitandexp, op) => check_binary(bitxorexp.as_ref(), bitandexp, op, checker),
+        }
+    }
+}
+
+// BitOrExp ::= BitXorExp | BitOrExp "|" BitXorExp;
+impl CheckExp for BitOrExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            BitOrExp::Bitxorexp(bitxorexp) => bitxorexp.check(checker),
+            BitOrExp::Orexp(bitorexp, bitxorexp, op) => check_binary(bitorexp.as_ref(), bitxorexp, op, checker),
+        }
+    }
+}
+
+// LAndExp ::= BitOrExp | LAndExp "&&" BitOrExp;
+impl CheckExp for LAndExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            LAndExp::Bitorexp(bitorexp) => bitorexp.check(checker),
+            LAndExp::Andexp(landexp, bitorexp) => check_binary(landexp.as_ref(), bitorexp, "land", checker),
+        }
+    }
+}
+
+// LOrExp ::= LAndExp | LOrExp "||" LAndExp;
+impl CheckExp for LOrExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            LOrExp::Landexp(landexp) => landexp.check(checker),
+            LOrExp::Orexp(lorexp, landexp) => check_binary(lorexp.as_ref(), landexp, "lor", checker),
+        }
+    }
+}
+
+// CondExp ::= LOrExp | LOrExp "?" Exp ":" CondExp;
+// the arms convert to `float` if one of them is, a constant condition picks the constant of its arm.
+// a pointer arm needs a pointer of the same type or the null pointer on the other side, the result is never a constant.
+impl CheckExp for CondExp {
+    fn check(&self, checker: &mut Checker) -> ExpValue {
+        match self {
+            CondExp::Lorexp(lorexp) => lorexp.check(checker),
+            CondExp::Condexp(lorexp, exp, condexp, span) => {
+                let cond = lorexp.check(checker);
+                checker.expect_condition(&cond.ty, cond.span);
+                let val1 = exp.check(checker);
+                let val2 = ExpValue { span: *span, ..condexp.check(checker) };
+                if let Some(ty) = [&val1, &val2].iter().find(|val| matches!(val.ty, Type::Pointer(_, _))).map(|val| val.ty.clone()) {
+                    for val in [&val1, &val2] {
+                        checker.expect_convertible(val, &ty);
+                    }
+                    return ExpValue { ty, constant: None, span: Span::default() };
+                }
+                for val in [&val1, &val2] {
+                    checker.expect_number(&val.ty, val.span);
+                }
+
+                let ty = if val1.ty == Type::Float || val2.ty == Type::Float { Type::Float } else { Type::Int };
+                let constant = cond.constant.filter(|_| cond.ty.is_number()).and_then(|c| {
+                    let is_true = if cond.ty == Type::Float { f32::from_bits(c as u32) != 0.0 } else { c != 0 };
+                    let arm = if is_true { &val1 } else { &val2 };
+                    arm.constant.filter(|_| arm.ty.is_number()).map(|x| convert_constant(x, &arm.ty, &ty))
+                });
+                ExpValue { ty, constant, span: Span::default() }
+            },
+        }
+    }
+}
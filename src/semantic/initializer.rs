@@ -0,0 +1,215 @@
+use crate::ast::*;
+use crate::diagnostic::Span;
+use super::SemanticError;
+
+/*
+ * 初始化列表的展开, `const` 和普通变量共用这一份, semantic 检查和 generator 也共用.
+ * 规则和 C 的 brace elision 一样:
+ *     1. 表达式按顺序填进下一个 int.
+ *     2. 遇到 `{`, 它初始化从当前位置开始的最大的子数组, 当前位置必须正好是这个子数组的开头.
+ *        比如 int a[2][3][4], 位置 0 和 12 的 `{` 是 int[3][4], 位置 4 的 `{` 是 int[4].
+ *        位置 5 的 `{` 对着的是一个 int, 报错. 最里面一维的列表里再出现 `{` 就是嵌套得太深了.
+ *     3. 子列表里没写的部分都是 0, 写多了报错.
+ * 结果是铺平之后的数组, 每个 int 一个 slot, 没有初始化的是 None. 表达式在里面的顺序就是源代码里的顺序.
+ */
+
+pub enum InitView<'a, T: Initializer> {
+    Exp(&'a T::Exp, Span),
+    List(&'a [T], Span),
+}
+
+impl<'a, T: Initializer> InitView<'a, T> {
+    fn span(&self) -> Span {
+        match self {
+            InitView::Exp(_, span) | InitView::List(_, span) => *span,
+        }
+    }
+}
+
+// ConstInitVal and InitVal have the same shape, only the expression differs.
+pub trait Initializer: Sized {
+    type Exp;
+    fn view(&self) -> InitView<'_, Self>;
+}
+
+impl Initializer for ConstInitVal {
+    type Exp = ConstExp;
+    fn view(&self) -> InitView<'_, Self> {
+        match self {
+            ConstInitVal::SingleExp(exp) => InitView::Exp(exp, exp.exp.span),
+            ConstInitVal::ZeroInit(span) => InitView::List(&[], *span),
+            ConstInitVal::MultiExp(vals, span) => InitView::List(vals, *span),
+            // already reported, checked as `{}` so that nothing else is.
+            ConstInitVal::Error(span) => InitView::List(&[], *span),
+        }
+    }
+}
+
+impl Initializer for InitVal {
+    type Exp = Exp;
+    fn view(&self) -> InitView<'_, Self> {
+        match self {
+            InitVal::SingleExp(exp) => InitView::Exp(exp, exp.span),
+            InitVal::ZeroInit(span) => InitView::List(&[], *span),
+            InitVal::MultiExp(vals, span) => InitView::List(vals, *span),
+            InitVal::Error(span) => InitView::List(&[], *span),
+        }
+    }
+}
+
+// the number of `int` in an array, 1 for a scalar.
+fn size(dims: &[i32]) -> usize {
+    dims.iter().map(|x| *x as usize).product()
+}
+
+// the variable `name` with `dims` is initialized by `init`, report what doesn't fit to `errors`.
+pub fn flatten<'a, T: Initializer>(init: &'a T, dims: &[i32], name: &str, errors: &mut Vec<SemanticError>) -> Vec<Option<&'a T::Exp>> {
+    let mut slots = vec![None; size(dims)];
+    let mut layout = Layout { name, dims: dims.len(), errors };
+    match init.view() {
+        InitView::Exp(exp, span) => {
+            if dims.is_empty() {
+                slots[0] = Some(exp);
+            } else {
+                layout.errors.push(SemanticError::ArrayNeedsList { name: name.to_string(), span });
+            }
+        },
+        // a scalar may have one level of braces, `int x = {1};`.
+        InitView::List(items, _) => layout.fill(items, dims, &mut slots),
+    }
+    slots
+}
+
+struct Layout<'e> {
+    name: &'e str,
+    dims: usize, // of the whole variable, for the message.
+    errors: &'e mut Vec<SemanticError>,
+}
+
+impl<'e> Layout<'e> {
+    // a braced list for an object of `dims`, whose `int`s are `slots`.
+    fn fill<'a, T: Initializer>(&mut self, items: &'a [T], dims: &[i32], slots: &mut [Option<&'a T::Exp>]) {
+        let mut pos = 0;
+        for item in items {
+            let view = item.view();
+            if pos >= slots.len() {
+                self.errors.push(SemanticError::ExcessElements { name: self.name.to_string(), span: view.span() });
+                return;
+            }
+            match view {
+                InitView::Exp(exp, _) => {
+                    slots[pos] = Some(exp);
+                    pos += 1;
+                },
+                InitView::List(sub, span) => {
+                    // the largest sub-array that starts right here.
+                    match (1..dims.len()).find(|k| pos % size(&dims[*k..]) == 0) {
+                        Some(k) => {
+                            let len = size(&dims[k..]);
+                            self.fill(sub, &dims[k..], &mut slots[pos..pos + len]);
+                            pos += len;
+                        },
+                        None => { // the element here is an `int`.
+                            let name = self.name.to_string();
+                            if dims.len() <= 1 { // there's no smaller sub-array at all.
+                                self.errors.push(SemanticError::InitializerTooDeep { name, dims: self.dims, span });
+                            } else {
+                                self.errors.push(SemanticError::BracedScalar { name, span });
+                            }
+                            pos += 1;
+                        },
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an initializer whose expressions are just numbers, the span of one is where it starts.
+    enum Init {
+        Exp(i32, usize),
+        List(Vec<Init>, usize),
+    }
+
+    impl Initializer for Init {
+        type Exp = i32;
+        fn view(&self) -> InitView<'_, Self> {
+            match self {
+                Init::Exp(value, lo) => InitView::Exp(value, Span::new(*lo, *lo + 1)),
+                Init::List(items, lo) => InitView::List(items, Span::new(*lo, *lo + 1)),
+            }
+        }
+    }
+
+    fn exp(value: i32) -> Init {
+        Init::Exp(value, 0)
+    }
+
+    fn list(items: Vec<Init>) -> Init {
+        Init::List(items, 0)
+    }
+
+    fn values(init: &Init, dims: &[i32]) -> (Vec<Option<i32>>, Vec<SemanticError>) {
+        let mut errors = Vec::new();
+        let slots = flatten(init, dims, "a", &mut errors);
+        (slots.into_iter().map(|x| x.copied()).collect(), errors)
+    }
+
+    #[test]
+    fn scalar() {
+        assert_eq!(values(&exp(1), &[]).0, [Some(1)]);
+        assert_eq!(values(&list(vec![exp(1)]), &[]).0, [Some(1)]);
+        assert_eq!(values(&list(vec![]), &[]).0, [None]);
+    }
+
+    #[test]
+    fn brace_elision() {
+        // int a[2][3] = {1, 2, 3, 4};
+        let (slots, errors) = values(&list(vec![exp(1), exp(2), exp(3), exp(4)]), &[2, 3]);
+        assert_eq!(slots, [Some(1), Some(2), Some(3), Some(4), None, None]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn sub_list_fills_the_largest_aligned_sub_array() {
+        // int a[2][3][4] = {1, 2, 3, 4, {5}, {6}, 7};
+        let init = list(vec![exp(1), exp(2), exp(3), exp(4), list(vec![exp(5)]), list(vec![exp(6)]), exp(7)]);
+        let (slots, errors) = values(&init, &[2, 3, 4]);
+        assert!(errors.is_empty());
+        assert_eq!(slots[..4], [Some(1), Some(2), Some(3), Some(4)]);
+        assert_eq!(slots[4..8], [Some(5), None, None, None]);
+        assert_eq!(slots[8..12], [Some(6), None, None, None]);
+        assert_eq!(slots[12], Some(7));
+        assert!(slots[13..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn array_needs_a_list() {
+        let (_, errors) = values(&exp(1), &[2]);
+        assert!(matches!(errors[..], [SemanticError::ArrayNeedsList { .. }]));
+    }
+
+    #[test]
+    fn excess_elements() {
+        let init = list(vec![exp(1), exp(2), Init::Exp(3, 7)]);
+        let (slots, errors) = values(&init, &[2]);
+        assert_eq!(slots, [Some(1), Some(2)]);
+        assert!(matches!(errors[..], [SemanticError::ExcessElements { span, .. }] if span.lo == 7));
+    }
+
+    #[test]
+    fn misaligned_and_too_deep_lists() {
+        // int a[2][2] = {1, {2}}; the `{` is at an `int`.
+        let init = list(vec![exp(1), Init::List(vec![exp(2)], 5)]);
+        let (_, errors) = values(&init, &[2, 2]);
+        assert!(matches!(errors[..], [SemanticError::BracedScalar { span, .. }] if span.lo == 5));
+
+        // int a[2] = {{1}};
+        let (_, errors) = values(&list(vec![list(vec![exp(1)])]), &[2]);
+        assert!(matches!(errors[..], [SemanticError::InitializerTooDeep { dims: 1, .. }]));
+    }
+}
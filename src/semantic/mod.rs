@@ -0,0 +1,1074 @@
+mod calls;
+mod constant;
+mod error;
+mod expression;
+mod initializer;
+mod structs;
+mod types;
+
+pub use calls::CallGraph;
+pub use error::SemanticError;
+pub use constant::{convert_constant, fold, fold_float};
+pub use initializer::{flatten, Initializer};
+pub use structs::{Element, Field, StructLayout};
+pub use types::{variadic_slot, FunctionSignature, Type};
+
+use crate::ast::*;
+use crate::diagnostic::Span;
+use expression::{int_only, CheckExp, ExpValue};
+use initializer::InitView;
+use std::collections::{HashMap, HashSet};
+
+/*
+ * 在生成 koopa 之前把整棵树检查一遍, 用户程序里的错误都在这里变成 SemanticError,
+ * 这样 generator 里的 unwrap/assert 只会因为编译器自己的 bug 触发.
+ *
+ * 作用域是一个 HashMap 的栈, 进入 block push, 离开 pop. 函数单独一张表, 存的是函数签名.
+ * 表达式的 check 返回它的类型, 顺便做常量求值, 规则和 generator 里 is_constant 一致.
+ * `float` 常量和 generator 一样存成 i32 的 bit pattern, 要看类型才知道怎么解释.
+ * 指针和 struct 是语言扩展, 没有打开的时候照样检查, 只是在用到的地方报一个错.
+ * struct 的名字和变量不在一个命名空间, 和 C 一样. struct 只能定义在最外层, 所以只有一张表.
+ */
+
+// the language extensions turned on by the command line, all off by default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Extensions {
+    pub pointers: bool, // `-fpointers`
+    pub structs: bool, // `-fstructs`
+}
+
+// `dims` are all the dimensions, the first one of an array parameter is unknown and kept as 0.
+// a pointer is indexed like an array parameter, its `dims` start with 0 too. unlike an array it can be assigned.
+enum Symbol {
+    Constant(BType, i32), // the value is known when compiling.
+    ConstArray(BType, Vec<i32>, Vec<i32>), // the values are flattened, empty if a dimension is bad.
+    Variable(BType),
+    Array(BType, Vec<i32>),
+    Pointer(BType, Vec<i32>),
+    Struct(String, Vec<i32>), // a struct variable or an array of structs, by the name of the struct.
+}
+
+impl Symbol {
+    fn dims(&self) -> &[i32] {
+        match self {
+            Symbol::Constant(_, _) | Symbol::Variable(_) => &[],
+            Symbol::ConstArray(_, dims, _) | Symbol::Array(_, dims) | Symbol::Pointer(_, dims) => dims,
+            Symbol::Struct(_, dims) => dims,
+        }
+    }
+
+    // the type of the elements, the same as the type of a scalar. a struct has none, it's checked before.
+    fn btype(&self) -> BType {
+        match self {
+            Symbol::Constant(btype, _) | Symbol::Variable(btype) => *btype,
+            Symbol::ConstArray(btype, _, _) | Symbol::Array(btype, _) | Symbol::Pointer(btype, _) => *btype,
+            Symbol::Struct(_, _) => unreachable!("The element type of a struct."),
+        }
+    }
+}
+
+// the prefix of the globals the generator adds, see `koopa_ir_gen/mod.rs`.
+const RESERVED_PREFIX: &str = "__sysy_";
+
+// `starttime()`/`stoptime()` are macros of `sylib.h` calling these entry points with `__LINE__`.
+// programs call them without the include, so a call to an undeclared timer is checked and generated the same way, see `Checker::timer`.
+pub const TIMERS: [(&str, &str); 2] = [("starttime", "_sysy_starttime"), ("stoptime", "_sysy_stoptime")];
+
+// the sysy runtime library, declared before the program. `-runtime` can add more prototypes to it.
+pub fn runtime_library() -> Vec<(String, FunctionSignature)> {
+    let array = || Type::Pointer(BType::Int, vec![]);
+    let float_array = || Type::Pointer(BType::Float, vec![]);
+    let library = vec![
+        ("getint", FunctionSignature::new(vec![], Type::Int)),
+        ("getch", FunctionSignature::new(vec![], Type::Int)),
+        ("getfloat", FunctionSignature::new(vec![], Type::Float)),
+        ("getarray", FunctionSignature::new(vec![array()], Type::Int)),
+        ("getfarray", FunctionSignature::new(vec![float_array()], Type::Int)),
+        ("putint", FunctionSignature::new(vec![Type::Int], Type::Void)),
+        ("putch", FunctionSignature::new(vec![Type::Int], Type::Void)),
+        ("putfloat", FunctionSignature::new(vec![Type::Float], Type::Void)),
+        ("putarray", FunctionSignature::new(vec![Type::Int, array()], Type::Void)),
+        ("putfarray", FunctionSignature::new(vec![Type::Int, float_array()], Type::Void)),
+        ("_sysy_starttime", FunctionSignature::new(vec![Type::Int], Type::Void)),
+        ("_sysy_stoptime", FunctionSignature::new(vec![Type::Int], Type::Void)),
+        ("putf", FunctionSignature::variadic(vec![Type::Str], Type::Void)),
+    ];
+    library.into_iter().map(|(name, signature)| (name.to_string(), signature)).collect()
+}
+
+pub struct Checker {
+    scopes: Vec<HashMap<String, Symbol>>,
+    functions: HashMap<String, FunctionSignature>, // declared so far, by a prototype or a definition.
+    defined: HashSet<String>, // the functions with a body, the library counts as one.
+    ahead: HashMap<String, FunctionSignature>, // every function of the program, a call may come before it.
+    current_function: (String, Type), // name and return type, for `return`.
+    loop_depth: i32,
+    switch_depth: i32, // `break` is fine in a `switch` too, `continue` isn't.
+    constant_required: bool, // checking a dimension, a `const` or global initializer or a case label.
+    variadic_slots: HashMap<String, usize>, // `i32` slots of the widest call to each variadic function.
+    calls: CallGraph,
+    timers: Vec<Span>, // the calls to an undeclared `starttime()`/`stoptime()`.
+    structs: HashMap<String, StructLayout>,
+    extensions: Extensions,
+    errors: Vec<SemanticError>,
+}
+
+// check the whole program, return all the errors found.
+// koopa has no variadic function, the generator declares one with as many `i32`s as its widest call needs.
+// the generator orders the functions by who calls whom too, and passes the line to the timers.
+pub fn check(ast: &CompUnit, runtime: &[(String, FunctionSignature)], extensions: Extensions) -> (Vec<SemanticError>, Checked) {
+    let mut checker = Checker::new(runtime, extensions);
+    checker.ahead = collect_functions(ast, runtime, extensions);
+    ast.check(&mut checker);
+    (checker.errors, Checked { variadic_slots: checker.variadic_slots, calls: checker.calls, timers: checker.timers })
+}
+
+// what the generator needs from the check besides the AST.
+pub struct Checked {
+    pub variadic_slots: HashMap<String, usize>,
+    pub calls: CallGraph,
+    pub timers: Vec<Span>,
+}
+
+// the functions of the program, in the order of their first declaration.
+pub fn function_names(ast: &CompUnit) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for pair in &ast.funcs {
+        if let DeclFuncPair::Func(func) = pair {
+            if !names.contains(&func.ident) {
+                names.push(func.ident.clone());
+            }
+        }
+    }
+    names
+}
+
+// the prototypes given by `-runtime`, in order, without the ones already in `runtime`.
+// they are checked like a program, constants may come first for the dimensions of array parameters.
+pub fn runtime_prototypes(ast: &CompUnit, runtime: &[(String, FunctionSignature)], extensions: Extensions) -> (Vec<SemanticError>, Vec<(String, FunctionSignature)>) {
+    let mut checker = Checker::new(runtime, extensions);
+    checker.ahead = collect_functions(ast, runtime, extensions);
+    ast.check(&mut checker);
+    let mut prototypes: Vec<(String, FunctionSignature)> = Vec::new();
+    for pair in &ast.funcs {
+        if let DeclFuncPair::Func(func) = pair {
+            if func.block.is_some() {
+                checker.errors.push(SemanticError::RuntimeDefinition { name: func.ident.clone(), span: func.span });
+            } else if !runtime.iter().chain(prototypes.iter()).any(|(name, _)| name == &func.ident) {
+                prototypes.push((func.ident.clone(), checker.functions[&func.ident].clone()));
+            }
+        }
+    }
+    (checker.errors, prototypes)
+}
+
+// the first signature of every function, for the calls before its declaration.
+// the dimensions of array parameters need the constants before the function, so the declarations are checked
+// in order too. the errors are reported by the real pass.
+fn collect_functions(ast: &CompUnit, runtime: &[(String, FunctionSignature)], extensions: Extensions) -> HashMap<String, FunctionSignature> {
+    let mut checker = Checker::new(runtime, extensions);
+    for pair in &ast.funcs {
+        match pair {
+            DeclFuncPair::Decl(decl) => decl.check(&mut checker),
+            DeclFuncPair::Struct(def) => def.check(&mut checker),
+            DeclFuncPair::Func(func) => {
+                checker.push_scope();
+                let signature = func.declare_params(&mut checker);
+                checker.pop_scope();
+                checker.functions.entry(func.ident.clone()).or_insert(signature);
+            },
+            DeclFuncPair::Error() => {},
+        }
+    }
+    checker.functions
+}
+
+impl Checker {
+    fn new(runtime: &[(String, FunctionSignature)], extensions: Extensions) -> Checker {
+        let mut checker = Checker {
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            defined: HashSet::new(),
+            ahead: HashMap::new(),
+            current_function: ("".to_string(), Type::Void),
+            loop_depth: 0,
+            switch_depth: 0,
+            constant_required: false,
+            variadic_slots: HashMap::new(),
+            calls: CallGraph::default(),
+            timers: Vec::new(),
+            structs: HashMap::new(),
+            extensions,
+            errors: Vec::new(),
+        };
+        for (name, signature) in runtime {
+            checker.defined.insert(name.clone());
+            checker.functions.insert(name.clone(), signature.clone());
+        }
+        checker
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn is_global(&self) -> bool {
+        self.scopes.len() == 1
+    }
+
+    // the name may only be defined once in the same scope, global names also clash with functions.
+    fn declare(&mut self, name: &str, symbol: Symbol, span: Span) {
+        if self.is_global() {
+            self.expect_unreserved(name, span);
+        }
+        let clash_function = self.is_global() && self.functions.contains_key(name);
+        let scope = self.scopes.last_mut().unwrap();
+        if scope.contains_key(name) || clash_function {
+            self.errors.push(SemanticError::Redeclared { name: name.to_string(), span });
+        }
+        scope.insert(name.to_string(), symbol);
+    }
+
+    // a function may be declared many times with the same signature, but defined once.
+    // the first signature is kept.
+    fn declare_function(&mut self, name: &str, signature: FunctionSignature, is_definition: bool, span: Span) {
+        self.expect_unreserved(name, span);
+        match self.functions.get(name) {
+            None if self.scopes[0].contains_key(name) => {
+                self.errors.push(SemanticError::Redeclared { name: name.to_string(), span });
+            },
+            None => {
+                self.functions.insert(name.to_string(), signature);
+            },
+            Some(previous) if *previous != signature => {
+                self.errors.push(SemanticError::ConflictingSignature {
+                    name: name.to_string(), previous: previous.clone(), found: signature, span,
+                });
+            },
+            Some(_) if is_definition && self.defined.contains(name) => {
+                self.errors.push(SemanticError::Redeclared { name: name.to_string(), span });
+            },
+            Some(_) => {},
+        }
+        if is_definition {
+            self.defined.insert(name.to_string());
+        }
+    }
+
+    // the intrinsics and the string literals are globals named `@__sysy_...`, the program can't have one.
+    fn expect_unreserved(&mut self, name: &str, span: Span) {
+        if name.starts_with(RESERVED_PREFIX) {
+            self.errors.push(SemanticError::ReservedName { name: name.to_string(), span });
+        }
+    }
+
+    // the signature of a function called by name.
+    fn lookup_function(&self, name: &str) -> Option<&FunctionSignature> {
+        self.functions.get(name).or_else(|| self.ahead.get(name))
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    // the entry point `starttime`/`stoptime` calls, None once the program declares the name itself.
+    fn timer(&self, name: &str) -> Option<&'static str> {
+        let (_, entry) = TIMERS.iter().find(|(timer, _)| *timer == name)?;
+        (self.lookup_function(name).is_none() && self.lookup(name).is_none()).then_some(*entry)
+    }
+
+    // the syntax of the pointer extension, reported once for every use when it's off.
+    fn expect_pointers(&mut self, span: Span) {
+        if !self.extensions.pointers {
+            self.errors.push(SemanticError::Extension {
+                feature: "pointers".to_string(), flag: "-fpointers".to_string(), span,
+            });
+        }
+    }
+
+    fn expect_structs(&mut self, span: Span) {
+        if !self.extensions.structs {
+            self.errors.push(SemanticError::Extension {
+                feature: "structs".to_string(), flag: "-fstructs".to_string(), span,
+            });
+        }
+    }
+
+    // the `i32`s an element of a field takes, an undeclared struct is already reported.
+    fn element_size(&self, element: &Element) -> i32 {
+        match element {
+            Element::Basic(_) => 1,
+            Element::Struct(name) => self.structs.get(name).map_or(0, |layout| layout.size),
+        }
+    }
+
+    // subscripts and dimensions need an `int`.
+    fn expect_int(&mut self, ty: &Type, span: Span) {
+        if *ty != Type::Int {
+            self.errors.push(SemanticError::MismatchedTypes { expected: Type::Int, found: ty.clone(), span });
+        }
+    }
+
+    // operands and conditions may be `int` or `float`.
+    fn expect_number(&mut self, ty: &Type, span: Span) {
+        if !ty.is_number() {
+            self.errors.push(SemanticError::MismatchedTypes { expected: Type::Int, found: ty.clone(), span });
+        }
+    }
+
+    // conditions may be pointers as well, a null pointer is false.
+    fn expect_condition(&mut self, ty: &Type, span: Span) {
+        if !matches!(ty, Type::Pointer(_, _)) {
+            self.expect_number(ty, span);
+        }
+    }
+
+    // a value stored to a `target`, by assignment, initialization, argument passing or `return`.
+    fn expect_convertible(&mut self, value: &ExpValue, target: &Type) {
+        if !value.converts_to(target) {
+            self.errors.push(SemanticError::MismatchedTypes { expected: target.clone(), found: value.ty.clone(), span: value.span });
+        }
+    }
+
+    // check an expression that has to be a constant, `true` if it has an error already.
+    // then it isn't reported again for not being a constant.
+    fn check_reported<E: CheckExp>(&mut self, exp: &E) -> (ExpValue, bool) {
+        let before = self.errors.len();
+        let value = exp.check(self);
+        (value, self.errors.len() > before)
+    }
+
+    // `check` where C requires a constant, see `constant_required`.
+    fn in_constant<R>(&mut self, check: impl FnOnce(&mut Checker) -> R) -> R {
+        let outer = std::mem::replace(&mut self.constant_required, true);
+        let result = check(self);
+        self.constant_required = outer;
+        result
+    }
+
+    // every dimension must be a positive constant, the bad ones are counted as 0.
+    fn check_dimensions(&mut self, dims: &Vec<ConstExp>) -> Vec<i32> {
+        let mut values = Vec::new();
+        for dim in dims {
+            let (value, reported) = self.in_constant(|checker| checker.check_reported(&dim.exp));
+            if value.ty != Type::Int {
+                self.expect_int(&value.ty, value.span);
+                values.push(0);
+                continue;
+            }
+            match value.constant {
+                None if reported => values.push(0),
+                None => {
+                    self.errors.push(SemanticError::NonConstantDimension { span: dim.exp.span });
+                    values.push(0);
+                },
+                Some(x) if x <= 0 => {
+                    self.errors.push(SemanticError::NonPositiveDimension { value: x, span: dim.exp.span });
+                    values.push(0);
+                },
+                Some(x) => values.push(x),
+            }
+        }
+        values
+    }
+}
+
+
+trait Check {
+    fn check(&self, checker: &mut Checker);
+}
+
+// CompUnit ::= [CompUnit] (Decl | FuncDef)
+impl Check for CompUnit {
+    fn check(&self, checker: &mut Checker) {
+        for pair in &self.funcs {
+            match pair {
+                DeclFuncPair::Decl(decl) => decl.check(checker),
+                DeclFuncPair::Func(func) => func.check(checker),
+                DeclFuncPair::Struct(def) => def.check(checker),
+                DeclFuncPair::Error() => {},
+            }
+        }
+    }
+}
+
+impl FuncDef {
+    // declare the parameters in the current scope, returns the signature.
+    fn declare_params(&self, checker: &mut Checker) -> FunctionSignature {
+        let mut params = Vec::new();
+        if let Some(v) = &self.params {
+            for param in &v.params {
+                match param {
+                    FuncFParam::Scalar(btype, ident, span) => {
+                        checker.declare(ident, Symbol::Variable(*btype), *span);
+                        params.push(Type::from(*btype));
+                    },
+                    FuncFParam::Array(btype, ident, dims, span) => {
+                        let mut dims = checker.check_dimensions(dims);
+                        params.push(Type::Pointer(*btype, dims.clone()));
+                        dims.insert(0, 0);
+                        checker.declare(ident, Symbol::Array(*btype, dims), *span);
+                    },
+                    FuncFParam::Pointer(btype, ident, span) => {
+                        checker.expect_pointers(*span);
+                        checker.declare(ident, Symbol::Pointer(*btype, vec![0]), *span);
+                        params.push(Type::Pointer(*btype, vec![]));
+                    },
+                    FuncFParam::Error() => {},
+                }
+            }
+        }
+        FunctionSignature::new(params, Type::from(self.func_type))
+    }
+}
+
+// FuncDef ::= FuncType IDENT "(" [FuncFParams] ")" (Block | ";");
+impl Check for FuncDef {
+    fn check(&self, checker: &mut Checker) {
+        // parameters live in the same scope as the outermost block of the body.
+        checker.push_scope();
+        let signature = self.declare_params(checker);
+        let ret = signature.ret.clone();
+
+        // declare before the body, so that recursion works.
+        checker.declare_function(&self.ident, signature, self.block.is_some(), self.span);
+        if let Some(block) = &self.block {
+            checker.current_function = (self.ident.clone(), ret);
+            for item in &block.items {
+                item.check(checker);
+            }
+        }
+        checker.pop_scope();
+    }
+}
+
+// Block ::= "{" {BlockItem} "}";
+impl Check for Block {
+    fn check(&self, checker: &mut Checker) {
+        checker.push_scope();
+        for item in &self.items {
+            item.check(checker);
+        }
+        checker.pop_scope();
+    }
+}
+
+// BlockItem ::= Decl | Statement;
+impl Check for BlockItem {
+    fn check(&self, checker: &mut Checker) {
+        match self {
+            BlockItem::Decl(decl) => decl.check(checker),
+            BlockItem::Statement(stmt) => stmt.check(checker),
+        }
+    }
+}
+
+// Decl ::= ConstDecl | VarDecl;
+impl Check for Decl {
+    fn check(&self, checker: &mut Checker) {
+        match self {
+            Decl::Constdecl(constdecl) => {
+                for def in &constdecl.constdefs {
+                    def.check(constdecl.btype, checker);
+                }
+            },
+            Decl::Vardecl(vardecl) => {
+                for def in &vardecl.vardefs {
+                    def.check(vardecl.btype, checker);
+                }
+            },
+            Decl::Structdecl(structdecl) => structdecl.check(checker),
+            Decl::Error() => {},
+        }
+    }
+}
+
+// StructDef ::= "struct" IDENT "{" FieldDecl {FieldDecl} "}" ";"
+// the struct is declared after its fields, it can't contain itself. the first definition is kept.
+impl Check for StructDef {
+    fn check(&self, checker: &mut Checker) {
+        checker.expect_structs(self.span);
+        let mut layout = StructLayout::default();
+        for field in &self.fields {
+            let dims = checker.check_dimensions(&field.dims);
+            let element = match &field.ty {
+                FieldType::Basic(btype) => Element::Basic(*btype),
+                FieldType::Struct(name, span) => {
+                    if !checker.structs.contains_key(name) {
+                        checker.errors.push(SemanticError::UndeclaredStruct { name: name.clone(), span: *span });
+                    }
+                    Element::Struct(name.clone())
+                },
+            };
+            if layout.field(&field.ident).is_some() {
+                checker.errors.push(SemanticError::Redeclared { name: field.ident.clone(), span: field.span });
+            }
+            let size = checker.element_size(&element);
+            layout.push(&field.ident, element, dims, size);
+        }
+        if checker.structs.contains_key(&self.ident) {
+            checker.errors.push(SemanticError::Redeclared { name: self.ident.clone(), span: self.span });
+            return;
+        }
+        checker.structs.insert(self.ident.clone(), layout);
+    }
+}
+
+// StructDecl ::= "struct" IDENT VarDef {"," VarDef} ";"
+// no initializer and no pointer, a global struct is zero initialized.
+impl Check for StructDecl {
+    fn check(&self, checker: &mut Checker) {
+        checker.expect_structs(self.span);
+        if !checker.structs.contains_key(&self.ident) {
+            checker.errors.push(SemanticError::UndeclaredStruct { name: self.ident.clone(), span: self.span });
+        }
+        for def in &self.vardefs {
+            let (ident, dims, span) = match def {
+                VarDef::Ident(ident, dims, span) => (ident, checker.check_dimensions(dims), span),
+                VarDef::Identinitval(ident, dims, _, span) => {
+                    checker.errors.push(SemanticError::StructInitializer { name: ident.clone(), span: *span });
+                    (ident, checker.check_dimensions(dims), span)
+                },
+                VarDef::Pointer(ident, _, span) => {
+                    checker.errors.push(SemanticError::StructPointer { span: *span });
+                    (ident, Vec::new(), span)
+                },
+            };
+            checker.declare(ident, Symbol::Struct(self.ident.clone(), dims), *span);
+        }
+    }
+}
+
+// every expression of an initializer, in source order, whatever the shape is.
+fn initializer_exps<'a, T: Initializer>(init: &'a T, exps: &mut Vec<&'a T::Exp>) {
+    match init.view() {
+        InitView::Exp(exp, _) => exps.push(exp),
+        InitView::List(items, _) => {
+            for item in items {
+                initializer_exps(item, exps);
+            }
+        },
+    }
+}
+
+// an element of an initializer: its constant value, where it is and whether it has reported an error.
+type CheckedElement = (Option<i32>, Span, bool);
+
+// check the layout against `dims`, and collect the value of every element, `None` for the non-constant ones.
+// the constants are converted to `btype` already. an element with an error is marked, see `check_reported`.
+// the second result is the flattened value of the whole variable, a missing or non-constant element is 0.
+fn check_initializer<T: Initializer>(init: &T, name: &str, btype: BType, dims: &[i32], checker: &mut Checker) -> (Vec<CheckedElement>, Vec<i32>)
+where
+    T::Exp: CheckExp,
+{
+    // a bad dimension is already reported, the layout can't be checked.
+    let slots = if !dims.contains(&0) { flatten(init, dims, name, &mut checker.errors) } else { Vec::new() };
+    let mut exps = Vec::new();
+    initializer_exps(init, &mut exps);
+    let mut values = Vec::new();
+    for exp in &exps {
+        let before = checker.errors.len();
+        let value = exp.check(checker);
+        let target = Type::from(btype);
+        checker.expect_convertible(&value, &target);
+        let constant = value.constant.filter(|_| value.ty.is_number()).map(|x| convert_constant(x, &value.ty, &target));
+        values.push((constant, value.span, checker.errors.len() > before));
+    }
+    let position: HashMap<*const T::Exp, usize> = exps.iter().enumerate().map(|(i, exp)| (*exp as *const T::Exp, i)).collect();
+    let flat = slots.iter().map(|slot| slot.and_then(|exp| values[position[&(exp as *const T::Exp)]].0).unwrap_or(0)).collect();
+    (values, flat)
+}
+
+// ConstDef ::= IDENT {"[" ConstExp "]"} "=" ConstInitVal
+impl ConstDef {
+    fn check(&self, btype: BType, checker: &mut Checker) {
+        let dims = checker.check_dimensions(&self.dims);
+
+        let (values, flat) = checker.in_constant(|checker| check_initializer(&self.constinitval, &self.ident, btype, &dims, checker));
+        for (value, span, reported) in &values {
+            if value.is_none() && !reported {
+                checker.errors.push(SemanticError::NonConstantInitializer { name: self.ident.clone(), span: *span });
+            }
+        }
+
+        // the name is visible after its initializer, the same as the generator.
+        if dims.is_empty() {
+            let value = values.first().and_then(|x| x.0).unwrap_or(0);
+            checker.declare(&self.ident, Symbol::Constant(btype, value), self.span);
+        } else {
+            checker.declare(&self.ident, Symbol::ConstArray(btype, dims, flat), self.span);
+        }
+    }
+}
+
+// VarDef ::= IDENT {"[" ConstExp "]"}
+//          | IDENT {"[" ConstExp "]"} "=" InitVal
+//          | "*" IDENT ["=" Exp]
+impl VarDef {
+    fn check(&self, btype: BType, checker: &mut Checker) {
+        let (ident, dims, span) = match self {
+            VarDef::Pointer(ident, exp, span) => {
+                checker.expect_pointers(*span);
+                if let Some(exp) = exp {
+                    let (value, reported) = checker.check_reported(exp);
+                    checker.expect_convertible(&value, &Type::Pointer(btype, vec![]));
+                    // no address is known when compiling, a global pointer starts as null.
+                    if checker.is_global() && !reported && !value.is_null() {
+                        checker.errors.push(SemanticError::NonConstantInitializer { name: ident.clone(), span: value.span });
+                    }
+                }
+                checker.declare(ident, Symbol::Pointer(btype, vec![0]), *span);
+                return;
+            },
+            VarDef::Ident(ident, dims, span) => (ident, checker.check_dimensions(dims), span),
+            VarDef::Identinitval(ident, dims, initval, span) => {
+                let dims = checker.check_dimensions(dims);
+                // global variables are initialized when compiling.
+                let (values, _) = if checker.is_global() {
+                    checker.in_constant(|checker| check_initializer(initval, ident, btype, &dims, checker))
+                } else {
+                    check_initializer(initval, ident, btype, &dims, checker)
+                };
+                if checker.is_global() {
+                    for (value, span, reported) in &values {
+                        if value.is_none() && !reported {
+                            checker.errors.push(SemanticError::NonConstantInitializer { name: ident.clone(), span: *span });
+                        }
+                    }
+                }
+                (ident, dims, span)
+            },
+        };
+        if dims.is_empty() {
+            checker.declare(ident, Symbol::Variable(btype), *span);
+        } else {
+            checker.declare(ident, Symbol::Array(btype, dims), *span);
+        }
+    }
+}
+
+
+// conditions of `if` and the loops, a `float` is compared with 0, a pointer with null.
+fn check_condition(exp: &Exp, checker: &mut Checker) {
+    let value = exp.check(checker);
+    checker.expect_condition(&value.ty, value.span);
+}
+
+// statement: open_statement
+//          | closed_statement
+impl Check for Statement {
+    fn check(&self, checker: &mut Checker) {
+        match self {
+            Statement::Open(os) => os.check(checker),
+            Statement::Closed(cs) => cs.check(checker),
+        }
+    }
+}
+
+impl Check for OpenStatement {
+    fn check(&self, checker: &mut Checker) {
+        match self {
+            OpenStatement::If(exp, stmt) => {
+                check_condition(exp, checker);
+                stmt.check(checker);
+            },
+            OpenStatement::Ifelse(exp, cs, os) => {
+                check_condition(exp, checker);
+                cs.check(checker);
+                os.check(checker);
+            },
+            OpenStatement::While(exp, stmt) => check_loop(Some(exp), stmt, checker),
+            OpenStatement::For(for_stmt) => for_stmt.check(checker),
+        }
+    }
+}
+
+impl Check for ClosedStatement {
+    fn check(&self, checker: &mut Checker) {
+        match self {
+            ClosedStatement::Stmt(stmt) => stmt.check(checker),
+            ClosedStatement::Ifelse(exp, cs1, cs2) => {
+                check_condition(exp, checker);
+                cs1.check(checker);
+                cs2.check(checker);
+            },
+            ClosedStatement::While(exp, stmt) => check_loop(Some(exp), stmt, checker),
+            ClosedStatement::For(for_stmt) => for_stmt.check(checker),
+        }
+    }
+}
+
+// the condition and the body of every loop, `break` and `continue` are allowed in the body.
+fn check_loop(cond: Option<&Exp>, body: &Statement, checker: &mut Checker) {
+    if let Some(exp) = cond {
+        check_condition(exp, checker);
+    }
+    checker.loop_depth += 1;
+    body.check(checker);
+    checker.loop_depth -= 1;
+}
+
+// ForHead ::= "for" "(" ForInit [Exp] ";" [SimpleStmt] ")";
+impl Check for ForStmt {
+    fn check(&self, checker: &mut Checker) {
+        // a name declared in the init lives until the end of the loop.
+        checker.push_scope();
+        match &self.init {
+            ForInit::Decl(decl) => decl.check(checker),
+            ForInit::Stmt(stmt) => stmt.check(checker),
+        }
+        check_loop(self.cond.as_ref(), &self.body, checker);
+        if let Some(step) = &self.step {
+            step.check(checker);
+        }
+        checker.pop_scope();
+    }
+}
+
+// the left side of `=`, `+=`, `++`..., a variable, an element of an array, a pointer or `*p`.
+// a bad target is reported here and checked on as an `int`.
+fn check_target(lval: &LVal, checker: &mut Checker) -> ExpValue {
+    let mut target = lval.check(checker);
+    if let LVal::Ident(ident, exps, span) = lval {
+        match checker.lookup(ident) {
+            Some(Symbol::Constant(_, _)) | Some(Symbol::ConstArray(_, _, _)) => {
+                checker.errors.push(SemanticError::AssignToConstant { name: ident.clone(), span: *span });
+                return target;
+            },
+            Some(Symbol::Pointer(_, _)) if exps.is_empty() => return target,
+            _ => {},
+        }
+    }
+    if !target.ty.is_number() {
+        checker.expect_number(&target.ty, target.span);
+        target.ty = Type::Int;
+    }
+    target
+}
+
+// the labels are `int` constants, each value and `default` at most once.
+// the body is one block, a declaration is visible in the cases after it.
+fn check_switch(exp: &Exp, items: &[SwitchItem], checker: &mut Checker) {
+    let value = exp.check(checker);
+    checker.expect_int(&value.ty, value.span);
+
+    let mut values = HashSet::new();
+    let mut has_default = false;
+    checker.switch_depth += 1;
+    checker.push_scope();
+    for item in items {
+        match item {
+            SwitchItem::Case(exp, span) => {
+                let (value, reported) = checker.in_constant(|checker| checker.check_reported(exp.as_ref()));
+                checker.expect_int(&value.ty, value.span);
+                match value.constant {
+                    None if reported => {},
+                    None => checker.errors.push(SemanticError::NonConstantCase { span: value.span }),
+                    Some(x) => {
+                        if value.ty == Type::Int && !values.insert(x) {
+                            checker.errors.push(SemanticError::DuplicateCase { value: x, span: *span });
+                        }
+                    },
+                }
+            },
+            SwitchItem::Default(span) => {
+                if has_default {
+                    checker.errors.push(SemanticError::DuplicateDefault { span: *span });
+                }
+                has_default = true;
+            },
+            SwitchItem::Item(item) => item.check(checker),
+        }
+    }
+    checker.pop_scope();
+    checker.switch_depth -= 1;
+}
+
+impl Check for Stmt {
+    fn check(&self, checker: &mut Checker) {
+        match self {
+            Stmt::LvalExp(lval, exp) => {
+                let target = check_target(lval, checker);
+                let value = exp.check(checker);
+                checker.expect_convertible(&value, &target.ty);
+            },
+            Stmt::CompoundAssign(lval, op, exp) => {
+                let target = check_target(lval, checker);
+                let value = exp.check(checker);
+                if let Type::Pointer(_, _) = target.ty { // `p += i` and `p -= i` only.
+                    if op != "add" && op != "sub" {
+                        checker.errors.push(SemanticError::MismatchedTypes { expected: Type::Int, found: target.ty.clone(), span: target.span });
+                    }
+                    checker.expect_int(&value.ty, value.span);
+                } else if int_only(op) {
+                    checker.expect_int(&target.ty, target.span);
+                    checker.expect_int(&value.ty, value.span);
+                } else { // the result converts back to the target, as `=` does.
+                    checker.expect_number(&value.ty, value.span);
+                }
+            },
+            Stmt::Increment(lval, _) => {
+                check_target(lval, checker);
+            },
+            Stmt::RetExp(exp, span) => {
+                let value = exp.check(checker);
+                let (name, ret) = checker.current_function.clone();
+                if ret == Type::Void {
+                    checker.errors.push(SemanticError::ReturnValueInVoid { name, span: *span });
+                } else {
+                    checker.expect_convertible(&value, &ret);
+                }
+            },
+            Stmt::RetNone(span) => {
+                let (name, ret) = checker.current_function.clone();
+                if ret != Type::Void {
+                    checker.errors.push(SemanticError::MissingReturnValue { name, span: *span });
+                }
+            },
+            Stmt::SingleExp(exp) => {
+                exp.check(checker); // the result is discarded, `void` is fine.
+            },
+            Stmt::Block(block) => block.check(checker),
+            Stmt::BreakKeyWord(span) => {
+                if checker.loop_depth == 0 && checker.switch_depth == 0 {
+                    checker.errors.push(SemanticError::BreakOutsideLoop { span: *span });
+                }
+            },
+            Stmt::ContinueKeyWord(span) => {
+                if checker.loop_depth == 0 {
+                    checker.errors.push(SemanticError::ContinueOutsideLoop { span: *span });
+                }
+            },
+            Stmt::DoWhile(stmt, exp) => {
+                check_loop(None, stmt, checker);
+                check_condition(exp, checker);
+            },
+            Stmt::Switch(exp, items) => check_switch(exp, items, checker),
+            Stmt::ZeroExp() | Stmt::Error() => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Extensions;
+    use crate::testing::{errors, koopa, run};
+
+    #[test]
+    fn undeclared_names() {
+        let rendered = errors("int main() {\n  int x = y;\n  return h(x);\n}\n", Extensions::default());
+        assert_eq!(rendered, "\
+error: use of undeclared identifier `y`
+ --> t.c:2:11
+  |
+2 |   int x = y;
+  |           ^
+error: call to undeclared function `h`
+ --> t.c:3:10
+  |
+3 |   return h(x);
+  |          ^^^^
+");
+    }
+
+    #[test]
+    fn redefinitions() {
+        // a parameter and the outermost block of the body share a scope, an inner block may shadow.
+        let rendered = errors("int g;\nint g;\nint f(int a) {\n  int a = 1;\n  { int a = 2; }\n  return a;\n}\n\
+            int main() {\n  int f = 2;\n  return f;\n}\n", Extensions::default());
+        assert_eq!(rendered, "\
+error: redefinition of `g`
+ --> t.c:2:5
+  |
+2 | int g;
+  |     ^ already defined in this scope
+error: redefinition of `a`
+ --> t.c:4:7
+  |
+4 |   int a = 1;
+  |       ^ already defined in this scope
+");
+    }
+
+    #[test]
+    fn assignment_to_constants() {
+        let rendered = errors("const int c = 1;\nconst int k[2] = {1, 2};\nint main() {\n  c = 3;\n  k[0] = 3;\n  return 0;\n}\n",
+            Extensions::default());
+        assert!(rendered.contains("error: cannot assign to constant `c`\n --> t.c:4:3\n  |\n4 |   c = 3;\n  |   ^\n"));
+        assert!(rendered.contains("error: cannot assign to constant `k`\n --> t.c:5:3\n  |\n5 |   k[0] = 3;\n  |   ^^^^\n"));
+    }
+
+    #[test]
+    fn break_and_continue_outside_loops() {
+        let rendered = errors("int main() {\n  break;\n  switch (1) { case 1: continue; }\n\
+            while (1) { switch (2) { case 2: continue; } break; }\n  switch (3) { case 3: break; }\n  continue;\n  return 0;\n}\n",
+            Extensions::default());
+        assert_eq!(rendered.matches("error: ").count(), 3);
+        assert!(rendered.contains("error: `break` statement not within a loop or switch\n --> t.c:2:3\n"));
+        assert!(rendered.contains("error: `continue` statement not within a loop\n --> t.c:3:24\n"));
+        assert!(rendered.contains("error: `continue` statement not within a loop\n --> t.c:6:3\n  |\n6 |   continue;\n  |   ^^^^^^^^^\n"));
+    }
+
+    #[test]
+    fn argument_counts() {
+        let rendered = errors("int f(int a) { return a; }\nint main() {\n  return f() + f(1, 2) + f(3);\n}\n", Extensions::default());
+        assert_eq!(rendered.matches("error: ").count(), 2);
+        assert!(rendered.contains("error: function `f` takes 1 argument, but 0 were given\n --> t.c:3:10\n  |\n3 |   return f() + f(1, 2) + f(3);\n  |          ^^^\n"));
+        assert!(rendered.contains("error: function `f` takes 1 argument, but 2 were given\n --> t.c:3:16\n"));
+    }
+
+    #[test]
+    fn array_arguments_keep_their_inner_dimensions() {
+        let rendered = errors("int f(int a[][10]) {\n  return a[0][0];\n}\nint main() {\n  int b[2][5], c[3][10];\n  return f(b) + f(c);\n}\n",
+            Extensions::default());
+        assert_eq!(rendered, "\
+error: mismatched types in argument 1 of `f`
+ --> t.c:6:12
+  |
+6 |   return f(b) + f(c);
+  |            ^ expected `int (*)[10]`, found `int (*)[5]`
+");
+    }
+
+    #[test]
+    fn void_results_are_not_values() {
+        let rendered = errors("void v() {}\nint main() {\n  v();\n  int x = v();\n  return x + v();\n}\n", Extensions::default());
+        assert_eq!(rendered.matches("error: mismatched types\n").count(), 2);
+        assert!(rendered.contains(" --> t.c:4:11\n  |\n4 |   int x = v();\n  |           ^^^ expected `int`, found `void`\n"));
+        assert!(rendered.contains(" --> t.c:5:14\n"));
+    }
+
+    #[test]
+    fn returns_agree_with_the_return_type() {
+        let rendered = errors("int r() {\n  return;\n}\nvoid w() {\n  return 1;\n}\nint main() {\n  w();\n  return r();\n}\n",
+            Extensions::default());
+        assert_eq!(rendered, "\
+error: non-void function `r` should return a value
+ --> t.c:2:3
+  |
+2 |   return;
+  |   ^^^^^^^
+error: void function `w` should not return a value
+ --> t.c:5:3
+  |
+5 |   return 1;
+  |   ^^^^^^^^^
+");
+    }
+
+    #[test]
+    fn generated_names_are_reserved() {
+        let rendered = errors("int __sysy_fadd(int a, int b) { return a; }\nint __sysy_str_0;\n\
+            int main() { int __sysy_x = 1; return __sysy_fadd(__sysy_x, 2); }\n", Extensions::default());
+        assert!(rendered.contains("error: `__sysy_fadd` is a reserved name\n --> t.c:1:5\n"));
+        assert!(rendered.contains("error: `__sysy_str_0` is a reserved name\n"));
+        assert_eq!(rendered.matches("is a reserved name").count(), 2); // a local is never a koopa global.
+    }
+
+    #[test]
+    fn division_by_zero_where_a_constant_is_required() {
+        let rendered = errors("const int c = 1 / 0;\nint g = 2 % 0;\nint a[3 / 0];\n\
+            int main() { const int l = 4 / (2 - 2); switch (getint()) { case 5 / 0: return 1; } return 0; }\n", Extensions::default());
+        assert_eq!(rendered.matches("error: division by zero in constant expression\n").count(), 5);
+        assert!(!rendered.contains("not a constant expression")); // not reported twice.
+    }
+
+    #[test]
+    fn division_by_zero_at_run_time() {
+        let text = koopa("int main() {\n  if (0) return 1 / 0;\n  int x = 7 % 0;\n  return 7 / 0 * 0 + x;\n}\n", Extensions::default());
+        assert!(text.contains(" = div 1, 0\n") && text.contains(" = mod 7, 0\n") && text.contains(" = div 7, 0\n"));
+    }
+
+    #[test]
+    fn prototypes_agree_with_the_definition() {
+        let rendered = errors("int f(int a[], int n);\nint f(int a[], int n);\nint f(int a[][2], int n) {\n  return n;\n}\n\
+            int main() {\n  return f(0);\n}\n", Extensions::default());
+        assert!(!rendered.contains("redefinition"), "{}", rendered);
+        assert!(rendered.contains("error: conflicting types for `f`\n --> t.c:3:5\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn pointers_need_the_flag() {
+        let rendered = errors("int main() {\n  int x;\n  int *p = &x;\n  return *p;\n}\n", Extensions::default());
+        assert_eq!(rendered.matches("error: pointers are a language extension\n").count(), 3);
+        assert!(rendered.contains(" --> t.c:3:12\n") && rendered.contains("enable them with `-fpointers`"));
+    }
+
+    #[test]
+    fn constant_array_reads_fold() {
+        let text = koopa("const int a[2][2] = {{1, 2}, {3, 4}};\nint b[a[1][0]];\n\
+            int main() {\n  const int c = a[0][1] + a[1][1];\n  int d[c];\n  return a[1][1];\n}\n", Extensions::default());
+        assert!(text.contains("global @b = alloc [i32, 3], zeroinit\n"));
+        assert!(text.contains("@d = alloc [i32, 6]\n"));
+        assert!(text.contains("  ret 4\n"));
+    }
+
+    #[test]
+    fn constant_array_index_out_of_bounds() {
+        let rendered = errors("const int a[2][2] = {{1, 2}, {3, 4}};\n\
+            int main() {\n  return a[2][0] + a[0][-1] + a[1][2];\n}\n", Extensions::default());
+        assert!(rendered.contains("error: index 2 is out of bounds of constant array `a`\n --> t.c:3:12\n"));
+        assert!(rendered.contains("error: index -1 is out of bounds of constant array `a`\n --> t.c:3:25\n"));
+        assert!(rendered.contains("error: index 2 is out of bounds of constant array `a`\n --> t.c:3:36\n"));
+        assert!(rendered.contains("^ the length is 2\n"));
+    }
+
+    #[test]
+    fn non_constant_index_is_not_folded() {
+        let text = "const int a[2][2] = {{1, 2}, {3, 4}};\nint main() {\n  int i = getint();\n  return a[i][1];\n}\n";
+        assert!(koopa(text, Extensions::default()).contains("getelemptr @a, %"));
+        assert_eq!(run(text, Extensions::default(), &[1]).0, 4);
+        let rendered = errors("const int a[2] = {1, 2};\nint main() {\n  int i = 1;\n  int b[a[i]];\n  return 0;\n}\n", Extensions::default());
+        assert!(rendered.contains("error: array dimension is not a constant expression\n --> t.c:4:9\n"));
+    }
+
+    #[test]
+    fn pointer_types_are_printed_like_c() {
+        let pointers = Extensions { pointers: true, structs: false };
+        let rendered = errors("int main() {\n  int x, a[2][3];\n  int *p = &a[1], *q = 1;\n  x = p;\n\
+            float *g = &x;\n  return x ? p : 2;\n}\n", pointers);
+        for expected in ["expected `int *`, found `int (*)[3]`", "expected `int *`, found `int`",
+                "expected `int`, found `int *`", "expected `float *`, found `int *`"] {
+            assert!(rendered.contains(expected), "{}", expected);
+        }
+        assert!(rendered.contains(" --> t.c:6:18\n")); // the last arm of `?:`.
+        // the null pointer and pointer conditions are fine.
+        koopa("int f(int *p) { if (p && !p) return 1; return p == 0; }\n\
+            int main() { int *p = 0; p = 0; return f(0) + f(p) + (p != 0); }\n", pointers);
+    }
+
+    #[test]
+    fn structs_need_the_flag() {
+        let rendered = errors("struct S { int a; };\nint main() {\n  struct S s;\n  s.a = 1;\n  return s.a;\n}\n", Extensions::default());
+        assert_eq!(rendered.matches("error: structs are a language extension\n").count(), 2);
+        assert!(rendered.contains(" --> t.c:1:8\n") && rendered.contains(" --> t.c:3:10\n"));
+    }
+
+    // the generator panics on these, only the check keeps them out.
+    #[test]
+    fn member_access_on_non_structs() {
+        let both = Extensions { pointers: true, structs: true };
+        let rendered = errors("struct T { int x; };\nstruct S { int a; struct T t; int b[2]; };\n\
+            int main() {\n  int x, *p;\n  struct S s, arr[2];\n  x.f = 1;\n  s.a.y = 2;\n  *p.x = 3;\n  arr.a = 4;\n\
+            s.t.z = 5;\n  return s.t.x + s;\n}\n", both);
+        for (line, found) in [(6, "`int`"), (7, "`int`"), (8, "`int *`"), (9, "`struct S *`")] {
+            assert!(rendered.contains(&format!("error: member access on a non-struct\n --> t.c:{}:", line)), "{}", line);
+            assert!(rendered.contains(&format!("expected a struct, found {}", found)));
+        }
+        assert!(rendered.contains("error: struct `T` has no field named `z`\n  --> t.c:10:1\n"));
+        assert!(rendered.contains("expected `int`, found `struct S`"));
+    }
+
+    #[test]
+    fn struct_declarations() {
+        let rendered = errors("struct S { int a; struct U u; };\nstruct S g = 0;\nint main() { struct S *q; return 0; }\n",
+            Extensions { pointers: true, structs: true });
+        assert!(rendered.contains("error: use of undeclared struct `U`\n"));
+        assert!(rendered.contains("error: struct variable `g` cannot have an initializer\n"));
+        assert!(rendered.contains("error: pointers to structs are not supported\n"));
+    }
+}
@@ -0,0 +1,48 @@
+use crate::ast::BType;
+
+/*
+ * struct 扩展的布局. koopa 没有 struct 类型, 一个 struct 就是 `[i32, size]`, 字段按声明的顺序一个接一个,
+ * `int` 和 `float` 占一个 i32, 数组字段和嵌套的 struct 都铺平. 偏移和大小都以 i32 为单位.
+ * semantic 和 generator 各自求出字段的维度, 再用同一个 `push` 排布局, 两边的偏移一定一致.
+ */
+
+// what a field (or each element of an array field) is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Element {
+    Basic(BType),
+    Struct(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub element: Element,
+    pub dims: Vec<i32>,
+    pub offset: i32,
+}
+
+impl Field {
+    // the `i32`s between two consecutive values of the `k`-th subscript.
+    pub fn stride(&self, k: usize, element_size: i32) -> i32 {
+        self.dims[k + 1..].iter().product::<i32>() * element_size
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StructLayout {
+    pub fields: Vec<Field>,
+    pub size: i32,
+}
+
+impl StructLayout {
+    // append a field, `element_size` is 1 for `int` and `float`, the size of the struct otherwise.
+    pub fn push(&mut self, name: &str, element: Element, dims: Vec<i32>, element_size: i32) {
+        let offset = self.size;
+        self.size += dims.iter().product::<i32>() * element_size;
+        self.fields.push(Field { name: name.to_string(), element, dims, offset });
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
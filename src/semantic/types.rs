@@ -0,0 +1,148 @@
+use std::fmt;
+
+use crate::ast::{BType, FuncType};
+
+/*
+ * 语义检查里用到的类型. 数组的元素只有 int 和 float 两种, 所以数组只需要记录元素类型和维度.
+ * 数组在表达式里总是退化成指向第一个元素的指针, 所以 Pointer 只记录第一维之后的维度:
+ * 参数 `int a[][10]` 和 `int a[5][10]` 里的 `a` 都是 `Pointer(BType::Int, vec![10])`, 和 C 一样写成 `int (*)[10]`.
+ * 打开指针扩展之后 `int *p` 也是 `Pointer(BType::Int, vec![])`, 常量 0 是空指针, 可以存到任何指针里.
+ * `int` 和 `float` 之间可以隐式转换, 其他的类型必须完全一样.
+ * 字符串只能传给 `putf`, 没有 `char` 类型, 所以它自成一类.
+ * struct 是扩展, 只能访问它的字段, 不能赋值和传参. struct 的数组和 Pointer 一样记录第一维之后的维度.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Float,
+    Void,
+    Pointer(BType, Vec<i32>),
+    Str,
+    Struct(String),
+    StructArray(String, Vec<i32>),
+}
+
+impl From<BType> for Type {
+    fn from(btype: BType) -> Type {
+        match btype {
+            BType::Int => Type::Int,
+            BType::Float => Type::Float,
+        }
+    }
+}
+
+impl From<FuncType> for Type {
+    fn from(func_type: FuncType) -> Type {
+        match func_type {
+            FuncType::Int => Type::Int,
+            FuncType::Float => Type::Float,
+            FuncType::Void => Type::Void,
+        }
+    }
+}
+
+impl Type {
+    // `int` or `float`, the operands of arithmetic.
+    pub fn is_number(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+
+    // a value of `self` can be stored to `target`, converted if needed.
+    pub fn converts_to(&self, target: &Type) -> bool {
+        self == target || (self.is_number() && target.is_number())
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Void => write!(f, "void"),
+            Type::Str => write!(f, "string"),
+            Type::Pointer(btype, dims) => write_pointer(f, &Type::from(*btype).to_string(), dims),
+            Type::Struct(name) => write!(f, "struct {}", name),
+            Type::StructArray(name, dims) => write_pointer(f, &format!("struct {}", name), dims),
+        }
+    }
+}
+
+// the way C writes it, `int *` or `int (*)[10]` for a pointer to the rows.
+fn write_pointer(f: &mut fmt::Formatter, element: &str, dims: &[i32]) -> fmt::Result {
+    if dims.is_empty() {
+        return write!(f, "{} *", element);
+    }
+    write!(f, "{} (*)", element)?;
+    for dim in dims {
+        write!(f, "[{}]", dim)?;
+    }
+    Ok(())
+}
+
+// the type of `name` indexed `count` times, `dims` are all the dimensions of `name`.
+// None if it's indexed more times than it has dimensions.
+pub fn index_type(btype: BType, dims: &[i32], count: usize) -> Option<Type> {
+    if count > dims.len() {
+        None
+    } else if count == dims.len() {
+        Some(Type::from(btype))
+    } else {
+        Some(Type::Pointer(btype, dims[count + 1..].to_vec()))
+    }
+}
+
+// the same as `index_type`, for a struct or an array of structs.
+pub fn struct_index_type(name: &str, dims: &[i32], count: usize) -> Option<Type> {
+    if count > dims.len() {
+        None
+    } else if count == dims.len() {
+        Some(Type::Struct(name.to_string()))
+    } else {
+        Some(Type::StructArray(name.to_string(), dims[count + 1..].to_vec()))
+    }
+}
+
+// a variadic function takes any `int` or `float` after `params`, only `putf` is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSignature {
+    pub params: Vec<Type>,
+    pub ret: Type,
+    pub variadic: bool,
+}
+
+impl FunctionSignature {
+    pub fn new(params: Vec<Type>, ret: Type) -> FunctionSignature {
+        FunctionSignature { params, ret, variadic: false }
+    }
+
+    pub fn variadic(params: Vec<Type>, ret: Type) -> FunctionSignature {
+        FunctionSignature { params, ret, variadic: true }
+    }
+}
+
+// where the variadic argument of type `ty` goes, when `next` `i32` slots are used.
+// returns the first slot and the number of slots. like C, a `float` is promoted to `double`,
+// which takes two slots starting at an even one (a register pair of risc-v).
+pub fn variadic_slot(next: usize, ty: &Type) -> (usize, usize) {
+    if *ty == Type::Float {
+        return (next + next % 2, 2);
+    }
+    (next, 1)
+}
+
+// `int(int *, float)`, the way C writes a function type.
+impl fmt::Display for FunctionSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(", self.ret)?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", param)?;
+        }
+        if self.variadic {
+            write!(f, ", ...")?;
+        }
+        write!(f, ")")
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use koopa::ir::dfg::DataFlowGraph;
+use koopa::ir::{BinaryOp, Function, Program, Type, TypeKind, Value, ValueKind};
+
+use crate::diagnostic::{Diagnostic, SourceFile, SourceMap};
+use crate::driver::{self, Output, Target};
+use crate::koopa_ir_gen::intrinsic;
+use crate::semantic::{self, fold, fold_float, Extensions};
+
+/*
+ * 测试用的编译流程, 和 main 一样走 `driver::compile`.
+ * 输入的文件名是 `t.c`, 运行时库就是默认的那一张表.
+ */
+
+fn compile(text: &str, extensions: Extensions, target: Target) -> (SourceMap, Result<Output, Vec<Diagnostic>>) {
+    let input = SourceFile::new("t.c".to_string(), text.to_string());
+    driver::compile(input, &[], &semantic::runtime_library(), extensions, target)
+}
+
+pub fn output(text: &str, extensions: Extensions, target: Target) -> Output {
+    let (source, result) = compile(text, extensions, target);
+    match result {
+        Ok(output) => output,
+        Err(diagnostics) => panic!("{}", diagnostics.iter().map(|diag| diag.render(&source)).collect::<String>()),
+    }
+}
+
+// the text of `-koopa`, `text` has to compile.
+pub fn koopa(text: &str, extensions: Extensions) -> String {
+    output(text, extensions, Target::Koopa).koopa()
+}
+
+// the assembly of `-riscv`, `text` has to compile.
+pub fn riscv(text: &str, extensions: Extensions) -> String {
+    output(text, extensions, Target::Riscv).riscv()
+}
+
+// the rendered diagnostics of `-koopa`, `text` must not compile.
+pub fn errors(text: &str, extensions: Extensions) -> String {
+    let (source, result) = compile(text, extensions, Target::Koopa);
+    match result {
+        Ok(output) => panic!("compiled:\n{}", output.koopa()),
+        Err(diagnostics) => diagnostics.iter().map(|diag| diag.render(&source)).collect(),
+    }
+}
+
+// interpret the in-memory koopa of `text` with `input` for `getint`/`getch`, returns what `main` returns and what is printed.
+// it's the Program `-riscv` starts from, so mutually recursive functions run too.
+pub fn run(text: &str, extensions: Extensions, input: &[i32]) -> (i32, String) {
+    let output = output(text, extensions, Target::Riscv);
+    Type::set_ptr_size(4);
+    let mut machine = Machine { program: &output.program, memory: HashMap::new(), next: 0x1000, globals: HashMap::new(),
+        input: input.iter(), output: String::new() };
+    for &global in output.program.inst_layout() {
+        let data = output.program.borrow_value(global);
+        let ValueKind::GlobalAlloc(alloc) = data.kind() else { panic!("Not a global alloc.") };
+        let addr = machine.alloc(data.ty());
+        machine.write(addr, &flatten_global(&output.program, alloc.init()));
+        machine.globals.insert(global, addr);
+    }
+    let main = *output.program.func_layout().iter().find(|&&func| output.program.func(func).name() == "@main").unwrap();
+    let ret = machine.call(main, &[]);
+    (ret, machine.output)
+}
+
+/*
+ * 一个很小的 koopa 解释器, 测试的时候看生成的代码算出来什么, 不只是长什么样.
+ * 内存是按 byte 编址的 word, 指针就是地址, 和 risc-v 一样 4 个 byte.
+ * 运行时库只有 getint, getch, putint, putch, putarray 和 starttime/stoptime, 外加 float 的 intrinsics.
+ */
+struct Machine<'a> {
+    program: &'a Program,
+    memory: HashMap<i32, i32>,
+    next: i32, // the next free address, nothing is freed.
+    globals: HashMap<Value, i32>,
+    input: std::slice::Iter<'a, i32>,
+    output: String,
+}
+
+// the words of a global initializer.
+fn flatten_global(program: &Program, value: Value) -> Vec<i32> {
+    let data = program.borrow_value(value);
+    match data.kind() {
+        ValueKind::Integer(int) => vec![int.value()],
+        ValueKind::ZeroInit(_) | ValueKind::Undef(_) => vec![0; data.ty().size() / 4],
+        ValueKind::Aggregate(aggr) => aggr.elems().iter().flat_map(|elem| flatten_global(program, *elem)).collect(),
+        _ => panic!("Not an initializer."),
+    }
+}
+
+// the words of a local aggregate.
+fn flatten_local(dfg: &DataFlowGraph, value: Value) -> Vec<i32> {
+    let data = dfg.value(value);
+    match data.kind() {
+        ValueKind::Integer(int) => vec![int.value()],
+        ValueKind::ZeroInit(_) | ValueKind::Undef(_) => vec![0; data.ty().size() / 4],
+        ValueKind::Aggregate(aggr) => aggr.elems().iter().flat_map(|elem| flatten_local(dfg, *elem)).collect(),
+        _ => panic!("Not an initializer."),
+    }
+}
+
+// the type a pointer of type `ty` points to.
+fn pointee(ty: &Type) -> Type {
+    match ty.kind() {
+        TypeKind::Pointer(base) => base.clone(),
+        _ => panic!("Not a pointer."),
+    }
+}
+
+impl Machine<'_> {
+    // `ty` is the type of the alloc, a pointer.
+    fn alloc(&mut self, ty: &Type) -> i32 {
+        let addr = self.next;
+        self.next += std::cmp::max(pointee(ty).size(), 4) as i32;
+        addr
+    }
+
+    fn write(&mut self, addr: i32, words: &[i32]) {
+        for (i, word) in words.iter().enumerate() {
+            self.memory.insert(addr + 4 * i as i32, *word);
+        }
+    }
+
+    fn call(&mut self, func: Function, args: &[i32]) -> i32 {
+        let program = self.program;
+        let data = program.func(func);
+        let Some(mut bb) = data.layout().entry_bb() else {
+            return self.library(data.name(), args);
+        };
+        let dfg = data.dfg();
+        let mut env: HashMap<Value, i32> = HashMap::new();
+        let ty = |value: Value| if value.is_global() { program.borrow_value(value).ty().clone() } else { dfg.value(value).ty().clone() };
+        loop {
+            let mut next = None;
+            for &inst in data.layout().bbs().node(&bb).unwrap().insts().keys() {
+                let operand = |value: Value, env: &HashMap<Value, i32>| {
+                    if value.is_global() {
+                        return self.globals[&value];
+                    }
+                    match dfg.value(value).kind() {
+                        ValueKind::Integer(int) => int.value(),
+                        ValueKind::FuncArgRef(arg) => args[arg.index()],
+                        ValueKind::Undef(_) => 0,
+                        _ => env[&value],
+                    }
+                };
+                let result = match dfg.value(inst).kind() {
+                    ValueKind::Alloc(_) => self.alloc(dfg.value(inst).ty()),
+                    ValueKind::Load(load) => self.memory.get(&operand(load.src(), &env)).copied().unwrap_or(0),
+                    ValueKind::Store(store) => {
+                        let dest = operand(store.dest(), &env);
+                        match dfg.value(store.value()).kind() {
+                            ValueKind::Aggregate(_) | ValueKind::ZeroInit(_) => self.write(dest, &flatten_local(dfg, store.value())),
+                            _ => self.write(dest, &[operand(store.value(), &env)]),
+                        }
+                        continue;
+                    },
+                    ValueKind::GetPtr(ptr) => {
+                        let size = pointee(&ty(ptr.src())).size() as i32;
+                        operand(ptr.src(), &env).wrapping_add(operand(ptr.index(), &env).wrapping_mul(size))
+                    },
+                    ValueKind::GetElemPtr(ptr) => {
+                        let TypeKind::Array(elem, _) = pointee(&ty(ptr.src())).kind().clone() else { panic!("Not an array.") };
+                        operand(ptr.src(), &env).wrapping_add(operand(ptr.index(), &env).wrapping_mul(elem.size() as i32))
+                    },
+                    ValueKind::Binary(binary) => {
+                        let op = match binary.op() {
+                            BinaryOp::NotEq => "ne", BinaryOp::Eq => "eq", BinaryOp::Gt => "gt", BinaryOp::Lt => "lt",
+                            BinaryOp::Ge => "ge", BinaryOp::Le => "le", BinaryOp::Add => "add", BinaryOp::Sub => "sub",
+                            BinaryOp::Mul => "mul", BinaryOp::Div => "div", BinaryOp::Mod => "mod", BinaryOp::And => "and",
+                            BinaryOp::Or => "or", BinaryOp::Xor => "xor", BinaryOp::Shl => "shl", BinaryOp::Sar => "sar",
+                            BinaryOp::Shr => panic!("No logical shift in the generator."),
+                        };
+                        fold(op, operand(binary.lhs(), &env), operand(binary.rhs(), &env))
+                    },
+                    ValueKind::Branch(branch) => {
+                        next = Some(if operand(branch.cond(), &env) != 0 { branch.true_bb() } else { branch.false_bb() });
+                        break;
+                    },
+                    ValueKind::Jump(jump) => {
+                        next = Some(jump.target());
+                        break;
+                    },
+                    ValueKind::Call(call) => {
+                        let values: Vec<i32> = call.args().iter().map(|arg| operand(*arg, &env)).collect();
+                        self.call(call.callee(), &values)
+                    },
+                    ValueKind::Return(ret) => return ret.value().map_or(0, |value| operand(value, &env)),
+                    _ => panic!("Not an instruction."),
+                };
+                env.insert(inst, result);
+            }
+            bb = next.expect("A block without a terminator.");
+        }
+    }
+
+    // a declaration, the runtime library or an intrinsic.
+    fn library(&mut self, name: &str, args: &[i32]) -> i32 {
+        let float = |bits: i32| f32::from_bits(bits as u32);
+        if let Some(intrinsic) = intrinsic(name) {
+            return match intrinsic {
+                "fadd" | "fsub" | "fmul" | "fdiv" |
+                "feq" | "fne" | "flt" | "fgt" | "fle" | "fge" => fold_float(&intrinsic[1..], float(args[0]), float(args[1])).1,
+                "itof" => (args[0] as f32).to_bits() as i32,
+                "ftoi" => float(args[0]) as i32,
+                "ptoi" => args[0],
+                _ => panic!("No intrinsic {} in the interpreter.", intrinsic),
+            };
+        }
+        match name {
+            "@getint" | "@getch" => return *self.input.next().expect("Out of input."),
+            "@putint" => self.output.push_str(&args[0].to_string()),
+            "@putch" => self.output.push(char::from(args[0] as u8)),
+            "@putarray" => {
+                let elems: Vec<String> = (0..args[0]).map(|i| self.memory[&(args[1] + 4 * i)].to_string()).collect();
+                self.output.push_str(&format!("{}: {}\n", args[0], elems.join(" ")));
+            },
+            "@_sysy_starttime" | "@_sysy_stoptime" => {},
+            _ => panic!("No function {} in the interpreter.", name),
+        }
+        0
+    }
+}